@@ -0,0 +1,243 @@
+#[macro_use]
+extern crate diesel;
+
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use errors::prelude::*;
+use log::{debug, error, info};
+use prost::Message;
+
+use tokio_amqp::*;
+
+type ConnectionPool = diesel::r2d2::Pool<ConnectionManager<PgConnection>>;
+type Connection = diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>;
+
+// Only one relay per database publishes events, so the order of
+// events is preserved even with several service replicas. The table and
+// the lock are the same for every service, so services using the outbox
+// must not share a database.
+const RELAY_LOCK_ID: i64 = 0x6f7574626f78;
+
+table! {
+    outbox_events (id) {
+        id -> Int8,
+        exchange -> Text,
+        routing_key -> Text,
+        payload -> Bytea,
+        created_at -> Timestamp,
+    }
+}
+
+sql_function!(fn pg_try_advisory_lock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool);
+sql_function!(fn pg_advisory_unlock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool);
+
+#[derive(Queryable)]
+struct Event {
+    id: i64,
+    exchange: String,
+    routing_key: String,
+    payload: Vec<u8>,
+    #[allow(dead_code)]
+    created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "outbox_events"]
+struct NewEvent<'a> {
+    exchange: &'a str,
+    routing_key: &'a str,
+    payload: Vec<u8>,
+}
+
+// Stores the message in the outbox. Call it inside the transaction
+// which makes the change the message describes.
+pub fn enqueue<M: Message>(connection: &PgConnection, exchange: &str, routing_key: &str, message: &M) -> Result<()> {
+    let mut payload = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut payload)?;
+
+    diesel::insert_into(outbox_events::table)
+        .values(NewEvent { exchange, routing_key, payload })
+        .execute(connection)?;
+
+    Ok(())
+}
+
+pub struct Relay {
+    pool: ConnectionPool,
+    address: String,
+    batch_size: i64,
+    poll_interval: std::time::Duration,
+
+    queues: Vec<String>,
+
+    lock: Option<Connection>,
+    channel: Option<lapin::Channel>,
+    declared_exchanges: HashSet<String>,
+}
+
+impl Relay {
+    pub fn new(pool: ConnectionPool, address: &str, batch_size: i64, poll_interval: std::time::Duration) -> Self {
+        Relay {
+            pool,
+            address: address.into(),
+            batch_size,
+            poll_interval,
+            queues: Vec::new(),
+            lock: None,
+            channel: None,
+            declared_exchanges: HashSet::new(),
+        }
+    }
+
+    // Queues declared before publishing, so messages sent to them through
+    // the default exchange are kept until their consumers start
+    pub fn declare_queues(mut self, queues: &[&str]) -> Self {
+        self.queues.extend(queues.iter().map(|queue| queue.to_string()));
+        self
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        info!("Starting outbox relay");
+        loop {
+            match self.relay_batch().await {
+                Ok(0) => tokio::time::delay_for(self.poll_interval).await,
+                Ok(count) => debug!("Relayed {} outbox events", count),
+                Err(e) => {
+                    error!("Outbox relay error: {}", e.to_string());
+                    self.reset();
+                    tokio::time::delay_for(self.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    async fn relay_batch(&mut self) -> Result<usize> {
+        if !self.acquire_lock()? {
+            return Ok(0);
+        }
+
+        let events: Vec<Event> = match &self.lock {
+            Some(connection) => outbox_events::table
+                .order(outbox_events::id)
+                .limit(self.batch_size)
+                .load(connection)?,
+            None => return Ok(0),
+        };
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let mut published = Vec::with_capacity(events.len());
+        let mut result = Ok(());
+        for event in &events {
+            result = self.publish(event).await;
+            if result.is_err() {
+                break;
+            }
+            published.push(event.id);
+        }
+
+        // Events published before the failure are removed too, the rest
+        // are retried in the next batch.
+        if let Some(connection) = &self.lock {
+            diesel::delete(outbox_events::table.filter(outbox_events::id.eq_any(&published)))
+                .execute(connection)?;
+        }
+
+        result.map(|_| published.len())
+    }
+
+    fn acquire_lock(&mut self) -> Result<bool> {
+        if self.lock.is_some() {
+            return Ok(true);
+        }
+
+        let connection = self.pool.get()?;
+        let locked = diesel::select(pg_try_advisory_lock(RELAY_LOCK_ID)).get_result::<bool>(&connection)?;
+        if locked {
+            info!("Acquired outbox relay lock");
+            self.lock = Some(connection);
+        }
+
+        Ok(locked)
+    }
+
+    async fn publish(&mut self, event: &Event) -> Result<()> {
+        let channel = self.open_channel().await?;
+
+        if !event.exchange.is_empty() && !self.declared_exchanges.contains(&event.exchange) {
+            channel
+                .exchange_declare(
+                    &event.exchange,
+                    lapin::ExchangeKind::Topic,
+                    lapin::options::ExchangeDeclareOptions {
+                        durable: true,
+                        ..lapin::options::ExchangeDeclareOptions::default()
+                    },
+                    lapin::types::FieldTable::default(),
+                )
+                .await?;
+            self.declared_exchanges.insert(event.exchange.clone());
+        }
+
+        let confirmation = channel
+            .basic_publish(
+                &event.exchange,
+                &event.routing_key,
+                lapin::options::BasicPublishOptions::default(),
+                event.payload.clone(),
+                lapin::BasicProperties::default()
+                    .with_delivery_mode(2)
+                    .with_message_id(event.id.to_string().into()),
+            )
+            .await?
+            .await?;
+
+        if confirmation.is_nack() {
+            return Err(errors::Error::Internal(anyhow::anyhow!("Broker rejected outbox event {}", event.id)));
+        }
+
+        Ok(())
+    }
+
+    async fn open_channel(&mut self) -> Result<lapin::Channel> {
+        if let Some(channel) = &self.channel {
+            return Ok(channel.clone());
+        }
+
+        let conn = lapin::Connection::connect(
+            &self.address,
+            lapin::ConnectionProperties::default().with_tokio(),
+        )
+        .await?;
+        let channel = conn.create_channel().await?;
+        channel
+            .confirm_select(lapin::options::ConfirmSelectOptions::default())
+            .await?;
+        for queue in &self.queues {
+            channel
+                .queue_declare(
+                    queue,
+                    lapin::options::QueueDeclareOptions::default(),
+                    lapin::types::FieldTable::default(),
+                )
+                .await?;
+        }
+
+        self.channel = Some(channel.clone());
+        Ok(channel)
+    }
+
+    fn reset(&mut self) {
+        // The connection goes back to the pool, so the session lock has
+        // to be released explicitly.
+        if let Some(connection) = self.lock.take() {
+            let _ = diesel::select(pg_advisory_unlock(RELAY_LOCK_ID)).get_result::<bool>(&connection);
+        }
+        self.channel = None;
+        self.declared_exchanges.clear();
+    }
+}
@@ -1,6 +1,7 @@
 use pb::auth_client::AuthClient;
 use errors::prelude::*;
 use log::info;
+use std::time::{Duration, UNIX_EPOCH};
 
 #[derive(Clone)]
 pub struct Client {
@@ -16,7 +17,7 @@ impl Client {
         Ok(Client { endpoint })
     }
 
-    pub async fn validate(&self, token: String) -> Result<Option<crate::Role>> {
+    pub async fn validate(&self, token: String) -> Result<Option<crate::Identity>> {
         let mut client = AuthClient::connect(self.endpoint.clone()).await?;
         
         let request = tonic::Request::new(pb::ValidateTokenRequest { token });
@@ -27,13 +28,29 @@ impl Client {
             return Ok(None);
         }
 
-        Ok(pb::Role::from_i32(message.role))
+        let identity = match (pb::Role::from_i32(message.role), message.user_id, message.session_id) {
+            (Some(role), Some(user_id), Some(session_id)) => crate::Identity {
+                user_id,
+                session_id,
+                role,
+                expires_at: UNIX_EPOCH + Duration::from_secs(message.expires_at.unwrap_or(0).max(0) as u64),
+                email: message.email,
+                phone: message.phone,
+                client_id: message.client_id,
+                scopes: message.scopes,
+            },
+            _ => return Ok(None),
+        };
+
+        Ok(Some(identity))
     }
 
-    pub async fn validate_role(&self, token: String, role: crate::Role) -> Result<()> {
+    // Tokens issued to oauth clients are never accepted here, use
+    // validate and check the scopes instead.
+    pub async fn validate_role(&self, token: String, role: crate::Role) -> Result<crate::Identity> {
         let res = self.validate(token).await?;
         match res {
-            Some(user_role) if user_role == role => Ok(()),
+            Some(identity) if identity.role == role && identity.client_id.is_none() => Ok(identity),
             _ => Err(errors::Error::Unauthorized("Permission denied".into())),
         }
     }
@@ -1,3 +1,25 @@
+use std::time::SystemTime;
+
 pub mod client;
 
 pub type Role = pb::Role;
+
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub user_id: i32,
+    pub session_id: i32,
+    pub role: Role,
+    pub expires_at: SystemTime,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    // Set for tokens issued to oauth clients
+    pub client_id: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+impl Identity {
+    // First-party sessions are not limited by scopes
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.client_id.is_none() || self.scopes.iter().any(|s| s == scope)
+    }
+}
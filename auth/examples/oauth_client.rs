@@ -0,0 +1,162 @@
+// Drives the oauth authorization code flow with PKCE against a running
+// auth service:
+//
+//   OAUTH_ADMIN_LOGIN=admin@example.com OAUTH_ADMIN_PASSWORD=... \
+//   OAUTH_USER_LOGIN=user@example.com OAUTH_USER_PASSWORD=... \
+//   cargo run -p auth --example oauth_client
+//
+// The service address is taken from AUTH_ADDRESS, localhost:7781 by default.
+
+use pb::auth_client::AuthClient;
+use pb::oauth_client::OauthClient;
+
+use rand::distributions::Alphanumeric;
+use rand::prelude::*;
+use sha2::{Digest, Sha256};
+
+type Error = Box<dyn std::error::Error>;
+
+const REDIRECT_URI: &str = "http://localhost:8080/callback";
+const SCOPE: &str = "profile shop";
+
+fn env(name: &str) -> Result<String, Error> {
+    std::env::var(name).map_err(|_| format!("{} is not set", name).into())
+}
+
+fn with_token<T>(message: T, token: &str) -> Result<tonic::Request<T>, Error> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse()?);
+    Ok(request)
+}
+
+async fn login(auth: &mut AuthClient<tonic::transport::Channel>, login: String, password: String) -> Result<String, Error> {
+    let response = auth.login(pb::LoginRequest { login, password }).await?;
+    Ok(response.into_inner().tokens.access)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let address = std::env::var("AUTH_ADDRESS").unwrap_or_else(|_| "localhost:7781".into());
+    let endpoint = format!("http://{}", address);
+    let mut auth = AuthClient::connect(endpoint.clone()).await?;
+    let mut oauth = OauthClient::connect(endpoint).await?;
+
+    let admin_token = login(&mut auth, env("OAUTH_ADMIN_LOGIN")?, env("OAUTH_ADMIN_PASSWORD")?).await?;
+    let client = oauth
+        .register_client(with_token(pb::RegisterClientRequest {
+            name: "Example client".into(),
+            redirect_uris: vec![REDIRECT_URI.into()],
+            scopes: SCOPE.split(' ').map(String::from).collect(),
+            confidential: Some(true),
+        }, &admin_token)?)
+        .await?
+        .into_inner();
+    let client_id = client.client.client_id;
+    let client_secret = client.client_secret;
+    println!("Registered client {}", client_id);
+
+    let verifier: String = rand::thread_rng().sample_iter(&Alphanumeric).take(64).collect();
+    let challenge = base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+    let state: String = rand::thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+
+    // The frontend makes this call once the user approves the consent screen
+    let user_token = login(&mut auth, env("OAUTH_USER_LOGIN")?, env("OAUTH_USER_PASSWORD")?).await?;
+    let authorization = oauth
+        .authorize(with_token(pb::AuthorizeRequest {
+            response_type: "code".into(),
+            client_id: client_id.clone(),
+            redirect_uri: REDIRECT_URI.into(),
+            scope: Some(SCOPE.into()),
+            state: Some(state.clone()),
+            code_challenge: challenge,
+            code_challenge_method: Some("S256".into()),
+        }, &user_token)?)
+        .await?
+        .into_inner();
+    println!("Redirecting to {}", authorization.redirect_uri);
+    if authorization.state.as_ref() != Some(&state) {
+        return Err("State mismatch".into());
+    }
+
+    let tokens = oauth
+        .token(pb::TokenRequest {
+            grant_type: "authorization_code".into(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            code: Some(authorization.code.clone()),
+            redirect_uri: Some(REDIRECT_URI.into()),
+            code_verifier: Some(verifier.clone()),
+            refresh_token: None,
+        })
+        .await?
+        .into_inner();
+    println!("Got tokens with scope \"{}\", expiring in {}s", tokens.scope, tokens.expires_in);
+
+    let replay = oauth
+        .token(pb::TokenRequest {
+            grant_type: "authorization_code".into(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            code: Some(authorization.code),
+            redirect_uri: Some(REDIRECT_URI.into()),
+            code_verifier: Some(verifier),
+            refresh_token: None,
+        })
+        .await;
+    if replay.is_ok() {
+        return Err("Authorization code was accepted twice".into());
+    }
+
+    let identity = auth
+        .validate_token(pb::ValidateTokenRequest { token: tokens.access_token.clone() })
+        .await?
+        .into_inner();
+    println!("Token belongs to user {:?} with scopes {:?}", identity.user_id, identity.scopes);
+
+    let refreshed = oauth
+        .token(pb::TokenRequest {
+            grant_type: "refresh_token".into(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+            code: None,
+            redirect_uri: None,
+            code_verifier: None,
+            refresh_token: Some(tokens.refresh_token),
+        })
+        .await?
+        .into_inner();
+    println!("Refreshed tokens");
+
+    let introspection = oauth
+        .introspect(pb::IntrospectRequest {
+            token: refreshed.access_token.clone(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+        })
+        .await?
+        .into_inner();
+    println!("Introspection before revocation: active = {}", introspection.active);
+
+    oauth
+        .revoke(pb::RevokeRequest {
+            token: refreshed.access_token.clone(),
+            client_id: client_id.clone(),
+            client_secret: client_secret.clone(),
+        })
+        .await?;
+
+    let introspection = oauth
+        .introspect(pb::IntrospectRequest {
+            token: refreshed.access_token,
+            client_id,
+            client_secret,
+        })
+        .await?
+        .into_inner();
+    println!("Introspection after revocation: active = {}", introspection.active);
+    if introspection.active {
+        return Err("Token is still active after revocation".into());
+    }
+
+    Ok(())
+}
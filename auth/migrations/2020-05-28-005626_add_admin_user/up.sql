@@ -1,3 +1,3 @@
-INSERT
-    INTO users
-    VALUES (0, NULL, 'admin@sskvor.dev', '$2y$10$lDhepwp/1I.FvfRdD.z69eOQ/ydCzs5RkZVP918JZBBWi0oaWe3GO', 'admin')
+-- The hard-coded admin is no longer created, use `auth create-admin`.
+-- The migration is kept so its version stays known, databases where it
+-- was applied are fixed by 2020-07-24-101317_disable_default_admin.
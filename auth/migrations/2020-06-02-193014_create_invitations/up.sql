@@ -0,0 +1,9 @@
+CREATE TABLE invitations (
+    id serial PRIMARY KEY,
+    token text NOT NULL UNIQUE,
+    phone text NULL,
+    email text NULL,
+    permissions access_level NOT NULL,
+    invited_by integer NOT NULL REFERENCES users(id),
+    expires_at timestamp NOT NULL
+);
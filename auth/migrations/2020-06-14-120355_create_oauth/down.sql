@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sessions DROP COLUMN scopes;
+ALTER TABLE sessions DROP COLUMN client_id;
+DROP TABLE oauth_codes;
+DROP TABLE oauth_consents;
+DROP TABLE oauth_clients;
@@ -0,0 +1,30 @@
+CREATE TABLE oauth_clients (
+    id serial PRIMARY KEY,
+    client_id text NOT NULL UNIQUE,
+    secret_hash text NULL,
+    name text NOT NULL,
+    redirect_uris text[] NOT NULL,
+    scopes text[] NOT NULL,
+    created_by integer NOT NULL REFERENCES users(id)
+);
+
+CREATE TABLE oauth_consents (
+    user_id integer NOT NULL REFERENCES users(id),
+    client_id text NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    scopes text[] NOT NULL,
+    granted_at timestamp NOT NULL,
+    PRIMARY KEY (user_id, client_id)
+);
+
+CREATE TABLE oauth_codes (
+    code text PRIMARY KEY,
+    client_id text NOT NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE,
+    user_id integer NOT NULL REFERENCES users(id),
+    redirect_uri text NOT NULL,
+    scopes text[] NOT NULL,
+    code_challenge text NOT NULL,
+    expires_at timestamp NOT NULL
+);
+
+ALTER TABLE sessions ADD COLUMN client_id text NULL REFERENCES oauth_clients(client_id) ON DELETE CASCADE;
+ALTER TABLE sessions ADD COLUMN scopes text[] NULL;
@@ -0,0 +1 @@
+-- The disabled admin is not restored, its password is published
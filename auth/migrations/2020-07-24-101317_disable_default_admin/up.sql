@@ -0,0 +1,14 @@
+-- The admin created by 2020-05-28-005626_add_admin_user has a published
+-- password. It is kept for foreign keys, but is disabled and demoted, so
+-- `auth create-admin` can bootstrap a real admin.
+DELETE FROM sessions WHERE user_id = 0 AND EXISTS (
+    SELECT 1 FROM users WHERE id = 0 AND email = 'admin@sskvor.dev'
+);
+
+DELETE FROM oauth_codes WHERE user_id = 0 AND EXISTS (
+    SELECT 1 FROM users WHERE id = 0 AND email = 'admin@sskvor.dev'
+);
+
+UPDATE users
+    SET disabled = true, permissions = 'user'
+    WHERE id = 0 AND email = 'admin@sskvor.dev';
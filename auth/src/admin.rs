@@ -0,0 +1,199 @@
+use structopt::StructOpt;
+
+use errors::Error;
+use errors::prelude::*;
+use crate::logins;
+use crate::models;
+use crate::repo;
+use crate::service::{self, TokensRepo, UsersRepo};
+
+#[derive(StructOpt)]
+#[structopt(name = "auth", about = "Online store authentication service")]
+pub struct Opts {
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Creates the first admin, the password is read from AUTH_ADMIN_PASSWORD or prompted
+    CreateAdmin {
+        #[structopt(long, env = "AUTH_ADMIN_LOGIN")]
+        login: String,
+    },
+
+    /// Sets a new password, the password is read from AUTH_ADMIN_PASSWORD or prompted
+    ResetPassword {
+        #[structopt(long)]
+        login: String,
+    },
+
+    /// Changes the role of a user
+    SetRole {
+        #[structopt(long)]
+        login: String,
+
+        #[structopt(long, possible_values = &["user", "admin"])]
+        role: String,
+    },
+
+    /// Prints registered users
+    ListUsers {
+        #[structopt(long)]
+        offset: Option<i64>,
+
+        #[structopt(long)]
+        limit: Option<i64>,
+    },
+
+    /// Logs a user out of all sessions
+    RevokeSessions {
+        #[structopt(long)]
+        login: String,
+    },
+
+    /// Rewrites logins stored before normalization, colliding logins are printed and left as is
+    NormalizeLogins,
+}
+
+pub struct Admin {
+    repo: repo::PgRepo,
+    logins: logins::Normalizer,
+}
+
+impl Admin {
+    pub fn new(repo: repo::PgRepo, logins: logins::Normalizer) -> Self {
+        Admin { repo, logins }
+    }
+
+    pub fn run(&self, command: Command) -> Result<()> {
+        match command {
+            Command::CreateAdmin { login } => self.create_admin(&login),
+            Command::ResetPassword { login } => self.reset_password(&login),
+            Command::SetRole { login, role } => self.set_role(&login, &role),
+            Command::ListUsers { offset, limit } => self.list_users(offset, limit),
+            Command::RevokeSessions { login } => self.revoke_sessions(&login),
+            Command::NormalizeLogins => self.normalize_logins(),
+        }
+    }
+
+    fn create_admin(&self, login: &str) -> Result<()> {
+        let login = self.logins.login(login)?;
+        let password = service::Service::hash_password(read_password()?)?;
+        let mut user = models::NewUser {
+            phone: None,
+            email: None,
+            password,
+            permissions: models::AccessLevel::Admin,
+        };
+        match login {
+            models::Login::Email(email) => user.email = Some(email),
+            models::Login::Phone(phone) => user.phone = Some(phone),
+        };
+
+        let user = match self.repo.add_first_admin(user) {
+            Ok(Some(user)) => user,
+            Ok(None) => return Err(Error::BadRequest("Admin already exists".into())),
+            Err(Error::DbNonUnique(_)) => return Err(Error::BadRequest("Login is already used".into())),
+            Err(e) => return Err(e),
+        };
+
+        println!("Created admin with id {}", user.id);
+        Ok(())
+    }
+
+    fn reset_password(&self, login: &str) -> Result<()> {
+        let user = self.find_user(login)?;
+        let password = service::Service::hash_password(read_password()?)?;
+        let revoked = self.repo.reset_password(user.id, password)?;
+
+        println!("Password of user {} is reset, revoked {} sessions", user.id, revoked);
+        Ok(())
+    }
+
+    fn set_role(&self, login: &str, role: &str) -> Result<()> {
+        let user = self.find_user(login)?;
+        let role = match role {
+            "admin" => models::AccessLevel::Admin,
+            "user" => models::AccessLevel::User,
+            _ => return Err(Error::BadRequest(format!("Unknown role {}", role))),
+        };
+        self.repo.set_user_role(user.id, role)?;
+
+        println!("User {} is now {:?}", user.id, role);
+        Ok(())
+    }
+
+    fn list_users(&self, offset: Option<i64>, limit: Option<i64>) -> Result<()> {
+        let res = self.repo.list_users(models::ListUsersRequest { offset, limit })?;
+
+        println!("{:>8} {:>6} {:>9} {:<32} {:<16}", "id", "role", "disabled", "email", "phone");
+        for user in res.users {
+            println!(
+                "{:>8} {:>6} {:>9} {:<32} {:<16}",
+                user.id,
+                format!("{:?}", user.permissions),
+                user.disabled,
+                user.email.unwrap_or_default(),
+                user.phone.unwrap_or_default(),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn revoke_sessions(&self, login: &str) -> Result<()> {
+        let user = self.find_user(login)?;
+        let revoked = self.repo.revoke_sessions(user.id)?;
+
+        println!("Revoked {} sessions of user {}", revoked, user.id);
+        Ok(())
+    }
+
+    fn normalize_logins(&self) -> Result<()> {
+        let res = self.repo.normalize_logins(&self.logins)?;
+
+        for collision in &res.collisions {
+            println!(
+                "Login {} of user {} normalizes to {} owned by user {}",
+                collision.login,
+                collision.user_id,
+                collision.normalized,
+                collision.owner_id,
+            );
+        }
+        println!("Normalized {} logins, found {} collisions", res.updated, res.collisions.len());
+        Ok(())
+    }
+
+    fn find_user(&self, login: &str) -> Result<models::User> {
+        let login = self.logins.login(login)?;
+        match self.repo.get_user_by_login(&login) {
+            Err(Error::DbNotFound(_)) => Err(Error::NotFound("User not found".into())),
+            res => res,
+        }
+    }
+}
+
+fn read_password() -> Result<String> {
+    if let Ok(password) = std::env::var("AUTH_ADMIN_PASSWORD") {
+        if password.is_empty() {
+            return Err(Error::BadRequest("Password is required".into()));
+        }
+        return Ok(password);
+    }
+
+    let password = prompt("Password: ")?;
+    if password.is_empty() {
+        return Err(Error::BadRequest("Password is required".into()));
+    }
+    if prompt("Repeat password: ")? != password {
+        return Err(Error::BadRequest("Passwords do not match".into()));
+    }
+
+    Ok(password)
+}
+
+fn prompt(text: &str) -> Result<String> {
+    rpassword::read_password_from_tty(Some(text)).map_err(|e| Error::Internal(e.into()))
+}
@@ -6,11 +6,24 @@ pub struct Settings {
     pub bind_address: std::net::SocketAddr,
     pub amqp_address: String,
     pub session_timeout: u32,
+    pub invite_timeout: u32,
+    // Lifetime of oauth authorization codes in seconds
+    pub oauth_code_timeout: u32,
+    pub outbox_batch_size: i64,
+    // Milliseconds
+    pub outbox_poll_interval: u64,
+    // ISO 3166 code used for phone numbers without a country code
+    pub default_region: String,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, config::ConfigError> {
         let mut s = config::Config::new();
+        s.set_default("default_region", "RU")?;
+        s.set_default("invite_timeout", 7i64 * 24 * 60 * 60)?;
+        s.set_default("oauth_code_timeout", 60i64)?;
+        s.set_default("outbox_batch_size", 100i64)?;
+        s.set_default("outbox_poll_interval", 500i64)?;
         s.merge(config::Environment::with_prefix("auth"))?;
         s.try_into()
     }
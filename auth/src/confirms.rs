@@ -1,67 +1,35 @@
-use crate::config;
-use crate::models;
-use crate::service;
-use async_trait::async_trait;
-use errors::prelude::*;
-use prost::Message;
-
-use tokio_amqp::*;
-
-#[derive(Clone)]
-pub struct ConfrimsSender {
-    channel: lapin::Channel,
-}
+use diesel::PgConnection;
 
-#[async_trait]
-impl service::ConfirmationsSender for ConfrimsSender {
-    async fn send(&self, login: models::Login, token: String) -> Result<()> {
-        println!("Token: {}", token);
-
-        let (login, queue) = match login {
-            models::Login::Email(email) => (email, "confirmations_email"),
-            models::Login::Phone(phone) => (phone, "confirmations_phone"),
-        };
-
-        // FIXME(sskvor)
-        let url = format!("https://hw.sskvor.dev/v1/confirm?token={}", token);
-        let c = pb::Confirmation { login, url };
+use errors::prelude::*;
+use crate::models;
 
-        let mut buf = Vec::with_capacity(c.encoded_len());
-        c.encode(&mut buf).unwrap();
+const EMAIL_QUEUE: &str = "confirmations_email";
+const PHONE_QUEUE: &str = "confirmations_phone";
 
-        self.channel
-            .basic_publish(
-                "",
-                queue,
-                lapin::options::BasicPublishOptions::default(),
-                buf,
-                lapin::BasicProperties::default(),
-            )
-            .await?;
+// Declared by the outbox relay, so confirmations published before the
+// senders start are not dropped
+pub const QUEUES: &[&str] = &[EMAIL_QUEUE, PHONE_QUEUE];
 
-        Ok(())
-    }
+// Must be called in the transaction storing the confirmation, the outbox
+// relay publishes it after commit.
+pub fn send(connection: &PgConnection, login: &models::Login, token: &str) -> Result<()> {
+    // FIXME(sskvor)
+    let url = format!("https://hw.sskvor.dev/v1/confirm?token={}", token);
+    enqueue(connection, login, url, pb::ConfirmationKind::ConfirmLogin)
 }
 
-impl ConfrimsSender {
-    pub async fn new(cfg: &config::Settings) -> Result<ConfrimsSender> {
-        let conn = lapin::Connection::connect(
-            &cfg.amqp_address,
-            lapin::ConnectionProperties::default().with_tokio(),
-        )
-        .await?;
-        let channel = conn.create_channel().await?;
+// Must be called in the transaction storing the invitation
+pub fn send_invite(connection: &PgConnection, login: &models::Login, token: &str) -> Result<()> {
+    let url = format!("https://hw.sskvor.dev/invitations/accept?token={}", token);
+    enqueue(connection, login, url, pb::ConfirmationKind::AcceptInvite)
+}
 
-        for queue in &["confirmations_email", "confirmations_phone"] {
-            channel
-                .queue_declare(
-                    queue,
-                    lapin::options::QueueDeclareOptions::default(),
-                    lapin::types::FieldTable::default(),
-                )
-                .await?;
-        }
+fn enqueue(connection: &PgConnection, login: &models::Login, url: String, kind: pb::ConfirmationKind) -> Result<()> {
+    let (login, queue) = match login {
+        models::Login::Email(email) => (email.clone(), EMAIL_QUEUE),
+        models::Login::Phone(phone) => (phone.clone(), PHONE_QUEUE),
+    };
 
-        Ok(ConfrimsSender { channel })
-    }
+    let c = pb::Confirmation { login, url, kind: Some(kind.into()), text: None };
+    amqp_outbox::enqueue(connection, "", queue, &c)
 }
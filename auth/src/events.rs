@@ -0,0 +1,30 @@
+use std::time::SystemTime;
+
+use diesel::PgConnection;
+use errors::prelude::*;
+use crate::proto_convert;
+
+pub use pb::auth_event::Event;
+
+pub const EXCHANGE: &str = "auth.events";
+
+fn routing_key(event: &Event) -> &'static str {
+    match event {
+        Event::UserRegistered(_) => "user.registered",
+        Event::UserConfirmed(_) => "user.confirmed",
+        Event::RoleChanged(_) => "user.role_changed",
+        Event::UserDisabled(_) => "user.disabled",
+        Event::SessionRevoked(_) => "session.revoked",
+    }
+}
+
+// Must be called in the transaction making the change, the outbox relay
+// publishes the event after commit.
+pub fn emit(connection: &PgConnection, event: Event) -> Result<()> {
+    let key = routing_key(&event);
+    let message = pb::AuthEvent {
+        created_at: proto_convert::unix_seconds(SystemTime::now()),
+        event: Some(event),
+    };
+    amqp_outbox::enqueue(connection, EXCHANGE, key, &message)
+}
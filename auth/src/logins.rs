@@ -0,0 +1,92 @@
+use errors::Error;
+use errors::prelude::*;
+use crate::config;
+use crate::models;
+
+use phonenumber::country;
+
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+#[derive(Clone)]
+pub struct Normalizer {
+    default_region: country::Id,
+}
+
+impl Normalizer {
+    pub fn new(cfg: &config::Settings) -> Result<Normalizer> {
+        let default_region = cfg.default_region
+            .to_uppercase()
+            .parse::<country::Id>()
+            .map_err(|_| Error::Internal(anyhow::anyhow!("Unknown phone region {}", cfg.default_region)))?;
+
+        Ok(Normalizer { default_region })
+    }
+
+    // Email and phone are told apart by the '@' sign.
+    pub fn login(&self, raw: &str) -> Result<models::Login> {
+        if raw.contains('@') {
+            Ok(models::Login::Email(self.email(raw)?))
+        } else {
+            Ok(models::Login::Phone(self.phone(raw)?))
+        }
+    }
+
+    pub fn email(&self, raw: &str) -> Result<String> {
+        let email = raw.trim().to_lowercase();
+        if !is_valid_email(&email) {
+            return Err(Error::BadRequest(format!("Invalid email {}", raw)));
+        }
+        Ok(email)
+    }
+
+    // Formats phone numbers as E.164, numbers without a country code
+    // are parsed in the default region.
+    pub fn phone(&self, raw: &str) -> Result<String> {
+        let number = phonenumber::parse(Some(self.default_region), raw.trim())
+            .map_err(|_| Error::BadRequest(format!("Invalid phone number {}", raw)))?;
+        if !phonenumber::is_valid(&number) {
+            return Err(Error::BadRequest(format!("Invalid phone number {}", raw)));
+        }
+        Ok(number.format().mode(phonenumber::Mode::E164).to_string())
+    }
+}
+
+fn is_valid_email(email: &str) -> bool {
+    if email.len() > MAX_EMAIL_LENGTH {
+        return false;
+    }
+
+    let (local, domain) = match email.rfind('@') {
+        Some(pos) => (&email[..pos], &email[pos + 1..]),
+        None => return false,
+    };
+
+    is_valid_local_part(local) && is_valid_domain(domain)
+}
+
+fn is_valid_local_part(local: &str) -> bool {
+    const SPECIAL: &str = "!#$%&'*+/=?^_`{|}~-.";
+
+    if local.is_empty() || local.len() > MAX_LOCAL_PART_LENGTH {
+        return false;
+    }
+    if local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return false;
+    }
+    local.chars().all(|c| c.is_ascii_alphanumeric() || SPECIAL.contains(c))
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return false;
+    }
+    labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
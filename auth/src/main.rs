@@ -6,11 +6,16 @@ extern crate diesel_migrations;
 extern crate diesel_derive_enum;
 
 use log::info;
+use structopt::StructOpt;
 use tonic::transport::Server;
 use pb::auth_server::AuthServer;
+use pb::oauth_server::OauthServer;
 
+mod admin;
 mod config;
 mod confirms;
+mod events;
+mod logins;
 mod models;
 mod proto_convert;
 mod repo;
@@ -23,16 +28,25 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
     env_logger::init();
 
+    let opts = admin::Opts::from_args();
     let cfg = config::Settings::new().expect("Failed to parse config");
+    let normalizer = logins::Normalizer::new(&cfg).expect("Failed to initialize logins normalizer");
     let repo = repo::PgRepo::new(&cfg).expect("Failed to initialize repo");
-    let confirms_sender = confirms::ConfrimsSender::new(&cfg).await.expect("Failed to initialize confirmations sender");
-    let auth_service = service::Service::new(&cfg, repo, confirms_sender);
+
+    if let Some(command) = opts.command {
+        admin::Admin::new(repo, normalizer).run(command)?;
+        return Ok(());
+    }
+    let mut relay = repo.outbox_relay(&cfg);
+    tokio::spawn(async move { relay.run().await });
+    let auth_service = service::Service::new(&cfg, repo, normalizer);
     let auth_client = auth_client::client::Client::new(&cfg.bind_address.to_string()).expect("Failed to initalize auth client");
     let server = server::Server::new(auth_service, auth_client);
 
     info!("Starting grpc server at {}", cfg.bind_address);
     Server::builder()
-        .add_service(AuthServer::new(server))
+        .add_service(AuthServer::new(server.clone()))
+        .add_service(OauthServer::new(server))
         .serve(cfg.bind_address)
         .await?;
 
@@ -15,6 +15,7 @@ impl std::default::Default for AccessLevel {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Login {
     Email(String),
     Phone(String),
@@ -28,6 +29,7 @@ pub struct User {
     pub email: Option<String>,
     pub password: String,
     pub permissions: AccessLevel,
+    pub disabled: bool,
 }
 
 #[derive(Insertable, Serialize, Deserialize, Queryable, Clone)]
@@ -49,6 +51,8 @@ pub struct Session {
     pub access_token: String,
     pub expires_at: SystemTime,
     pub user_id: i32,
+    pub client_id: Option<String>,
+    pub scopes: Option<Vec<String>>,
 }
 
 #[derive(Insertable)]
@@ -58,6 +62,59 @@ pub struct NewSession<'a> {
     pub access_token: &'a str,
     pub expires_at: SystemTime,
     pub user_id: i32,
+    pub client_id: Option<String>,
+    pub scopes: Option<Vec<String>>,
+}
+
+// Sessions issued to oauth clients are limited to the granted scopes
+#[derive(Clone)]
+pub struct Grant {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Queryable, Identifiable)]
+#[table_name = "oauth_clients"]
+pub struct OauthClient {
+    pub id: i32,
+    pub client_id: String,
+    pub secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub created_by: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_clients"]
+pub struct NewOauthClient {
+    pub client_id: String,
+    pub secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub created_by: i32,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "oauth_codes"]
+pub struct OauthCode {
+    pub code: String,
+    pub client_id: String,
+    pub user_id: i32,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub code_challenge: String,
+    pub expires_at: SystemTime,
+}
+
+#[derive(Queryable, Insertable, AsChangeset)]
+#[table_name = "oauth_consents"]
+pub struct OauthConsent {
+    pub user_id: i32,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub granted_at: SystemTime,
 }
 
 #[derive(Queryable, Identifiable)]
@@ -79,6 +136,29 @@ pub struct NewConfirmation {
     pub user_id: i32,
 }
 
+#[derive(Queryable, Identifiable)]
+#[table_name = "invitations"]
+pub struct Invitation {
+    pub id: i32,
+    pub token: String,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub permissions: AccessLevel,
+    pub invited_by: i32,
+    pub expires_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "invitations"]
+pub struct NewInvitation {
+    pub token: String,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub permissions: AccessLevel,
+    pub invited_by: i32,
+    pub expires_at: SystemTime,
+}
+
 #[derive(Deserialize)]
 pub struct LoginRequest {
     pub login: String,
@@ -102,10 +182,17 @@ pub struct UpdateUserRequest {
     pub role: AccessLevel,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 pub struct ValidateTokenResponse {
     pub valid: bool,
     pub role: AccessLevel,
+    pub user_id: Option<i32>,
+    pub session_id: Option<i32>,
+    pub expires_at: Option<SystemTime>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub client_id: Option<String>,
+    pub scopes: Vec<String>,
 }
 
 pub struct ListUsersRequest {
@@ -116,3 +203,91 @@ pub struct ListUsersRequest {
 pub struct ListUsersResponse {
     pub users: Vec<User>,
 }
+
+// Stored login which normalizes to a login of another user
+pub struct LoginCollision {
+    pub user_id: i32,
+    pub login: String,
+    pub normalized: String,
+    pub owner_id: i32,
+}
+
+pub struct NormalizeLoginsResponse {
+    pub updated: usize,
+    pub collisions: Vec<LoginCollision>,
+}
+
+pub struct InviteUserRequest {
+    pub login: String,
+    pub role: AccessLevel,
+}
+
+pub struct AcceptInviteRequest {
+    pub token: String,
+    pub password: String,
+}
+
+pub struct ListInvitationsRequest {
+    pub offset: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+pub struct ListInvitationsResponse {
+    pub invitations: Vec<Invitation>,
+}
+
+pub struct RegisterClientRequest {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub confidential: bool,
+}
+
+pub struct RegisterClientResponse {
+    pub client: OauthClient,
+    pub client_secret: Option<String>,
+}
+
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: String,
+    pub code_challenge_method: Option<String>,
+}
+
+pub struct AuthorizeResponse {
+    pub redirect_uri: String,
+    pub code: String,
+    pub state: Option<String>,
+}
+
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+}
+
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client: ClientCredentials,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+}
+
+pub struct TokenResponse {
+    pub tokens: LoginResponse,
+    pub expires_in: i64,
+    pub scopes: Vec<String>,
+}
+
+pub struct IntrospectResponse {
+    pub active: bool,
+    pub scopes: Vec<String>,
+    pub client_id: Option<String>,
+    pub user_id: Option<i32>,
+    pub expires_at: Option<SystemTime>,
+}
@@ -1,3 +1,5 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use crate::models;
 
 // FIXME(BigRedEye) CODEGEN THIS SHIT
@@ -11,6 +13,20 @@ pub fn parse_role(role: i32) -> models::AccessLevel {
     }
 }
 
+pub fn format_role(role: models::AccessLevel) -> i32 {
+    match role {
+        models::AccessLevel::User => pb::Role::User.into(),
+        models::AccessLevel::Admin => pb::Role::Admin.into(),
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
 impl From<pb::RegisterRequest> for models::NewUser {
     fn from(req: pb::RegisterRequest) -> models::NewUser {
         return models::NewUser {
@@ -69,7 +85,14 @@ impl From<models::ValidateTokenResponse> for pb::ValidateTokenResponse {
             role: match rsp.role {
                 models::AccessLevel::User => 0,
                 models::AccessLevel::Admin => 1,
-            }
+            },
+            user_id: rsp.user_id,
+            session_id: rsp.session_id,
+            expires_at: rsp.expires_at.map(unix_seconds),
+            email: rsp.email,
+            phone: rsp.phone,
+            client_id: rsp.client_id,
+            scopes: rsp.scopes,
         }
     }
 }
@@ -92,6 +115,7 @@ impl From<models::User> for pb::UserInfo {
             },
             email: user.email,
             phone: user.phone,
+            disabled: Some(user.disabled),
         }
     }
 }
@@ -104,3 +128,158 @@ impl From<pb::ListUsersRequest> for models::ListUsersRequest {
         }
     }
 }
+
+impl From<pb::InviteUserRequest> for models::InviteUserRequest {
+    fn from(req: pb::InviteUserRequest) -> models::InviteUserRequest {
+        return models::InviteUserRequest {
+            login: req.login,
+            role: parse_role(req.role),
+        }
+    }
+}
+
+impl From<pb::AcceptInviteRequest> for models::AcceptInviteRequest {
+    fn from(req: pb::AcceptInviteRequest) -> models::AcceptInviteRequest {
+        return models::AcceptInviteRequest {
+            token: req.token,
+            password: req.password,
+        }
+    }
+}
+
+impl From<models::LoginResponse> for pb::AcceptInviteResponse {
+    fn from(rsp: models::LoginResponse) -> pb::AcceptInviteResponse {
+        return pb::AcceptInviteResponse {
+            tokens: pb::Tokens{
+                access: rsp.access_token,
+                refresh: rsp.refresh_token,
+            }
+        }
+    }
+}
+
+impl From<models::Invitation> for pb::Invitation {
+    fn from(invitation: models::Invitation) -> pb::Invitation {
+        return pb::Invitation {
+            id: invitation.id,
+            role: format_role(invitation.permissions),
+            email: invitation.email,
+            phone: invitation.phone,
+            invited_by: invitation.invited_by,
+            expires_at: unix_seconds(invitation.expires_at),
+        }
+    }
+}
+
+impl From<pb::ListInvitationsRequest> for models::ListInvitationsRequest {
+    fn from(req: pb::ListInvitationsRequest) -> models::ListInvitationsRequest {
+        return models::ListInvitationsRequest {
+            offset: req.offset,
+            limit: req.limit,
+        }
+    }
+}
+
+impl From<models::ListInvitationsResponse> for pb::ListInvitationsResponse {
+    fn from(rsp: models::ListInvitationsResponse) -> pb::ListInvitationsResponse {
+        return pb::ListInvitationsResponse {
+            invitations: rsp.invitations.into_iter().map(|invitation| invitation.into()).collect()
+        }
+    }
+}
+
+impl From<pb::RegisterClientRequest> for models::RegisterClientRequest {
+    fn from(req: pb::RegisterClientRequest) -> models::RegisterClientRequest {
+        return models::RegisterClientRequest {
+            name: req.name,
+            redirect_uris: req.redirect_uris,
+            scopes: req.scopes,
+            confidential: req.confidential.unwrap_or(false),
+        }
+    }
+}
+
+impl From<models::OauthClient> for pb::OauthClient {
+    fn from(client: models::OauthClient) -> pb::OauthClient {
+        return pb::OauthClient {
+            client_id: client.client_id,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            scopes: client.scopes,
+            confidential: client.secret_hash.is_some(),
+        }
+    }
+}
+
+impl From<models::RegisterClientResponse> for pb::RegisterClientResponse {
+    fn from(rsp: models::RegisterClientResponse) -> pb::RegisterClientResponse {
+        return pb::RegisterClientResponse {
+            client: rsp.client.into(),
+            client_secret: rsp.client_secret,
+        }
+    }
+}
+
+impl From<pb::AuthorizeRequest> for models::AuthorizeRequest {
+    fn from(req: pb::AuthorizeRequest) -> models::AuthorizeRequest {
+        return models::AuthorizeRequest {
+            response_type: req.response_type,
+            client_id: req.client_id,
+            redirect_uri: req.redirect_uri,
+            scope: req.scope,
+            state: req.state,
+            code_challenge: req.code_challenge,
+            code_challenge_method: req.code_challenge_method,
+        }
+    }
+}
+
+impl From<models::AuthorizeResponse> for pb::AuthorizeResponse {
+    fn from(rsp: models::AuthorizeResponse) -> pb::AuthorizeResponse {
+        return pb::AuthorizeResponse {
+            redirect_uri: rsp.redirect_uri,
+            code: rsp.code,
+            state: rsp.state,
+        }
+    }
+}
+
+impl From<pb::TokenRequest> for models::TokenRequest {
+    fn from(req: pb::TokenRequest) -> models::TokenRequest {
+        return models::TokenRequest {
+            grant_type: req.grant_type,
+            client: models::ClientCredentials {
+                client_id: req.client_id,
+                client_secret: req.client_secret,
+            },
+            code: req.code,
+            redirect_uri: req.redirect_uri,
+            code_verifier: req.code_verifier,
+            refresh_token: req.refresh_token,
+        }
+    }
+}
+
+impl From<models::TokenResponse> for pb::TokenResponse {
+    fn from(rsp: models::TokenResponse) -> pb::TokenResponse {
+        return pb::TokenResponse {
+            access_token: rsp.tokens.access_token,
+            token_type: "Bearer".into(),
+            expires_in: rsp.expires_in,
+            refresh_token: rsp.tokens.refresh_token,
+            scope: rsp.scopes.join(" "),
+        }
+    }
+}
+
+impl From<models::IntrospectResponse> for pb::IntrospectResponse {
+    fn from(rsp: models::IntrospectResponse) -> pb::IntrospectResponse {
+        return pb::IntrospectResponse {
+            active: rsp.active,
+            scope: if rsp.active { Some(rsp.scopes.join(" ")) } else { None },
+            client_id: rsp.client_id,
+            user_id: rsp.user_id,
+            exp: rsp.expires_at.map(unix_seconds),
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use log::info;
+use std::collections::HashMap;
+
+use log::{info, warn};
 
 use diesel::prelude::*;
 use diesel::r2d2::ConnectionManager;
@@ -7,6 +9,10 @@ use errors::prelude::*;
 use crate::service;
 use crate::models;
 use crate::config;
+use crate::confirms;
+use crate::events;
+use crate::logins;
+use crate::proto_convert;
 
 type ConnectionPool = diesel::r2d2::Pool<ConnectionManager<PgConnection>>;
 type Connection = diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>;
@@ -46,6 +52,125 @@ impl PgRepo {
     fn open_connection(&self) -> Result<Connection> {
         self.pool.get().map_err(errors::Error::DbConnection)
     }
+
+    pub fn outbox_relay(&self, cfg: &config::Settings) -> amqp_outbox::Relay {
+        amqp_outbox::Relay::new(
+            self.pool.clone(),
+            &cfg.amqp_address,
+            cfg.outbox_batch_size,
+            std::time::Duration::from_millis(cfg.outbox_poll_interval),
+        )
+        .declare_queues(confirms::QUEUES)
+    }
+
+    // Rewrites logins stored before normalization was introduced.
+    // Rows whose normalized login is already taken by another user are
+    // reported and left untouched.
+    pub fn normalize_logins(&self, normalizer: &logins::Normalizer) -> Result<models::NormalizeLoginsResponse> {
+        use crate::schema::users::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let all: Vec<models::User> = users
+                .order(id)
+                .for_update()
+                .load(&connection)?;
+
+            let mut owners: HashMap<String, i32> = HashMap::new();
+            for user in &all {
+                for login in user.email.iter().chain(user.phone.iter()) {
+                    owners.insert(login.clone(), user.id);
+                }
+            }
+
+            let mut updated = 0;
+            let mut collisions = Vec::new();
+            for user in &all {
+                let new_email = match &user.email {
+                    Some(raw) => normalized_login(&mut owners, user.id, raw, normalizer.email(raw), &mut collisions),
+                    None => None,
+                };
+                let new_phone = match &user.phone {
+                    Some(raw) => normalized_login(&mut owners, user.id, raw, normalizer.phone(raw), &mut collisions),
+                    None => None,
+                };
+
+                if let Some(new_email) = new_email {
+                    diesel::update(users.filter(id.eq(user.id)))
+                        .set(email.eq(new_email))
+                        .execute(&connection)?;
+                    updated += 1;
+                }
+                if let Some(new_phone) = new_phone {
+                    diesel::update(users.filter(id.eq(user.id)))
+                        .set(phone.eq(new_phone))
+                        .execute(&connection)?;
+                    updated += 1;
+                }
+            }
+
+            Ok(models::NormalizeLoginsResponse { updated, collisions })
+        })
+    }
+}
+
+fn revoke_user_sessions(connection: &PgConnection, user: i32) -> Result<usize> {
+    use crate::schema::sessions::dsl::*;
+
+    let revoked: Vec<(i32, i32)> = diesel::delete(sessions.filter(user_id.eq(user)))
+        .returning((id, user_id))
+        .get_results(connection)?;
+
+    announce_revoked(connection, &revoked)
+}
+
+// Emits SessionRevoked for the deleted (session, user) pairs, must be called
+// in the transaction deleting them
+fn announce_revoked(connection: &PgConnection, revoked: &[(i32, i32)]) -> Result<usize> {
+    for (session, user) in revoked {
+        events::emit(connection, events::Event::SessionRevoked(pb::SessionRevoked {
+            user_id: *user,
+            session_id: *session,
+        }))?;
+    }
+
+    Ok(revoked.len())
+}
+
+// Returns the login to store if it differs from the raw one.
+fn normalized_login(
+    owners: &mut HashMap<String, i32>,
+    user_id: i32,
+    raw: &str,
+    normalized: Result<String>,
+    collisions: &mut Vec<models::LoginCollision>,
+) -> Option<String> {
+    let normalized = match normalized {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            warn!("Cannot normalize login {} of user {}: {}", raw, user_id, e);
+            return None;
+        }
+    };
+    if normalized == raw {
+        return None;
+    }
+
+    match owners.get(&normalized) {
+        Some(&owner) if owner != user_id => {
+            collisions.push(models::LoginCollision {
+                user_id,
+                login: raw.into(),
+                normalized,
+                owner_id: owner,
+            });
+            None
+        }
+        _ => {
+            owners.insert(normalized.clone(), user_id);
+            Some(normalized)
+        }
+    }
 }
 
 impl service::UsersRepo for PgRepo {
@@ -53,79 +178,165 @@ impl service::UsersRepo for PgRepo {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
-        let user = diesel::insert_into(users)
-            .values(user)
-            .get_result(&connection)?;
+        connection.transaction::<_, errors::Error, _>(|| {
+            let user: models::User = diesel::insert_into(users)
+                .values(user)
+                .get_result(&connection)?;
 
-        Ok(user)
+            events::emit(&connection, events::Event::UserRegistered(pb::UserRegistered { user_id: user.id }))?;
+
+            Ok(user)
+        })
     }
 
-    fn get_user_by_login(&self, login: &str) -> Result<models::User> {
+    fn get_user(&self, user: i32) -> Result<models::User> {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
         let user = users
-            .filter(email.eq(login))
-            .or_filter(phone.eq(login))
+            .filter(id.eq(user))
             .get_result(&connection)?;
 
         Ok(user)
     }
 
-    fn confirm_user(&self, user: i32, login: models::Login) -> Result<()> {
+    fn get_user_by_login(&self, login: &models::Login) -> Result<models::User> {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
-        let req = diesel::update(users.filter(id.eq(user)));
-        let req = match login {
-            models::Login::Email(new_email) => req.set(email.eq(new_email)),
-            models::Login::Phone(new_phone) => req.set(email.eq(new_phone)),
+        let user = match login {
+            models::Login::Email(login) => users.filter(email.eq(login)).get_result(&connection)?,
+            models::Login::Phone(login) => users.filter(phone.eq(login)).get_result(&connection)?,
         };
-        req.execute(&connection)?;
 
-        Ok(())
+        Ok(user)
+    }
+
+    fn confirm_user(&self, user: i32, login: models::Login) -> Result<()> {
+        use crate::schema::users::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let req = diesel::update(users.filter(id.eq(user)));
+            let event = match login {
+                models::Login::Email(new_email) => {
+                    req.set(email.eq(&new_email)).execute(&connection)?;
+                    pb::UserConfirmed { user_id: user, email: Some(new_email), phone: None }
+                }
+                models::Login::Phone(new_phone) => {
+                    req.set(phone.eq(&new_phone)).execute(&connection)?;
+                    pb::UserConfirmed { user_id: user, email: None, phone: Some(new_phone) }
+                }
+            };
+
+            events::emit(&connection, events::Event::UserConfirmed(event))
+        })
     }
 
     fn set_user_role(&self, user: i32, role: models::AccessLevel) -> Result<()> {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
-        let req = diesel::update(users.filter(id.eq(user))).set(permissions.eq(role));
-        req.execute(&connection)?;
+        connection.transaction::<_, errors::Error, _>(|| {
+            let count = diesel::update(users.filter(id.eq(user)))
+                .set(permissions.eq(role))
+                .execute(&connection)?;
 
-        Ok(())
+            if count > 0 {
+                events::emit(&connection, events::Event::RoleChanged(pb::RoleChanged {
+                    user_id: user,
+                    role: proto_convert::format_role(role),
+                }))?;
+            }
+
+            Ok(())
+        })
     }
 
-    fn get_user_role(&self, user: i32) -> Result<models::AccessLevel> {
+    fn disable_user(&self, user: i32) -> Result<usize> {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
-        let perms = users
-            .filter(id.eq(user))
-            .select(permissions)
-            .get_result(&connection)?;
+        connection.transaction::<_, errors::Error, _>(|| {
+            let count = diesel::update(users.filter(id.eq(user)).filter(disabled.eq(false)))
+                .set(disabled.eq(true))
+                .execute(&connection)?;
 
-        Ok(perms)
+            if count > 0 {
+                revoke_user_sessions(&connection, user)?;
+                events::emit(&connection, events::Event::UserDisabled(pb::UserDisabled { user_id: user }))?;
+            }
+
+            Ok(count)
+        })
     }
 
-    fn get_password_hash(&self, login: &str) -> Result<String> {
+    fn get_password_hash(&self, login: &models::Login) -> Result<String> {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
-        let hash: String = users
-            .filter(email.eq(login))
-            .or_filter(phone.eq(login))
-            .select(password)
-            .get_result(&connection)?;
+        let hash: String = match login {
+            models::Login::Email(login) => users.filter(email.eq(login)).select(password).get_result(&connection)?,
+            models::Login::Phone(login) => users.filter(phone.eq(login)).select(password).get_result(&connection)?,
+        };
 
         Ok(hash)
     }
 
+    // Sessions are revoked in the same transaction, so the old password
+    // cannot be used to keep a session alive
+    fn reset_password(&self, user: i32, hash: String) -> Result<usize> {
+        use crate::schema::users::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            diesel::update(users.filter(id.eq(user)))
+                .set(password.eq(hash))
+                .execute(&connection)?;
+
+            revoke_user_sessions(&connection, user)
+        })
+    }
+
+    // Returns None if there is an active admin already. The table is locked
+    // so concurrent calls cannot both create an admin.
+    fn add_first_admin(&self, user: models::NewUser) -> Result<Option<models::User>> {
+        use crate::schema::users::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            diesel::sql_query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE").execute(&connection)?;
+
+            let admins: i64 = users
+                .filter(permissions.eq(models::AccessLevel::Admin))
+                .filter(disabled.eq(false))
+                .count()
+                .get_result(&connection)?;
+            if admins > 0 {
+                return Ok(None);
+            }
+
+            let user: models::User = diesel::insert_into(users)
+                .values(user)
+                .get_result(&connection)?;
+
+            events::emit(&connection, events::Event::UserRegistered(pb::UserRegistered { user_id: user.id }))?;
+            events::emit(&connection, events::Event::UserConfirmed(pb::UserConfirmed {
+                user_id: user.id,
+                email: user.email.clone(),
+                phone: user.phone.clone(),
+            }))?;
+
+            Ok(Some(user))
+        })
+    }
+
     fn list_users(&self, req: models::ListUsersRequest) -> Result<models::ListUsersResponse> {
         use crate::schema::users::dsl::*;
         let connection = self.open_connection()?;
 
         let res = users
+            .order(id)
             .offset(req.offset.unwrap_or(0))
             .limit(req.limit.unwrap_or(i64::max_value()))
             .load(&connection)?;
@@ -150,11 +361,20 @@ impl service::TokensRepo for PgRepo {
         use crate::schema::sessions::dsl::*;
         let connection = self.open_connection()?;
 
-        let count = diesel::delete(sessions)
-            .filter(id.eq(session_id))
-            .execute(&connection)?;
+        connection.transaction::<_, errors::Error, _>(|| {
+            let revoked: Vec<(i32, i32)> = diesel::delete(sessions)
+                .filter(id.eq(session_id))
+                .returning((id, user_id))
+                .get_results(&connection)?;
 
-        Ok(count)
+            announce_revoked(&connection, &revoked)
+        })
+    }
+
+    fn revoke_sessions(&self, user: i32) -> Result<usize> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| revoke_user_sessions(&connection, user))
     }
 
     fn get_session_by_access_token(&self, token: &str) -> Result<models::Session> {
@@ -196,11 +416,13 @@ impl service::ConfirmationsRepo for PgRepo {
             models::Login::Phone(new_phone) => value.phone = Some(new_phone.to_owned()),
         };
 
-        diesel::insert_into(confirmations)
-            .values(value)
-            .execute(&connection)?;
+        connection.transaction::<_, errors::Error, _>(|| {
+            diesel::insert_into(confirmations)
+                .values(value)
+                .execute(&connection)?;
 
-        Ok(())
+            confirms::send(&connection, login, confirmation_token)
+        })
     }
 
     fn find_confirmation(&self, confirmation_token: &str) -> Result<models::Confirmation> {
@@ -225,3 +447,156 @@ impl service::ConfirmationsRepo for PgRepo {
         Ok(())
     }
 }
+
+impl service::InvitationsRepo for PgRepo {
+    fn add_invitation(&self, invitation: models::NewInvitation, login: &models::Login) -> Result<models::Invitation> {
+        use crate::schema::invitations::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let invitation: models::Invitation = diesel::insert_into(invitations)
+                .values(invitation)
+                .get_result(&connection)?;
+
+            confirms::send_invite(&connection, login, &invitation.token)?;
+
+            Ok(invitation)
+        })
+    }
+
+    fn list_invitations(&self, req: models::ListInvitationsRequest) -> Result<models::ListInvitationsResponse> {
+        use crate::schema::invitations::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = invitations
+            .order(id)
+            .offset(req.offset.unwrap_or(0))
+            .limit(req.limit.unwrap_or(i64::max_value()))
+            .load(&connection)?;
+
+        Ok(models::ListInvitationsResponse{ invitations: res })
+    }
+
+    fn remove_invitation(&self, invitation_id: i32) -> Result<usize> {
+        use crate::schema::invitations::dsl::*;
+        let connection = self.open_connection()?;
+
+        let count = diesel::delete(invitations)
+            .filter(id.eq(invitation_id))
+            .execute(&connection)?;
+
+        Ok(count)
+    }
+
+    fn accept_invitation(&self, invitation_token: &str, password_hash: String) -> Result<models::User> {
+        use crate::schema::invitations::dsl as inv;
+        use crate::schema::users::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let invitation: models::Invitation = inv::invitations
+                .filter(inv::token.eq(invitation_token))
+                .filter(inv::expires_at.gt(std::time::SystemTime::now()))
+                .for_update()
+                .get_result(&connection)?;
+
+            let user: models::User = diesel::insert_into(users)
+                .values(models::NewUser {
+                    phone: invitation.phone,
+                    email: invitation.email,
+                    password: password_hash,
+                    permissions: invitation.permissions,
+                })
+                .get_result(&connection)?;
+
+            diesel::delete(inv::invitations)
+                .filter(inv::id.eq(invitation.id))
+                .execute(&connection)?;
+
+            events::emit(&connection, events::Event::UserRegistered(pb::UserRegistered { user_id: user.id }))?;
+            events::emit(&connection, events::Event::UserConfirmed(pb::UserConfirmed {
+                user_id: user.id,
+                email: user.email.clone(),
+                phone: user.phone.clone(),
+            }))?;
+
+            Ok(user)
+        })
+    }
+}
+
+impl service::OauthRepo for PgRepo {
+    fn add_client(&self, client: models::NewOauthClient) -> Result<models::OauthClient> {
+        use crate::schema::oauth_clients::dsl::*;
+        let connection = self.open_connection()?;
+
+        let client = diesel::insert_into(oauth_clients)
+            .values(client)
+            .get_result(&connection)?;
+
+        Ok(client)
+    }
+
+    fn get_client(&self, client: &str) -> Result<models::OauthClient> {
+        use crate::schema::oauth_clients::dsl::*;
+        let connection = self.open_connection()?;
+
+        let client = oauth_clients
+            .filter(client_id.eq(client))
+            .get_result(&connection)?;
+
+        Ok(client)
+    }
+
+    fn grant_consent(&self, consent: models::OauthConsent) -> Result<()> {
+        use crate::schema::oauth_consents::dsl::*;
+        let connection = self.open_connection()?;
+
+        diesel::insert_into(oauth_consents)
+            .values(&consent)
+            .on_conflict((user_id, client_id))
+            .do_update()
+            .set(&consent)
+            .execute(&connection)?;
+
+        Ok(())
+    }
+
+    fn add_code(&self, authorization_code: models::OauthCode) -> Result<()> {
+        use crate::schema::oauth_codes::dsl::*;
+        let connection = self.open_connection()?;
+
+        diesel::insert_into(oauth_codes)
+            .values(authorization_code)
+            .execute(&connection)?;
+
+        Ok(())
+    }
+
+    // Codes are single use, so the code is removed whether the
+    // exchange succeeds or not.
+    fn take_code(&self, authorization_code: &str) -> Result<models::OauthCode> {
+        use crate::schema::oauth_codes::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = diesel::delete(oauth_codes.filter(code.eq(authorization_code)))
+            .get_result(&connection)?;
+
+        Ok(res)
+    }
+
+    fn remove_client_session(&self, client: &str, token: &str) -> Result<usize> {
+        use crate::schema::sessions::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let revoked: Vec<(i32, i32)> = diesel::delete(sessions)
+                .filter(client_id.eq(client))
+                .filter(access_token.eq(token).or(refresh_token.eq(token)))
+                .returning((id, user_id))
+                .get_results(&connection)?;
+
+            announce_revoked(&connection, &revoked)
+        })
+    }
+}
@@ -10,6 +10,60 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::models::Access_level;
+
+    invitations (id) {
+        id -> Int4,
+        token -> Text,
+        phone -> Nullable<Text>,
+        email -> Nullable<Text>,
+        permissions -> Access_level,
+        invited_by -> Int4,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    oauth_clients (id) {
+        id -> Int4,
+        client_id -> Text,
+        secret_hash -> Nullable<Text>,
+        name -> Text,
+        redirect_uris -> Array<Text>,
+        scopes -> Array<Text>,
+        created_by -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    oauth_codes (code) {
+        code -> Text,
+        client_id -> Text,
+        user_id -> Int4,
+        redirect_uri -> Text,
+        scopes -> Array<Text>,
+        code_challenge -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+
+    oauth_consents (user_id, client_id) {
+        user_id -> Int4,
+        client_id -> Text,
+        scopes -> Array<Text>,
+        granted_at -> Timestamp,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
 
@@ -30,6 +84,8 @@ table! {
         access_token -> Text,
         expires_at -> Timestamp,
         user_id -> Int4,
+        client_id -> Nullable<Text>,
+        scopes -> Nullable<Array<Text>>,
     }
 }
 
@@ -43,14 +99,23 @@ table! {
         email -> Nullable<Text>,
         password -> Text,
         permissions -> Access_level,
+        disabled -> Bool,
     }
 }
 
 joinable!(confirmations -> users (user_id));
+joinable!(invitations -> users (invited_by));
+joinable!(oauth_clients -> users (created_by));
+joinable!(oauth_codes -> users (user_id));
+joinable!(oauth_consents -> users (user_id));
 joinable!(sessions -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
     confirmations,
+    invitations,
+    oauth_clients,
+    oauth_codes,
+    oauth_consents,
     products,
     sessions,
     users,
@@ -5,7 +5,9 @@ use errors::prelude::*;
 use crate::service;
 use crate::models;
 use pb::auth_server::Auth;
+use pb::oauth_server::Oauth;
 
+#[derive(Clone)]
 pub struct Server {
     auth: service::Service,
     auth_client: auth_client::client::Client,
@@ -27,13 +29,23 @@ fn parse_token<T>(request: &Request<T>) -> Result<String> {
     Ok(token.into())
 }
 
+impl Server {
+    // Only first-party sessions may approve oauth requests
+    async fn first_party_identity<T>(&self, request: &Request<T>) -> Result<auth_client::Identity> {
+        match self.auth_client.validate(parse_token(request)?).await? {
+            Some(identity) if identity.client_id.is_none() => Ok(identity),
+            _ => Err(Error::Unauthorized("Permission denied".into())),
+        }
+    }
+}
+
 #[tonic::async_trait]
 impl Auth for Server {
     async fn register(
         &self,
         request: Request<pb::RegisterRequest>,
     ) -> std::result::Result<Response<pb::RegisterResponse>, Status> {
-        self.auth.register(request.into_inner().into()).await?;
+        self.auth.register(request.into_inner().into())?;
         Ok(Response::new(pb::RegisterResponse::default()))
     }
 
@@ -81,6 +93,15 @@ impl Auth for Server {
         Ok(Response::new(pb::UpdateUserResponse::default()))
     }
 
+    async fn disable_user(
+        &self,
+        request: Request<pb::DisableUserRequest>,
+    ) -> std::result::Result<Response<pb::DisableUserResponse>, Status> {
+        self.auth_client.validate_role(parse_token(&request)?, auth_client::Role::Admin).await?;
+        self.auth.disable_user(request.into_inner().id)?;
+        Ok(Response::new(pb::DisableUserResponse::default()))
+    }
+
     async fn validate_token(
         &self,
         request: Request<pb::ValidateTokenRequest>,
@@ -88,5 +109,92 @@ impl Auth for Server {
         let res = self.auth.validate(&request.into_inner().token);
         Ok(Response::new(res.into()))
     }
+
+    async fn invite_user(
+        &self,
+        request: Request<pb::InviteUserRequest>,
+    ) -> std::result::Result<Response<pb::InviteUserResponse>, Status> {
+        let admin = self.auth_client.validate_role(parse_token(&request)?, auth_client::Role::Admin).await?;
+        let invitation = self.auth.invite_user(admin.user_id, request.into_inner().into())?;
+        Ok(Response::new(pb::InviteUserResponse{ invitation: invitation.into() }))
+    }
+
+    async fn accept_invite(
+        &self,
+        request: Request<pb::AcceptInviteRequest>,
+    ) -> std::result::Result<Response<pb::AcceptInviteResponse>, Status> {
+        let response = self.auth.accept_invite(request.into_inner().into())?;
+        Ok(Response::new(response.into()))
+    }
+
+    async fn list_invitations(
+        &self,
+        request: Request<pb::ListInvitationsRequest>,
+    ) -> std::result::Result<Response<pb::ListInvitationsResponse>, Status> {
+        self.auth_client.validate_role(parse_token(&request)?, auth_client::Role::Admin).await?;
+        let invitations = self.auth.list_invitations(request.into_inner().into())?;
+        Ok(Response::new(invitations.into()))
+    }
+
+    async fn revoke_invitation(
+        &self,
+        request: Request<pb::RevokeInvitationRequest>,
+    ) -> std::result::Result<Response<pb::RevokeInvitationResponse>, Status> {
+        self.auth_client.validate_role(parse_token(&request)?, auth_client::Role::Admin).await?;
+        self.auth.revoke_invitation(request.into_inner().id)?;
+        Ok(Response::new(pb::RevokeInvitationResponse::default()))
+    }
 }
 
+#[tonic::async_trait]
+impl Oauth for Server {
+    async fn register_client(
+        &self,
+        request: Request<pb::RegisterClientRequest>,
+    ) -> std::result::Result<Response<pb::RegisterClientResponse>, Status> {
+        let admin = self.auth_client.validate_role(parse_token(&request)?, auth_client::Role::Admin).await?;
+        let response = self.auth.register_client(admin.user_id, request.into_inner().into())?;
+        Ok(Response::new(response.into()))
+    }
+
+    async fn authorize(
+        &self,
+        request: Request<pb::AuthorizeRequest>,
+    ) -> std::result::Result<Response<pb::AuthorizeResponse>, Status> {
+        let identity = self.first_party_identity(&request).await?;
+        let response = self.auth.authorize(identity.user_id, request.into_inner().into())?;
+        Ok(Response::new(response.into()))
+    }
+
+    async fn token(
+        &self,
+        request: Request<pb::TokenRequest>,
+    ) -> std::result::Result<Response<pb::TokenResponse>, Status> {
+        let response = self.auth.token(request.into_inner().into())?;
+        Ok(Response::new(response.into()))
+    }
+
+    async fn revoke(
+        &self,
+        request: Request<pb::RevokeRequest>,
+    ) -> std::result::Result<Response<pb::RevokeResponse>, Status> {
+        let req = request.into_inner();
+        self.auth.revoke(models::ClientCredentials {
+            client_id: req.client_id,
+            client_secret: req.client_secret,
+        }, &req.token)?;
+        Ok(Response::new(pb::RevokeResponse::default()))
+    }
+
+    async fn introspect(
+        &self,
+        request: Request<pb::IntrospectRequest>,
+    ) -> std::result::Result<Response<pb::IntrospectResponse>, Status> {
+        let req = request.into_inner();
+        let response = self.auth.introspect(models::ClientCredentials {
+            client_id: req.client_id,
+            client_secret: req.client_secret,
+        }, &req.token)?;
+        Ok(Response::new(response.into()))
+    }
+}
@@ -6,25 +6,28 @@ use {
 
     crate::models,
     crate::repo,
-    crate::confirms,
     crate::config,
+    crate::logins,
 
     rand::prelude::*,
     rand::distributions::Alphanumeric,
 
-    async_trait::async_trait,
-
     bcrypt,
+    sha2::{Digest, Sha256},
+    subtle::ConstantTimeEq,
 };
 
 pub trait UsersRepo {
     fn add_user(&self, user: models::NewUser) -> Result<models::User>;
-    fn get_user_by_login(&self, login: &str) -> Result<models::User>;
+    fn get_user(&self, user: i32) -> Result<models::User>;
+    fn get_user_by_login(&self, login: &models::Login) -> Result<models::User>;
 
     fn confirm_user(&self, user: i32, login: models::Login) -> Result<()>;
     fn set_user_role(&self, user: i32, role: models::AccessLevel) -> Result<()>;
-    fn get_user_role(&self, user: i32) -> Result<models::AccessLevel>;
-    fn get_password_hash(&self, login: &str) -> Result<String>;
+    fn disable_user(&self, user: i32) -> Result<usize>;
+    fn reset_password(&self, user: i32, hash: String) -> Result<usize>;
+    fn add_first_admin(&self, user: models::NewUser) -> Result<Option<models::User>>;
+    fn get_password_hash(&self, login: &models::Login) -> Result<String>;
     fn list_users(&self, req: models::ListUsersRequest) -> Result<models::ListUsersResponse>;
 }
 
@@ -33,6 +36,7 @@ pub trait TokensRepo {
     fn get_session_by_access_token(&self, token: &str) -> Result<models::Session>;
     fn get_session_by_refresh_token(&self, token: &str) -> Result<models::Session>;
     fn remove_session(&self, id: i32) -> Result<usize>;
+    fn revoke_sessions(&self, user: i32) -> Result<usize>;
 }
 
 pub trait ConfirmationsRepo {
@@ -41,39 +45,65 @@ pub trait ConfirmationsRepo {
     fn remove_confirmation(&self, token: &str) -> Result<()>;
 }
 
-#[async_trait]
-pub trait ConfirmationsSender {
-    async fn send(&self, login: models::Login, token: String) -> Result<()>;
+pub trait InvitationsRepo {
+    fn add_invitation(&self, invitation: models::NewInvitation, login: &models::Login) -> Result<models::Invitation>;
+    fn list_invitations(&self, req: models::ListInvitationsRequest) -> Result<models::ListInvitationsResponse>;
+    fn remove_invitation(&self, id: i32) -> Result<usize>;
+    fn accept_invitation(&self, token: &str, password_hash: String) -> Result<models::User>;
+}
+
+pub trait OauthRepo {
+    fn add_client(&self, client: models::NewOauthClient) -> Result<models::OauthClient>;
+    fn get_client(&self, client_id: &str) -> Result<models::OauthClient>;
+    fn grant_consent(&self, consent: models::OauthConsent) -> Result<()>;
+    fn add_code(&self, code: models::OauthCode) -> Result<()>;
+    fn take_code(&self, code: &str) -> Result<models::OauthCode>;
+    fn remove_client_session(&self, client_id: &str, token: &str) -> Result<usize>;
 }
 
 #[derive(Clone)]
 pub struct Service<> {
     session_timeout: u32,
+    invite_timeout: u32,
+    oauth_code_timeout: u32,
     repo: repo::PgRepo,
-    confirms_sender: confirms::ConfrimsSender,
+    logins: logins::Normalizer,
 }
 
 const BCRYPT_COST: u32 = 10;
 
+// Scopes oauth clients may request
+const SCOPES: &[&str] = &["profile", "shop", "shop:admin"];
+const CODE_CHALLENGE_METHOD: &str = "S256";
+const PROFILE_SCOPE: &str = "profile";
+
 impl Service {
-    pub fn new(cfg: &config::Settings, repo: repo::PgRepo, confirms_sender: confirms::ConfrimsSender) -> Self {
+    pub fn new(
+        cfg: &config::Settings,
+        repo: repo::PgRepo,
+        logins: logins::Normalizer,
+    ) -> Self {
         Service {
             session_timeout: cfg.session_timeout,
+            invite_timeout: cfg.invite_timeout,
+            oauth_code_timeout: cfg.oauth_code_timeout,
             repo,
-            confirms_sender
+            logins,
         }
     }
 
-    pub async fn register(&self, user: models::NewUser) -> Result<()> {
+    pub fn register(&self, mut user: models::NewUser) -> Result<()> {
         if user.email.is_none() && user.phone.is_none() {
             return Err(Error::BadRequest("Login is required".into()));
         }
         if user.password.is_empty() {
             return Err(Error::BadRequest("Password is required".into()));
         }
+        user.email = user.email.map(|email| self.logins.email(&email)).transpose()?;
+        user.phone = user.phone.map(|phone| self.logins.phone(&phone)).transpose()?;
 
         let user_id = self.register_user(user.clone())?;
-        match self.generate_confirmations(user_id, &user).await {
+        match self.generate_confirmations(user_id, &user) {
             Ok(_) => (),
             Err(Error::DbNonUnique(_)) => return Err(Error::BadRequest("Login is already used".into())),
             Err(e) => return Err(e),
@@ -90,17 +120,17 @@ impl Service {
         Ok(user.id)
     }
 
-    fn hash_password(password: String) -> Result<String> {
+    pub fn hash_password(password: String) -> Result<String> {
         let hash = bcrypt::hash(password, BCRYPT_COST)?;
         Ok(hash)
     }
 
-    async fn generate_confirmations(&self, user_id: i32, user: &models::NewUser) -> Result<()> {
+    fn generate_confirmations(&self, user_id: i32, user: &models::NewUser) -> Result<()> {
         if let Some(login) = user.email.as_ref() {
-            self.generate_confirmation(models::Login::Email(login.clone()), user_id).await?;
+            self.generate_confirmation(models::Login::Email(login.clone()), user_id)?;
         }
         if let Some(login) = user.phone.as_ref() {
-            self.generate_confirmation(models::Login::Phone(login.clone()), user_id).await?;
+            self.generate_confirmation(models::Login::Phone(login.clone()), user_id)?;
         }
 
         Ok(())
@@ -113,38 +143,66 @@ impl Service {
             .collect::<String>()
     }
 
-    async fn generate_confirmation(&self, login: models::Login, user: i32) -> Result<()> {
+    // The confirmation is sent through the outbox in the same transaction
+    fn generate_confirmation(&self, login: models::Login, user: i32) -> Result<()> {
         let token = Self::gen_token();
-
-        self.repo.add_confirmation(user, &login, &token)?;
-        self.confirms_sender.send(login, token).await?;
-
-        Ok(())
+        self.repo.add_confirmation(user, &login, &token)
     }
 
     pub fn login(&self, request: models::LoginRequest) -> Result<models::LoginResponse> {
-        let user = match self.repo.get_user_by_login(&request.login) {
+        let login = match self.logins.login(&request.login) {
+            Ok(login) => login,
+            Err(_) => return Err(Error::Unauthorized("Invalid credentials".into())),
+        };
+        let user = match self.find_login(&login, &request.login) {
             Ok(user) => user,
             Err(Error::DbNotFound(_)) => return Err(Error::Unauthorized("Invalid credentials".into())),
             Err(e) => return Err(e)
         };
         let hash_equal = bcrypt::verify(request.password, &user.password)?;
-        if hash_equal {
-            Ok(self.gen_tokens(user.id)?)
+        if hash_equal && user.disabled {
+            Err(Error::Unauthorized("User is disabled".into()))
+        } else if hash_equal {
+            Ok(self.gen_tokens(user.id, None)?)
         } else {
             Err(Error::Unauthorized("Invalid credentials".into()))
         }
     }
 
-    fn gen_tokens(&self, user_id: i32) -> Result<models::LoginResponse> {
+    // Logins stored before normalization only match as typed until
+    // `auth normalize-logins` rewrites them, so the raw login is tried too
+    fn find_login(&self, login: &models::Login, raw: &str) -> Result<models::User> {
+        match self.repo.get_user_by_login(login) {
+            Err(Error::DbNotFound(e)) => {
+                let raw = match login {
+                    models::Login::Email(_) => models::Login::Email(raw.into()),
+                    models::Login::Phone(_) => models::Login::Phone(raw.into()),
+                };
+                if raw == *login {
+                    return Err(Error::DbNotFound(e));
+                }
+                self.repo.get_user_by_login(&raw)
+            }
+            res => res,
+        }
+    }
+
+    // Sessions without a grant are first-party and are not limited by scopes
+    fn gen_tokens(&self, user_id: i32, grant: Option<models::Grant>) -> Result<models::LoginResponse> {
         let access_token = Self::gen_token();
         let refresh_token = Self::gen_token();
 
+        let (client_id, scopes) = match grant {
+            Some(grant) => (Some(grant.client_id), Some(grant.scopes)),
+            None => (None, None),
+        };
         let session = models::NewSession {
             refresh_token: &refresh_token,
             access_token: &access_token,
             expires_at: std::time::SystemTime::now() + Duration::new(self.session_timeout.into(), 0),
             user_id,
+            client_id,
+            scopes,
         };
         self.repo.add_session(session)?;
 
@@ -158,38 +216,54 @@ impl Service {
     pub fn validate(&self, token: &str) -> models::ValidateTokenResponse {
         let session = match self.repo.get_session_by_access_token(token) {
             Ok(s) => s,
-            Err(_) => return models::ValidateTokenResponse{ valid: false, role: models::AccessLevel::User },
+            Err(_) => return models::ValidateTokenResponse::default(),
         };
+        if SystemTime::now() >= session.expires_at {
+            return models::ValidateTokenResponse::default();
+        }
 
-        let mut valid = SystemTime::now() < session.expires_at;
-        let role = self.repo.get_user_role(session.user_id).unwrap_or_else(|_| {
-            valid = false;
-            models::AccessLevel::User
-        });
+        let user = match self.repo.get_user(session.user_id) {
+            Ok(user) if !user.disabled => user,
+            _ => return models::ValidateTokenResponse::default(),
+        };
 
-        models::ValidateTokenResponse{ valid, role }
+        let scopes = session.scopes.unwrap_or_default();
+        let profile = session.client_id.is_none() || scopes.iter().any(|s| s == PROFILE_SCOPE);
+
+        models::ValidateTokenResponse {
+            valid: true,
+            role: user.permissions,
+            user_id: Some(user.id),
+            session_id: Some(session.id),
+            expires_at: Some(session.expires_at),
+            email: if profile { user.email } else { None },
+            phone: if profile { user.phone } else { None },
+            client_id: session.client_id,
+            scopes,
+        }
     }
 
     pub fn refresh(&self, req: models::RefreshRequest) -> Result<models::LoginResponse> {
+        // Oauth clients refresh their tokens through the token endpoint
         let session = match self.repo.get_session_by_refresh_token(&req.refresh_token) {
-            Ok(s) => s,
-            Err(Error::DbNotFound(_)) => return Err(Error::Unauthorized("Unknown session".into())),
+            Ok(s) if s.client_id.is_none() => s,
+            Ok(_) | Err(Error::DbNotFound(_)) => return Err(Error::Unauthorized("Unknown session".into())),
             Err(error) => return Err(error.into()),
         };
 
         let _ = self.repo.remove_session(session.id);
 
-        Ok(self.gen_tokens(session.user_id)?)
+        Ok(self.gen_tokens(session.user_id, None)?)
     }
 
     pub fn confirm(&self, token: &str) -> Result<()> {
         let confirmation = self.repo.find_confirmation(token)?;
         self.repo.remove_confirmation(token)?;
         if let Some(email) = confirmation.email {
-            self.repo.confirm_user(confirmation.user_id, models::Login::Email(email))?;
+            self.repo.confirm_user(confirmation.user_id, models::Login::Email(self.logins.email(&email)?))?;
         }
         if let Some(phone) = confirmation.phone {
-            self.repo.confirm_user(confirmation.user_id, models::Login::Email(phone))?;
+            self.repo.confirm_user(confirmation.user_id, models::Login::Phone(self.logins.phone(&phone)?))?;
         }
         Ok(())
     }
@@ -201,4 +275,319 @@ impl Service {
     pub fn list_users(&self, req: models::ListUsersRequest) -> Result<models::ListUsersResponse> {
         self.repo.list_users(req)
     }
+
+    pub fn disable_user(&self, user_id: i32) -> Result<()> {
+        match self.repo.disable_user(user_id)? {
+            0 => Err(Error::NotFound("User not found or already disabled".into())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn invite_user(&self, invited_by: i32, req: models::InviteUserRequest) -> Result<models::Invitation> {
+        let login = self.logins.login(&req.login)?;
+        match self.repo.get_user_by_login(&login) {
+            Ok(_) => return Err(Error::BadRequest("Login is already used".into())),
+            Err(Error::DbNotFound(_)) => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut invitation = models::NewInvitation {
+            token: Self::gen_token(),
+            phone: None,
+            email: None,
+            permissions: req.role,
+            invited_by,
+            expires_at: SystemTime::now() + Duration::new(self.invite_timeout.into(), 0),
+        };
+        match &login {
+            models::Login::Email(email) => invitation.email = Some(email.clone()),
+            models::Login::Phone(phone) => invitation.phone = Some(phone.clone()),
+        };
+
+        self.repo.add_invitation(invitation, &login)
+    }
+
+    pub fn accept_invite(&self, req: models::AcceptInviteRequest) -> Result<models::LoginResponse> {
+        if req.password.is_empty() {
+            return Err(Error::BadRequest("Password is required".into()));
+        }
+
+        let password = Self::hash_password(req.password)?;
+        let user = match self.repo.accept_invitation(&req.token, password) {
+            Ok(user) => user,
+            Err(Error::DbNotFound(_)) => return Err(Error::NotFound("Invitation not found or expired".into())),
+            Err(Error::DbNonUnique(_)) => return Err(Error::BadRequest("Login is already used".into())),
+            Err(e) => return Err(e),
+        };
+
+        self.gen_tokens(user.id, None)
+    }
+
+    pub fn list_invitations(&self, req: models::ListInvitationsRequest) -> Result<models::ListInvitationsResponse> {
+        self.repo.list_invitations(req)
+    }
+
+    pub fn revoke_invitation(&self, id: i32) -> Result<()> {
+        match self.repo.remove_invitation(id)? {
+            0 => Err(Error::NotFound("Invitation not found".into())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn register_client(&self, created_by: i32, req: models::RegisterClientRequest) -> Result<models::RegisterClientResponse> {
+        if req.name.is_empty() {
+            return Err(Error::BadRequest("Client name is required".into()));
+        }
+        if req.redirect_uris.is_empty() {
+            return Err(Error::BadRequest("Redirect uri is required".into()));
+        }
+        for uri in &req.redirect_uris {
+            validate_redirect_uri(uri)?;
+        }
+        if let Some(scope) = req.scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+            return Err(Error::BadRequest(format!("Unknown scope {}", scope)));
+        }
+
+        let client_secret = if req.confidential { Some(Self::gen_token()) } else { None };
+        let client = self.repo.add_client(models::NewOauthClient {
+            client_id: Self::gen_token(),
+            secret_hash: client_secret.clone().map(Self::hash_password).transpose()?,
+            name: req.name,
+            redirect_uris: req.redirect_uris,
+            scopes: req.scopes,
+            created_by,
+        })?;
+
+        Ok(models::RegisterClientResponse { client, client_secret })
+    }
+
+    // Issues an authorization code after the user has approved the request
+    pub fn authorize(&self, user_id: i32, req: models::AuthorizeRequest) -> Result<models::AuthorizeResponse> {
+        if req.response_type != "code" {
+            return Err(Error::BadRequest(format!("Unsupported response type {}", req.response_type)));
+        }
+
+        let client = match self.repo.get_client(&req.client_id) {
+            Ok(client) => client,
+            Err(Error::DbNotFound(_)) => return Err(Error::BadRequest("Unknown client".into())),
+            Err(e) => return Err(e),
+        };
+        if !client.redirect_uris.contains(&req.redirect_uri) {
+            return Err(Error::BadRequest("Redirect uri is not registered".into()));
+        }
+
+        let scopes = match &req.scope {
+            Some(scope) => parse_scopes(scope),
+            None => client.scopes.clone(),
+        };
+        if let Some(scope) = scopes.iter().find(|s| !client.scopes.contains(s)) {
+            return Err(Error::BadRequest(format!("Scope {} is not allowed for the client", scope)));
+        }
+
+        if req.code_challenge_method.as_deref() != Some(CODE_CHALLENGE_METHOD) {
+            return Err(Error::BadRequest("Only S256 code challenge method is supported".into()));
+        }
+        match base64::decode_config(&req.code_challenge, base64::URL_SAFE_NO_PAD) {
+            Ok(digest) if digest.len() == 32 => (),
+            _ => return Err(Error::BadRequest("Invalid code challenge".into())),
+        }
+
+        let now = SystemTime::now();
+        self.repo.grant_consent(models::OauthConsent {
+            user_id,
+            client_id: client.client_id.clone(),
+            scopes: scopes.clone(),
+            granted_at: now,
+        })?;
+
+        let code = Self::gen_token();
+        self.repo.add_code(models::OauthCode {
+            code: code.clone(),
+            client_id: client.client_id,
+            user_id,
+            redirect_uri: req.redirect_uri.clone(),
+            scopes,
+            code_challenge: req.code_challenge,
+            expires_at: now + Duration::new(self.oauth_code_timeout.into(), 0),
+        })?;
+
+        let mut redirect_uri = url::Url::parse(&req.redirect_uri)
+            .map_err(|_| Error::BadRequest("Invalid redirect uri".into()))?;
+        {
+            let mut query = redirect_uri.query_pairs_mut();
+            query.append_pair("code", &code);
+            if let Some(state) = &req.state {
+                query.append_pair("state", state);
+            }
+        }
+
+        Ok(models::AuthorizeResponse {
+            redirect_uri: redirect_uri.to_string(),
+            code,
+            state: req.state,
+        })
+    }
+
+    pub fn token(&self, req: models::TokenRequest) -> Result<models::TokenResponse> {
+        let client = self.authenticate_client(&req.client)?;
+        match req.grant_type.as_str() {
+            "authorization_code" => self.exchange_code(client, req),
+            "refresh_token" => self.refresh_client_tokens(client, req),
+            _ => Err(Error::BadRequest(format!("Unsupported grant type {}", req.grant_type))),
+        }
+    }
+
+    fn exchange_code(&self, client: models::OauthClient, req: models::TokenRequest) -> Result<models::TokenResponse> {
+        let code = req.code.ok_or_else(|| Error::BadRequest("Code is required".into()))?;
+        let verifier = req.code_verifier.ok_or_else(|| Error::BadRequest("Code verifier is required".into()))?;
+
+        let grant = match self.repo.take_code(&code) {
+            Ok(grant) => grant,
+            Err(Error::DbNotFound(_)) => return Err(Error::BadRequest("Invalid authorization code".into())),
+            Err(e) => return Err(e),
+        };
+        if grant.client_id != client.client_id
+            || SystemTime::now() >= grant.expires_at
+            || req.redirect_uri.as_deref() != Some(grant.redirect_uri.as_str())
+        {
+            return Err(Error::BadRequest("Invalid authorization code".into()));
+        }
+        if !verify_code_challenge(&verifier, &grant.code_challenge) {
+            return Err(Error::BadRequest("Invalid code verifier".into()));
+        }
+        if self.repo.get_user(grant.user_id)?.disabled {
+            return Err(Error::Unauthorized("User is disabled".into()));
+        }
+
+        let tokens = self.gen_tokens(grant.user_id, Some(models::Grant {
+            client_id: client.client_id,
+            scopes: grant.scopes.clone(),
+        }))?;
+
+        Ok(models::TokenResponse {
+            tokens,
+            expires_in: self.session_timeout.into(),
+            scopes: grant.scopes,
+        })
+    }
+
+    fn refresh_client_tokens(&self, client: models::OauthClient, req: models::TokenRequest) -> Result<models::TokenResponse> {
+        let token = req.refresh_token.ok_or_else(|| Error::BadRequest("Refresh token is required".into()))?;
+
+        let session = match self.repo.get_session_by_refresh_token(&token) {
+            Ok(s) if s.client_id.as_ref() == Some(&client.client_id) => s,
+            Ok(_) | Err(Error::DbNotFound(_)) => return Err(Error::BadRequest("Invalid refresh token".into())),
+            Err(e) => return Err(e),
+        };
+
+        let _ = self.repo.remove_session(session.id);
+
+        let scopes = session.scopes.unwrap_or_default();
+        let tokens = self.gen_tokens(session.user_id, Some(models::Grant {
+            client_id: client.client_id,
+            scopes: scopes.clone(),
+        }))?;
+
+        Ok(models::TokenResponse {
+            tokens,
+            expires_in: self.session_timeout.into(),
+            scopes,
+        })
+    }
+
+    // Unknown tokens are not an error, see RFC 7009
+    pub fn revoke(&self, client: models::ClientCredentials, token: &str) -> Result<()> {
+        let client = self.authenticate_client(&client)?;
+        self.repo.remove_client_session(&client.client_id, token)?;
+        Ok(())
+    }
+
+    // Clients may only introspect tokens issued to them
+    pub fn introspect(&self, client: models::ClientCredentials, token: &str) -> Result<models::IntrospectResponse> {
+        let client = self.authenticate_client(&client)?;
+
+        let inactive = models::IntrospectResponse {
+            active: false,
+            scopes: vec![],
+            client_id: None,
+            user_id: None,
+            expires_at: None,
+        };
+        let session = match self.repo.get_session_by_access_token(token) {
+            Ok(s) if s.client_id.as_ref() == Some(&client.client_id) => s,
+            Ok(_) | Err(Error::DbNotFound(_)) => return Ok(inactive),
+            Err(e) => return Err(e),
+        };
+        if SystemTime::now() >= session.expires_at || self.repo.get_user(session.user_id)?.disabled {
+            return Ok(inactive);
+        }
+
+        Ok(models::IntrospectResponse {
+            active: true,
+            scopes: session.scopes.unwrap_or_default(),
+            client_id: session.client_id,
+            user_id: Some(session.user_id),
+            expires_at: Some(session.expires_at),
+        })
+    }
+
+    // Public clients have no secret, the code is protected by PKCE instead
+    fn authenticate_client(&self, creds: &models::ClientCredentials) -> Result<models::OauthClient> {
+        let client = match self.repo.get_client(&creds.client_id) {
+            Ok(client) => client,
+            Err(Error::DbNotFound(_)) => return Err(Error::Unauthorized("Invalid client credentials".into())),
+            Err(e) => return Err(e),
+        };
+
+        let authenticated = match (&client.secret_hash, &creds.client_secret) {
+            (None, _) => true,
+            (Some(hash), Some(secret)) => bcrypt::verify(secret, hash)?,
+            (Some(_), None) => false,
+        };
+        if !authenticated {
+            return Err(Error::Unauthorized("Invalid client credentials".into()));
+        }
+
+        Ok(client)
+    }
+}
+
+fn parse_scopes(scope: &str) -> Vec<String> {
+    let mut scopes: Vec<String> = scope.split_whitespace().map(String::from).collect();
+    scopes.sort();
+    scopes.dedup();
+    scopes
+}
+
+// Redirects go to https, plain http is allowed for local clients only
+fn validate_redirect_uri(uri: &str) -> Result<()> {
+    let parsed = url::Url::parse(uri).map_err(|_| Error::BadRequest(format!("Invalid redirect uri {}", uri)))?;
+    if parsed.fragment().is_some() {
+        return Err(Error::BadRequest(format!("Redirect uri {} must not contain a fragment", uri)));
+    }
+
+    let local = match parsed.host_str() {
+        Some("localhost") | Some("127.0.0.1") | Some("[::1]") => true,
+        _ => false,
+    };
+    match parsed.scheme() {
+        "https" => Ok(()),
+        "http" if local => Ok(()),
+        _ => Err(Error::BadRequest(format!("Redirect uri {} must use https", uri))),
+    }
+}
+
+// RFC 7636: the challenge is BASE64URL(SHA256(verifier)) without padding
+fn verify_code_challenge(verifier: &str, challenge: &str) -> bool {
+    const UNRESERVED: &str = "-._~";
+
+    if verifier.len() < 43 || verifier.len() > 128 {
+        return false;
+    }
+    if !verifier.chars().all(|c| c.is_ascii_alphanumeric() || UNRESERVED.contains(c)) {
+        return false;
+    }
+
+    let expected = base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+    expected.as_bytes().ct_eq(challenge.as_bytes()).into()
 }
@@ -15,12 +15,25 @@ impl amqp_consumer::Consumer for EmailSender {
         let mut buf = &*delivery.data;
         let c = pb::Confirmation::decode(&mut buf)?;
 
-        let text = format!("Visit {} to confirm your email", c.url);
+        let (subject, text) = match c.kind.and_then(pb::ConfirmationKind::from_i32) {
+            Some(pb::ConfirmationKind::AcceptInvite) => (
+                "You are invited to the online store",
+                format!("Visit {} to accept the invitation", c.url),
+            ),
+            Some(pb::ConfirmationKind::OrderStatusChanged) => (
+                "Your order has been updated",
+                format!("{}\n\nVisit {} to see the order", c.text.clone().unwrap_or_default(), c.url),
+            ),
+            _ => (
+                "Confirm your email address",
+                format!("Visit {} to confirm your email", c.url),
+            ),
+        };
 
         let email = EmailBuilder::new()
             .to(c.login.clone())
             .from("noreply@sskvor.dev")
-            .subject("Confirm your email address")
+            .subject(subject)
             .text(text)
             .build()
             .map_err(|e| {
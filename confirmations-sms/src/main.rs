@@ -13,7 +13,11 @@ impl amqp_consumer::Consumer for SmsSender {
         let mut buf = &*delivery.data;
         let c = pb::Confirmation::decode(&mut buf)?;
 
-        let text = format!("Visit {} to confirm your phone", c.url);
+        let text = match c.kind.and_then(pb::ConfirmationKind::from_i32) {
+            Some(pb::ConfirmationKind::AcceptInvite) => format!("Visit {} to accept the invitation", c.url),
+            Some(pb::ConfirmationKind::OrderStatusChanged) => format!("{} {}", c.text.clone().unwrap_or_default(), c.url),
+            _ => format!("Visit {} to confirm your phone", c.url),
+        };
         // let query = format!("https://sms.ru/sms/send?api_id={}&to={}")
 
         let encoded: String = url::form_urlencoded::Serializer::new(String::new())
@@ -49,6 +49,12 @@ pub enum Error {
 
     #[error("Unauthorized: {}", .0)]
     Unauthorized(String),
+
+    #[error("Conflict: {}", .0)]
+    Conflict(String),
+
+    #[error("Rate limited: {}", .0)]
+    RateLimited(String),
 }
 
 impl From<diesel::result::Error> for Error {
@@ -67,6 +73,8 @@ impl From<Error> for tonic::Status {
             Error::BadRequest(x) => tonic::Status::invalid_argument(x),
             Error::NotFound(x) => tonic::Status::not_found(x),
             Error::Unauthorized(x) => tonic::Status::unauthenticated(x),
+            Error::Conflict(x) => tonic::Status::failed_precondition(x),
+            Error::RateLimited(x) => tonic::Status::resource_exhausted(x),
             Error::Internal(x) => tonic::Status::internal(x.to_string()),
             x => tonic::Status::internal(x.to_string())
         }
@@ -12,6 +12,8 @@ import (
 	gw "github.com/grpc-ecosystem/grpc-gateway/runtime"
 	log "github.com/sirupsen/logrus"
 	"google.golang.org/grpc"
+	"google.golang.org/grpc/codes"
+	"google.golang.org/grpc/status"
 
 	pb "github.com/BigRedEye/dc-hw/api/proto"
 	config "github.com/BigRedEye/dc-hw/gateway/config"
@@ -38,21 +40,67 @@ func initLogging(conf *config.Config) {
 	}
 }
 
+type statusOverride struct {
+	http.ResponseWriter
+	status int
+}
+
+func (w *statusOverride) WriteHeader(int) {
+	w.ResponseWriter.WriteHeader(w.status)
+}
+
+// Version conflicts come as FailedPrecondition, which is 400 by default
+func httpError(ctx context.Context, mux *gw.ServeMux, marshaler gw.Marshaler, w http.ResponseWriter, r *http.Request, err error) {
+	if s, ok := status.FromError(err); ok && s.Code() == codes.FailedPrecondition {
+		w = &statusOverride{ResponseWriter: w, status: http.StatusPreconditionFailed}
+	}
+	gw.DefaultHTTPError(ctx, mux, marshaler, w, r, err)
+}
+
 func runGrpcGateway(conf *config.Config) {
 	ctx := context.Background()
 	ctx, cancel := context.WithCancel(ctx)
 	defer cancel()
 
+	gw.HTTPError = httpError
 	mux := gw.NewServeMux()
 	opts := []grpc.DialOption{grpc.WithInsecure()}
 	err := pb.RegisterAuthHandlerFromEndpoint(ctx, mux, conf.AuthAddress, opts)
 	if err != nil {
 		log.WithError(err).Fatalln("Failed to start grpc-gateway")
 	}
+	err = pb.RegisterOauthHandlerFromEndpoint(ctx, mux, conf.AuthAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
 	err = pb.RegisterShopHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
 	if err != nil {
 		log.WithError(err).Fatalln("Failed to start grpc-gateway")
 	}
+	err = pb.RegisterInventoryHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
+	err = pb.RegisterCartHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
+	err = pb.RegisterOrdersHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
+	err = pb.RegisterPromotionsHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
+	err = pb.RegisterReviewsHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
+	err = pb.RegisterWishlistHandlerFromEndpoint(ctx, mux, conf.ShopAddress, opts)
+	if err != nil {
+		log.WithError(err).Fatalln("Failed to start grpc-gateway")
+	}
 
 	log.Println("Starting grpc-gateway server at", conf.BindAddress)
 	err = http.ListenAndServe(conf.BindAddress, mux)
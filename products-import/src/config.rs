@@ -4,6 +4,7 @@ use config;
 pub struct Settings {
     pub amqp_address: String,
     pub auth_address: String,
+    pub shop_address: String,
     pub amqp_queue: String,
     pub bind_address: std::net::SocketAddr,
     // pub tmp_storage: std::path::PathBuf,
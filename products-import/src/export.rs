@@ -0,0 +1,154 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use futures::StreamExt;
+
+use pb::shop_client::ShopClient;
+
+use crate::{config, format_price, Record, Response};
+
+#[derive(serde::Deserialize)]
+pub struct ExportParams {
+    // csv by default
+    format: Option<String>,
+    category_id: Option<i32>,
+    code: Option<String>,
+    name_prefix: Option<String>,
+    // Price bounds are amounts in this currency
+    currency: Option<String>,
+    min_price: Option<String>,
+    max_price: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Csv,
+    Jsonl,
+}
+
+pub struct ExportService {
+    config: config::Settings,
+}
+
+impl ExportService {
+    pub fn new(config: config::Settings) -> Self {
+        ExportService { config }
+    }
+
+    async fn export(&self, request: HttpRequest, params: ExportParams) -> Result<HttpResponse, Error> {
+        let format = match params.format.as_deref() {
+            None | Some("csv") => Format::Csv,
+            Some("jsonl") => Format::Jsonl,
+            Some(other) => return Ok(bad_request(format!("Unknown format {}", other))),
+        };
+        let message = match params.into_message() {
+            Ok(message) => message,
+            Err(e) => return Ok(bad_request(e)),
+        };
+
+        let mut grpc_request = tonic::Request::new(message);
+        if let Some(token) = request.headers().get("authorization").and_then(|token| token.to_str().ok()) {
+            match token.parse() {
+                Ok(token) => grpc_request.metadata_mut().insert("authorization", token),
+                Err(_) => return Ok(bad_request("Invalid authorization header".into())),
+            };
+        }
+
+        let mut client = match ShopClient::connect(format!("http://{}", self.config.shop_address)).await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to connect to the shop: {}", e.to_string());
+                return Ok(HttpResponse::BadGateway().json(Response{ status: "Shop is unavailable".into() }));
+            }
+        };
+        let products = match client.export_products(grpc_request).await {
+            Ok(response) => response.into_inner(),
+            Err(status) => return Ok(error_response(&status)),
+        };
+
+        // Errors in the middle of the export can only cut the response short
+        let mut first = true;
+        let body = products.map(move |product| {
+            let product = product.map_err(|status| actix_web::error::ErrorBadGateway(status.message().to_string()))?;
+            let line = encode(&Record::from(product), format, first)?;
+            first = false;
+            Ok::<_, Error>(web::Bytes::from(line))
+        });
+
+        let content_type = match format {
+            Format::Csv => "text/csv",
+            Format::Jsonl => "application/x-ndjson",
+        };
+        Ok(HttpResponse::Ok().content_type(content_type).streaming(body))
+    }
+}
+
+pub async fn export_products(svc: web::Data<ExportService>, request: HttpRequest, params: web::Query<ExportParams>) -> Result<HttpResponse, Error> {
+    svc.export(request, params.into_inner()).await
+}
+
+impl ExportParams {
+    fn into_message(self) -> Result<pb::ExportProductsRequest, String> {
+        let currency = self.currency;
+        let money = |amount: Option<String>| -> Result<Option<pb::Money>, String> {
+            match (amount, &currency) {
+                (Some(amount), Some(currency)) => Ok(Some(pb::Money { currency: currency.clone(), amount })),
+                (Some(_), None) => Err("Price bounds need a currency".into()),
+                (None, _) => Ok(None),
+            }
+        };
+
+        Ok(pb::ExportProductsRequest {
+            min_price: money(self.min_price)?,
+            max_price: money(self.max_price)?,
+            category_id: self.category_id,
+            code: self.code,
+            name_prefix: self.name_prefix,
+        })
+    }
+}
+
+// Stock and ratings are not exported, so importing the file back leaves
+// them as they are
+impl From<pb::Product> for Record {
+    fn from(product: pb::Product) -> Record {
+        Record {
+            uniq_id: product.code.unwrap_or_default(),
+            product_name: product.name.unwrap_or_default(),
+            amazon_category_and_sub_category: product.category.unwrap_or_default(),
+            price: product.price.as_ref().map(format_price),
+            number_available_in_stock: None,
+            product_description: product.description.filter(|description| !description.is_empty()),
+            average_review_rating: None,
+            number_of_reviews: None,
+        }
+    }
+}
+
+fn encode(record: &Record, format: Format, with_header: bool) -> Result<Vec<u8>, Error> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::WriterBuilder::new().has_headers(with_header).from_writer(Vec::new());
+            writer.serialize(record).map_err(actix_web::error::ErrorInternalServerError)?;
+            writer.into_inner().map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))
+        }
+        Format::Jsonl => {
+            let mut line = serde_json::to_vec(record).map_err(actix_web::error::ErrorInternalServerError)?;
+            line.push(b'\n');
+            Ok(line)
+        }
+    }
+}
+
+fn bad_request(status: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(Response{ status })
+}
+
+fn error_response(status: &tonic::Status) -> HttpResponse {
+    let response = Response{ status: status.message().into() };
+    match status.code() {
+        tonic::Code::InvalidArgument => HttpResponse::BadRequest().json(response),
+        tonic::Code::Unauthenticated => HttpResponse::Unauthorized().json(response),
+        tonic::Code::PermissionDenied => HttpResponse::Forbidden().json(response),
+        tonic::Code::NotFound => HttpResponse::NotFound().json(response),
+        _ => HttpResponse::BadGateway().json(response),
+    }
+}
@@ -7,6 +7,7 @@ use prost::Message;
 use tokio_amqp::*;
 
 mod config;
+mod export;
 
 #[derive(serde::Serialize)]
 struct Response {
@@ -77,10 +78,32 @@ impl BatchPublisher {
     async fn submit(&mut self, record: Record) {
         self.batch.products.push(pb::Product {
             id: None,
-            code: record.uniq_id,
-            name: record.product_name,
-            category: record.amazon_category_and_sub_category,
+            code: Some(record.uniq_id.clone()),
+            name: Some(record.product_name),
+            category: Some(record.amazon_category_and_sub_category),
+            price: record.price.as_ref().and_then(|price| parse_price(price)),
+            category_id: None,
+            description: record.product_description,
+            version: None,
+            deleted_at: None,
+            rating: None,
+            rating_count: None,
         });
+        if let Some(on_hand) = record.number_available_in_stock.as_ref().and_then(|stock| parse_stock(stock)) {
+            self.batch.stock.push(pb::StockLevel {
+                code: record.uniq_id.clone(),
+                on_hand,
+            });
+        }
+        let average = record.average_review_rating.as_ref().and_then(|rating| parse_rating(rating));
+        let count = record.number_of_reviews.as_ref().and_then(|count| parse_count(count));
+        if let (Some(average), Some(count)) = (average, count) {
+            self.batch.ratings.push(pb::RatingSeed {
+                code: record.uniq_id,
+                average,
+                count,
+            });
+        }
 
         if self.batch.products.len() >= self.batch_size {
             log::info!("Start submit");
@@ -120,6 +143,8 @@ impl BatchPublisher {
         let mut buf = Vec::with_capacity(self.batch.encoded_len());
         self.batch.encode(&mut buf).unwrap();
         self.batch.products.clear();
+        self.batch.stock.clear();
+        self.batch.ratings.clear();
 
         channel
             .basic_publish(
@@ -135,11 +160,64 @@ impl BatchPublisher {
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
+// Columns of the import file, exports use the same layout
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct Record {
     uniq_id: String,
     product_name: String,
     amazon_category_and_sub_category: String,
+    price: Option<String>,
+    number_available_in_stock: Option<String>,
+    product_description: Option<String>,
+    average_review_rating: Option<String>,
+    number_of_reviews: Option<String>,
+}
+
+// Stock looks like "5 new" or "1 used", only the number matters
+fn parse_stock(raw: &str) -> Option<i32> {
+    raw.split_whitespace().next()?.replace(',', "").parse().ok().filter(|on_hand: &i32| *on_hand >= 0)
+}
+
+// Ratings look like "4.5 out of 5 stars"
+fn parse_rating(raw: &str) -> Option<f64> {
+    raw.split_whitespace().next()?.parse().ok().filter(|rating: &f64| *rating >= 1.0 && *rating <= 5.0)
+}
+
+// Review counts look like "1,015"
+fn parse_count(raw: &str) -> Option<i32> {
+    raw.trim().replace(',', "").parse().ok().filter(|count: &i32| *count > 0)
+}
+
+const SYMBOLS: &[(&str, &str)] = &[("£", "GBP"), ("$", "USD"), ("€", "EUR"), ("₽", "RUB")];
+
+// Prices look like "£3.42" or "JPY 300", ranges and unknown currencies
+// are imported without a price. The amount is validated by the shop.
+fn parse_price(raw: &str) -> Option<pb::Money> {
+    let raw = raw.trim();
+    let (prefix, currency) = match SYMBOLS.iter().find(|(symbol, _)| raw.starts_with(symbol)) {
+        Some((symbol, currency)) => (*symbol, *currency),
+        None => {
+            let code = raw.get(..3).filter(|code| code.chars().all(|c| c.is_ascii_uppercase()))?;
+            (code, code)
+        }
+    };
+    let amount = raw[prefix.len()..].trim().replace(',', "");
+    if amount.is_empty() || !amount.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+
+    Some(pb::Money {
+        currency: currency.to_string(),
+        amount,
+    })
+}
+
+// The reverse of parse_price
+fn format_price(price: &pb::Money) -> String {
+    match SYMBOLS.iter().find(|(_, currency)| *currency == price.currency) {
+        Some((symbol, _)) => format!("{}{}", symbol, price.amount),
+        None => format!("{} {}", price.currency, price.amount),
+    }
 }
 
 impl ImportService {
@@ -193,8 +271,10 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         App::new()
             .data(ImportService::new(config.clone()))
+            .data(export::ExportService::new(config.clone()))
             .wrap(middleware::Logger::default())
             .service(web::resource("/v1/import").route(web::post().to(process_file)))
+            .service(web::resource("/v1/export").route(web::get().to(export::export_products)))
     })
     .bind(bind_address)?
     .run()
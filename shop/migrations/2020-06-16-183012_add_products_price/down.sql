@@ -0,0 +1,5 @@
+DROP INDEX products_currency_price_idx;
+
+ALTER TABLE products
+    DROP COLUMN currency,
+    DROP COLUMN price;
@@ -0,0 +1,7 @@
+-- Prices are stored in minor units of the currency, e.g. pence for GBP
+ALTER TABLE products
+    ADD COLUMN price BIGINT CHECK (price >= 0),
+    ADD COLUMN currency TEXT,
+    ADD CONSTRAINT products_price_currency_check CHECK ((price IS NULL) = (currency IS NULL));
+
+CREATE INDEX products_currency_price_idx ON products (currency, price);
@@ -0,0 +1,4 @@
+DROP TABLE reservation_items;
+DROP TABLE reservations;
+DROP TABLE stock_adjustments;
+DROP TABLE stock;
@@ -0,0 +1,35 @@
+CREATE TABLE stock (
+    product_id INTEGER PRIMARY KEY REFERENCES products (id) ON DELETE CASCADE,
+    on_hand INTEGER NOT NULL DEFAULT 0,
+    reserved INTEGER NOT NULL DEFAULT 0,
+    CHECK (reserved >= 0 AND reserved <= on_hand)
+);
+
+CREATE TABLE stock_adjustments (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    delta INTEGER NOT NULL,
+    reason TEXT NOT NULL CHECK (reason IN ('restock', 'correction', 'damaged', 'returned', 'sold', 'imported')),
+    comment TEXT,
+    created_by INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX stock_adjustments_product_id_idx ON stock_adjustments (product_id);
+
+CREATE TABLE reservations (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('active', 'released', 'committed', 'expired')),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX reservations_active_expires_at_idx ON reservations (expires_at) WHERE status = 'active';
+
+CREATE TABLE reservation_items (
+    reservation_id INTEGER NOT NULL REFERENCES reservations (id) ON DELETE CASCADE,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    PRIMARY KEY (reservation_id, product_id)
+);
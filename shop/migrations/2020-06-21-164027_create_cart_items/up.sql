@@ -0,0 +1,9 @@
+-- No foreign key on products: items of deleted products stay in the
+-- cart and are shown as stale
+CREATE TABLE cart_items (
+    user_id INTEGER NOT NULL,
+    product_id INTEGER NOT NULL,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    added_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, product_id)
+);
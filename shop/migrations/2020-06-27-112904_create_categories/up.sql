@@ -0,0 +1,42 @@
+CREATE TABLE categories (
+    id SERIAL PRIMARY KEY,
+    parent_id INTEGER REFERENCES categories (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Full path like "Hobbies > Model Trains", descendants share its prefix
+    path TEXT NOT NULL UNIQUE
+);
+
+CREATE INDEX categories_parent_id_idx ON categories (parent_id);
+CREATE INDEX categories_path_prefix_idx ON categories (path text_pattern_ops);
+
+ALTER TABLE products ADD COLUMN category_id INTEGER REFERENCES categories (id) ON DELETE SET NULL;
+
+CREATE INDEX products_category_id_idx ON products (category_id);
+
+-- Every prefix of every product category becomes a node
+CREATE TEMPORARY TABLE category_paths AS
+SELECT DISTINCT
+    array_to_string(segments[1:i], ' > ') AS path,
+    array_to_string(segments[1:i - 1], ' > ') AS parent_path,
+    segments[i] AS name
+FROM (
+    SELECT array_remove(regexp_split_to_array(trim(category), '\s*>\s*'), '') AS segments
+    FROM products
+) AS split,
+generate_series(1, coalesce(array_length(segments, 1), 0)) AS i;
+
+INSERT INTO categories (name, path)
+SELECT name, path FROM category_paths;
+
+UPDATE categories
+SET parent_id = parent.id
+FROM category_paths, categories AS parent
+WHERE category_paths.path = categories.path
+    AND parent.path = category_paths.parent_path;
+
+UPDATE products
+SET category_id = categories.id
+FROM categories
+WHERE categories.path = array_to_string(array_remove(regexp_split_to_array(trim(products.category), '\s*>\s*'), ''), ' > ');
+
+DROP TABLE category_paths;
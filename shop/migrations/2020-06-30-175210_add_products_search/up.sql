@@ -0,0 +1,10 @@
+ALTER TABLE products ADD COLUMN description TEXT NOT NULL DEFAULT '';
+
+-- Not listed in src/schema.rs, only used by raw search queries
+ALTER TABLE products ADD COLUMN search_vector tsvector GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', category), 'B') ||
+    setweight(to_tsvector('english', description), 'C')
+) STORED;
+
+CREATE INDEX products_search_vector_idx ON products USING GIN (search_vector);
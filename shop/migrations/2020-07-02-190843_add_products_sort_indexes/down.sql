@@ -0,0 +1,4 @@
+DROP INDEX products_lower_name_idx;
+DROP INDEX products_price_id_idx;
+DROP INDEX products_code_id_idx;
+DROP INDEX products_name_id_idx;
@@ -0,0 +1,7 @@
+-- Keyset pagination sorts by (key, id)
+CREATE INDEX products_name_id_idx ON products (name, id);
+CREATE INDEX products_code_id_idx ON products (code, id);
+CREATE INDEX products_price_id_idx ON products (price, id);
+
+-- Case insensitive name prefix filter
+CREATE INDEX products_lower_name_idx ON products (lower(name) text_pattern_ops);
@@ -0,0 +1,3 @@
+CREATE INDEX products_code_id_idx ON products (code, id);
+
+ALTER TABLE products DROP CONSTRAINT products_code_key;
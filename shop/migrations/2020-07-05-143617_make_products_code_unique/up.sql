@@ -0,0 +1,54 @@
+-- Repeated imports created copies of the same products, the oldest copy
+-- of every code is kept and takes over the stock, reservations and carts
+-- of the others. Order items are snapshots and keep the old ids.
+CREATE TEMPORARY TABLE product_duplicates ON COMMIT DROP AS
+SELECT id, keep_id
+FROM (
+    SELECT id, min(id) OVER (PARTITION BY code) AS keep_id
+    FROM products
+) ranked
+WHERE id <> keep_id;
+
+UPDATE stock_adjustments a
+SET product_id = d.keep_id
+FROM product_duplicates d
+WHERE a.product_id = d.id;
+
+INSERT INTO stock (product_id, on_hand, reserved)
+SELECT d.keep_id, sum(s.on_hand), sum(s.reserved)
+FROM stock s
+JOIN product_duplicates d ON d.id = s.product_id
+GROUP BY d.keep_id
+ON CONFLICT (product_id) DO UPDATE
+SET on_hand = stock.on_hand + excluded.on_hand,
+    reserved = stock.reserved + excluded.reserved;
+
+INSERT INTO reservation_items (reservation_id, product_id, quantity)
+SELECT r.reservation_id, d.keep_id, sum(r.quantity)
+FROM reservation_items r
+JOIN product_duplicates d ON d.id = r.product_id
+GROUP BY r.reservation_id, d.keep_id
+ON CONFLICT (reservation_id, product_id) DO UPDATE
+SET quantity = reservation_items.quantity + excluded.quantity;
+
+INSERT INTO cart_items (user_id, product_id, quantity, added_at)
+SELECT c.user_id, d.keep_id, sum(c.quantity), min(c.added_at)
+FROM cart_items c
+JOIN product_duplicates d ON d.id = c.product_id
+GROUP BY c.user_id, d.keep_id
+ON CONFLICT (user_id, product_id) DO UPDATE
+SET quantity = cart_items.quantity + excluded.quantity;
+
+DELETE FROM cart_items c
+USING product_duplicates d
+WHERE c.product_id = d.id;
+
+-- Cascades to the merged stock and reservation items
+DELETE FROM products p
+USING product_duplicates d
+WHERE p.id = d.id;
+
+ALTER TABLE products ADD CONSTRAINT products_code_key UNIQUE (code);
+
+-- The unique index serves keyset pagination by code
+DROP INDEX products_code_id_idx;
@@ -0,0 +1,3 @@
+DROP INDEX products_deleted_at_idx;
+
+ALTER TABLE products DROP COLUMN deleted_at;
@@ -0,0 +1,14 @@
+CREATE TABLE product_revisions (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    action TEXT NOT NULL CHECK (action IN ('created', 'updated', 'deleted', 'restored', 'reverted')),
+    source TEXT NOT NULL CHECK (source IN ('api', 'import')),
+    -- Not set for imports
+    user_id INTEGER,
+    -- Snapshots of the product fields, before is not set for created products
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX product_revisions_product_id_idx ON product_revisions (product_id, id);
@@ -0,0 +1,7 @@
+CREATE TABLE outbox_events (
+    id bigserial PRIMARY KEY,
+    exchange text NOT NULL,
+    routing_key text NOT NULL,
+    payload bytea NOT NULL,
+    created_at timestamp NOT NULL DEFAULT now()
+);
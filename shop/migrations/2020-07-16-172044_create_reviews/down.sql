@@ -0,0 +1,5 @@
+ALTER TABLE products
+    DROP COLUMN rating_sum,
+    DROP COLUMN rating_count;
+
+DROP TABLE reviews;
@@ -0,0 +1,21 @@
+-- One review per user and product, only approved reviews count towards
+-- the rating of the product
+CREATE TABLE reviews (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    rating INTEGER NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    text TEXT NOT NULL DEFAULT '',
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'hidden')),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (product_id, user_id)
+);
+
+CREATE INDEX reviews_product_id_status_idx ON reviews (product_id, status, id);
+CREATE INDEX reviews_status_idx ON reviews (status, id);
+
+-- Sum and count of approved ratings plus the ones seeded by imports
+ALTER TABLE products
+    ADD COLUMN rating_sum DOUBLE PRECISION NOT NULL DEFAULT 0,
+    ADD COLUMN rating_count INTEGER NOT NULL DEFAULT 0;
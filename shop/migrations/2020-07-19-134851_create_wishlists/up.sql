@@ -0,0 +1,18 @@
+CREATE TABLE wishlists (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL CHECK (name <> ''),
+    -- Set while the list is shared, anyone with the token can read it
+    share_token TEXT UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (user_id, name)
+);
+
+-- No foreign key on products: items of deleted products stay on the
+-- list and are shown as stale
+CREATE TABLE wishlist_items (
+    wishlist_id INTEGER NOT NULL REFERENCES wishlists (id) ON DELETE CASCADE,
+    product_id INTEGER NOT NULL,
+    added_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (wishlist_id, product_id)
+);
@@ -0,0 +1,28 @@
+-- Percent discounts take value percent off, amount ones take value minor
+-- units of the currency off every unit of matching products, or once off
+-- the cart subtotal
+CREATE TABLE promotions (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('percent', 'amount')),
+    value BIGINT NOT NULL CHECK (value > 0),
+    -- Required for amount discounts and subtotal thresholds, percent
+    -- discounts without it apply to any currency
+    currency TEXT,
+    scope TEXT NOT NULL CHECK (scope IN ('category', 'products', 'cart')),
+    category_id INTEGER REFERENCES categories (id) ON DELETE CASCADE,
+    product_ids INTEGER[] NOT NULL DEFAULT '{}',
+    min_subtotal BIGINT NOT NULL DEFAULT 0 CHECK (min_subtotal >= 0),
+    -- Upper case, promotions with a coupon only apply when it is given
+    coupon_code TEXT UNIQUE,
+    starts_at TIMESTAMP,
+    ends_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now(),
+    CHECK (kind <> 'percent' OR value <= 100),
+    CHECK (kind <> 'amount' OR currency IS NOT NULL),
+    CHECK (scope <> 'category' OR category_id IS NOT NULL),
+    CHECK (starts_at IS NULL OR ends_at IS NULL OR starts_at < ends_at)
+);
+
+CREATE INDEX promotions_ends_at_idx ON promotions (ends_at);
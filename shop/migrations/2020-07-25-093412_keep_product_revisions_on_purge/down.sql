@@ -0,0 +1,13 @@
+DELETE FROM product_revisions r
+WHERE NOT EXISTS (SELECT 1 FROM products p WHERE p.id = r.product_id);
+
+ALTER TABLE product_revisions DROP CONSTRAINT product_revisions_source_check;
+ALTER TABLE product_revisions ADD CONSTRAINT product_revisions_source_check
+    CHECK (source IN ('api', 'import'));
+
+ALTER TABLE product_revisions DROP CONSTRAINT product_revisions_action_check;
+ALTER TABLE product_revisions ADD CONSTRAINT product_revisions_action_check
+    CHECK (action IN ('created', 'updated', 'deleted', 'restored', 'reverted'));
+
+ALTER TABLE product_revisions ADD CONSTRAINT product_revisions_product_id_fkey
+    FOREIGN KEY (product_id) REFERENCES products (id) ON DELETE CASCADE;
@@ -0,0 +1,11 @@
+-- Revisions outlive purged products, the last one records the purge
+ALTER TABLE product_revisions DROP CONSTRAINT product_revisions_product_id_fkey;
+
+ALTER TABLE product_revisions DROP CONSTRAINT product_revisions_action_check;
+ALTER TABLE product_revisions ADD CONSTRAINT product_revisions_action_check
+    CHECK (action IN ('created', 'updated', 'deleted', 'restored', 'reverted', 'purged'));
+
+-- Products are purged by the sweeper once their retention is over
+ALTER TABLE product_revisions DROP CONSTRAINT product_revisions_source_check;
+ALTER TABLE product_revisions ADD CONSTRAINT product_revisions_source_check
+    CHECK (source IN ('api', 'import', 'sweeper'));
@@ -0,0 +1,3 @@
+CREATE INDEX products_price_id_idx ON products (price, id);
+
+DROP INDEX products_currency_price_id_idx;
@@ -0,0 +1,4 @@
+-- Price orders group products by currency
+CREATE INDEX products_currency_price_id_idx ON products (currency, price, id);
+
+DROP INDEX products_price_id_idx;
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use errors::prelude::*;
+
+// Minimal caller of an RPC
+#[derive(Clone, Copy, PartialEq, Debug, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Anonymous,
+    User,
+    Admin,
+}
+
+// Methods checked by the access policy, by the names the server passes
+// to Service::auth
+pub const METHODS: &[&str] = &[
+    "add_item",
+    "add_product",
+    "add_review",
+    "add_to_wishlist",
+    "adjust_stock",
+    "batch_delete_products",
+    "batch_get_products",
+    "batch_update_products",
+    "checkout",
+    "clear_cart",
+    "commit_reservation",
+    "create_promotion",
+    "create_wishlist",
+    "delete_my_review",
+    "delete_product",
+    "delete_promotion",
+    "delete_wishlist",
+    "evaluate_cart",
+    "export_products",
+    "get_cart",
+    "get_category_tree",
+    "get_order",
+    "get_product",
+    "get_product_history",
+    "get_promotion",
+    "get_shared_wishlist",
+    "get_stock",
+    "get_wishlist",
+    "list_categories",
+    "list_deleted_products",
+    "list_my_orders",
+    "list_orders",
+    "list_products",
+    "list_promotions",
+    "list_reviews",
+    "list_wishlists",
+    "moderate_review",
+    "purge_product",
+    "release_stock",
+    "remove_from_wishlist",
+    "remove_item",
+    "reserve_stock",
+    "restore_product",
+    "revert_product",
+    "search_products",
+    "share_wishlist",
+    "unshare_wishlist",
+    "update_order_status",
+    "update_product",
+    "update_promotion",
+    "update_quantity",
+];
+
+// What a request brings to identify its caller
+pub struct Credentials {
+    pub token: Option<String>,
+    // Address of the connection, usually the gateway
+    pub peer: Option<IpAddr>,
+    pub forwarded_for: Option<String>,
+}
+
+impl Credentials {
+    // Address of the client. The gateway forwards it as the last entry of
+    // x-forwarded-for, the header is ignored from anyone else as clients
+    // connecting directly could pick any address.
+    pub fn client(&self, trusted_proxies: &[IpAddr]) -> String {
+        let peer = match self.peer {
+            Some(peer) => peer,
+            None => return "unknown".into(),
+        };
+        if !trusted_proxies.contains(&peer) {
+            return peer.to_string();
+        }
+
+        self.forwarded_for
+            .as_ref()
+            .and_then(|value| value.rsplit(',').map(str::trim).find(|address| !address.is_empty()))
+            .map(String::from)
+            .unwrap_or_else(|| peer.to_string())
+    }
+}
+
+pub enum Caller {
+    // Client is the address the request came from
+    Anonymous { client: String },
+    User(auth_client::Identity),
+}
+
+impl Caller {
+    pub fn assert_role(&self, minimal_role: auth_client::Role) -> Result<()> {
+        let identity = match self {
+            Caller::User(identity) => identity,
+            Caller::Anonymous { .. } => return Err(errors::Error::Unauthorized("Permission denied".into())),
+        };
+        let scope = match minimal_role {
+            auth_client::Role::Admin => "shop:admin",
+            auth_client::Role::User => "shop",
+        };
+        if identity.role < minimal_role || !identity.has_scope(scope) {
+            return Err(errors::Error::Unauthorized("Permission denied".into()))
+        };
+        Ok(())
+    }
+
+    pub fn assert_access(&self, access: Access) -> Result<()> {
+        match access {
+            Access::Anonymous => Ok(()),
+            Access::User => self.assert_role(auth_client::Role::User),
+            Access::Admin => self.assert_role(auth_client::Role::Admin),
+        }
+    }
+}
+
+// Tag of the caller in logs
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Caller::Anonymous { client } => write!(f, "anonymous@{}", client),
+            Caller::User(identity) => write!(f, "user:{}", identity.user_id),
+        }
+    }
+}
+
+// Most clients tracked at once. Once reached, refilled buckets are
+// dropped as they are no different from new ones, then the clients seen
+// least recently, until EVICT_BATCH slots are free. Evicting in batches
+// keeps the scans rare while spoofed clients keep coming.
+const MAX_BUCKETS: usize = 10_000;
+const EVICT_BATCH: usize = MAX_BUCKETS / 10;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token bucket per client refilled at rate requests per second and holding
+// at most burst requests
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32, burst: u32) -> Self {
+        RateLimiter {
+            rate: f64::from(rate),
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes a token of the client, false if there are none left
+    pub fn acquire(&self, client: &str) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(client) {
+            self.evict(&mut buckets, now);
+        }
+
+        let bucket = buckets
+            .entry(client.to_string())
+            .or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+
+        let keep = MAX_BUCKETS - EVICT_BATCH;
+        if buckets.len() > keep {
+            let mut by_age: Vec<(Instant, String)> = buckets
+                .iter()
+                .map(|(client, bucket)| (bucket.updated, client.clone()))
+                .collect();
+            by_age.sort();
+            let excess = buckets.len() - keep;
+            for (_, client) in by_age.into_iter().take(excess) {
+                buckets.remove(&client);
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::models;
+use crate::search;
+
+pub const SEPARATOR: &str = " > ";
+
+// "Hobbies > Model Trains > Rolling Stock" -> ["Hobbies", "Model Trains", "Rolling Stock"]
+pub fn split(raw: &str) -> Vec<String> {
+    raw.split('>')
+        .map(|segment| segment.trim())
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub fn join(segments: &[String]) -> String {
+    segments.join(SEPARATOR)
+}
+
+// LIKE pattern matching all descendants of the path
+pub fn descendants_pattern(path: &str) -> String {
+    format!("{}{}%", search::escape_like(path), SEPARATOR)
+}
+
+// Whether the path is the root itself or one of its descendants
+pub fn is_within(path: &str, root: &str) -> bool {
+    path == root || (path.starts_with(root) && path[root.len()..].starts_with(SEPARATOR))
+}
+
+// Builds the subtrees under the root, categories must contain the whole
+// subtree. Children are sorted by name.
+pub fn build_tree(categories: Vec<models::Category>, root: Option<i32>) -> Vec<models::CategoryTree> {
+    let mut children: HashMap<Option<i32>, Vec<models::Category>> = HashMap::new();
+    for category in categories {
+        children.entry(category.parent_id).or_insert_with(Vec::new).push(category);
+    }
+
+    build_subtrees(&mut children, root)
+}
+
+fn build_subtrees(children: &mut HashMap<Option<i32>, Vec<models::Category>>, parent: Option<i32>) -> Vec<models::CategoryTree> {
+    let mut nodes = children.remove(&parent).unwrap_or_default();
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    nodes
+        .into_iter()
+        .map(|category| {
+            let subtrees = build_subtrees(children, Some(category.id));
+            models::CategoryTree { category, children: subtrees }
+        })
+        .collect()
+}
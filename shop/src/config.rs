@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
 use config;
 
+use crate::access::{self, Access};
+
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Settings {
     pub database_url: String,
@@ -7,12 +12,85 @@ pub struct Settings {
     pub auth_address: String,
     pub amqp_address: String,
     pub amqp_queue: String,
+    // Seconds
+    pub reservation_ttl: u32,
+    // Seconds between expired reservations sweeps
+    pub reservation_sweep_interval: u64,
+    // Page size of list calls without a limit
+    pub default_page_size: i64,
+    pub max_page_size: i64,
+    // Products in one batch call
+    pub max_batch_size: usize,
+    // Units of one product in a cart, small enough that adding to a full
+    // item can't overflow
+    pub max_cart_quantity: u16,
+    // Seconds before deleted products are purged
+    pub product_retention: u64,
+    pub outbox_batch_size: i64,
+    // Milliseconds
+    pub outbox_poll_interval: u64,
+    // Minimal caller per RPC by method name, e.g.
+    // SHOP_ACCESS__LIST_PRODUCTS=user. Methods without an entry need a
+    // user, methods which need the caller's identity or admin rights
+    // check them anyway. Unknown method names fail the startup.
+    pub access: HashMap<String, Access>,
+    // Requests per second of one anonymous client
+    pub anonymous_rate_limit: u32,
+    pub anonymous_burst: u32,
+    // Comma separated addresses of the gateways, x-forwarded-for is
+    // trusted only from them
+    #[serde(deserialize_with = "comma_separated")]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+fn comma_separated<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<IpAddr>, D::Error> {
+    let addresses: String = serde::Deserialize::deserialize(deserializer)?;
+    addresses
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(|address| address.parse().map_err(serde::de::Error::custom))
+        .collect()
 }
 
+// Read-only catalog methods served without a token by default
+const ANONYMOUS_METHODS: &[&str] = &[
+    "get_product",
+    "list_products",
+    "search_products",
+    "list_categories",
+    "get_category_tree",
+    "list_reviews",
+    "get_shared_wishlist",
+];
+
 impl Settings {
     pub fn new() -> Result<Self, config::ConfigError> {
         let mut s = config::Config::new();
-        s.merge(config::Environment::with_prefix("shop"))?;
-        s.try_into()
+        s.set_default("reservation_ttl", 15i64 * 60)?;
+        s.set_default("reservation_sweep_interval", 60i64)?;
+        s.set_default("default_page_size", 50i64)?;
+        s.set_default("max_page_size", 1000i64)?;
+        s.set_default("max_batch_size", 500i64)?;
+        s.set_default("max_cart_quantity", 1000i64)?;
+        s.set_default("product_retention", 30i64 * 24 * 60 * 60)?;
+        s.set_default("outbox_batch_size", 100i64)?;
+        s.set_default("outbox_poll_interval", 500i64)?;
+        for method in ANONYMOUS_METHODS {
+            s.set_default(&format!("access.{}", method), "anonymous")?;
+        }
+        s.set_default("anonymous_rate_limit", 10i64)?;
+        s.set_default("anonymous_burst", 50i64)?;
+        s.set_default("trusted_proxies", "")?;
+        s.merge(config::Environment::with_prefix("shop").separator("__"))?;
+
+        let settings: Settings = s.try_into()?;
+        // A typo would silently fall back to the default policy
+        for method in settings.access.keys() {
+            if !access::METHODS.contains(&method.as_str()) {
+                return Err(config::ConfigError::Message(format!("Access policy for unknown method {}", method)));
+            }
+        }
+        Ok(settings)
     }
 }
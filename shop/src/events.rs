@@ -0,0 +1,43 @@
+use std::time::SystemTime;
+
+use diesel::PgConnection;
+use errors::prelude::*;
+use crate::models;
+use crate::proto_convert;
+
+pub use pb::product_event::Event;
+
+pub const EXCHANGE: &str = "shop.products";
+
+fn routing_key(event: &Event) -> &'static str {
+    match event {
+        Event::ProductCreated(_) => "product.created",
+        Event::ProductUpdated(_) => "product.updated",
+        Event::ProductDeleted(_) => "product.deleted",
+    }
+}
+
+// Restored products reappear in the catalog, so consumers see them as
+// created
+pub fn product_changed(action: models::RevisionAction, product: &models::Product) -> Event {
+    use models::RevisionAction::*;
+
+    match action {
+        Created | Restored => Event::ProductCreated(pb::ProductCreated { product: Some(product.clone().into()) }),
+        Updated | Reverted => Event::ProductUpdated(pb::ProductUpdated { product: Some(product.clone().into()) }),
+        Deleted | Purged => Event::ProductDeleted(pb::ProductDeleted { product_id: product.id, version: product.version }),
+    }
+}
+
+// Must be called in the transaction making the change, the outbox relay
+// publishes the event after commit. There is one relay per database and
+// it publishes in the order of the outbox, so the events of a product
+// keep the order of its changes.
+pub fn emit(connection: &PgConnection, event: Event) -> Result<()> {
+    let key = routing_key(&event);
+    let message = pb::ProductEvent {
+        created_at: proto_convert::unix_seconds(SystemTime::now()),
+        event: Some(event),
+    };
+    amqp_outbox::enqueue(connection, EXCHANGE, key, &message)
+}
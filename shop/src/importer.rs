@@ -1,4 +1,8 @@
+use std::convert::TryFrom;
+
 use crate::config;
+use crate::models;
+use crate::money::Money;
 use crate::repo;
 use prost::Message;
 use errors::prelude::*;
@@ -40,7 +44,60 @@ impl amqp_consumer::Consumer for ImporterHandler {
         let batch = pb::ProductsBatch::decode(&mut buf)?;
         log::info!("Start loading batch with {} products", batch.products.len());
 
-        let _ = self.repo.add_products(&batch.products.into_iter().map(|e| e.into()).collect::<Vec<_>>());
+        let mut products: Vec<models::NewProduct> = batch.products
+            .into_iter()
+            .filter_map(|mut product| {
+                let code = product.code.clone().unwrap_or_default();
+                // Like unparseable prices, prices the shop rejects are dropped
+                // and the product is imported without one
+                if let Some(price) = product.price.clone() {
+                    if let Err(e) = Money::try_from(price.clone()) {
+                        log::warn!("Importing product {} without price {} {}: {}", code, price.currency, price.amount, e.to_string());
+                        product.price = None;
+                    }
+                }
+                match models::NewProduct::try_from(product) {
+                    Ok(product) => Some(product),
+                    Err(e) => {
+                        log::warn!("Skipping product {}: {}", code, e.to_string());
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        // Products keep the raw category text and link to the parsed path
+        let raw_categories: Vec<String> = products.iter().map(|product| product.category.clone()).collect();
+        match self.repo.ensure_categories(&raw_categories) {
+            Ok(ids) => {
+                for product in products.iter_mut() {
+                    product.category_id = ids.get(&product.category).cloned();
+                }
+            }
+            Err(e) => log::error!("Failed to import categories: {}", e.to_string()),
+        }
+        match self.repo.upsert_products(&products, models::Actor::import()) {
+            Ok(stats) => {
+                log::info!(
+                    "Imported products: {} inserted, {} updated, {} unchanged, {} in the trash",
+                    stats.inserted, stats.updated, stats.unchanged, stats.trashed.len(),
+                );
+                if !stats.trashed.is_empty() {
+                    log::warn!("Skipped deleted products, restore them to import: {}", stats.trashed.join(", "));
+                }
+            }
+            Err(e) => log::error!("Failed to import products: {}", e.to_string()),
+        }
+
+        let seeds: Vec<models::RatingSeed> = batch.ratings.into_iter().map(|seed| seed.into()).collect();
+        if let Err(e) = self.repo.seed_ratings(&seeds) {
+            log::error!("Failed to import ratings: {}", e.to_string());
+        }
+
+        let levels: Vec<models::StockLevel> = batch.stock.into_iter().map(|level| level.into()).collect();
+        if let Err(e) = self.repo.set_stock_levels(&levels) {
+            log::error!("Failed to import stock levels: {}", e.to_string());
+        }
 
         log::info!("Finish loading batch");
 
@@ -5,16 +5,31 @@ extern crate diesel_migrations;
 
 use log::info;
 use tonic::transport::Server;
+use pb::cart_server::CartServer;
+use pb::inventory_server::InventoryServer;
+use pb::orders_server::OrdersServer;
+use pb::promotions_server::PromotionsServer;
+use pb::reviews_server::ReviewsServer;
 use pb::shop_server::ShopServer;
+use pb::wishlist_server::WishlistServer;
 
+mod access;
+mod categories;
 mod config;
+mod events;
 mod importer;
 mod models;
+mod money;
+mod notifications;
+mod paging;
+mod promotions;
 mod proto_convert;
 mod repo;
 mod schema;
+mod search;
 mod server;
 mod service;
+mod sweeper;
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
@@ -24,9 +39,22 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let cfg = config::Settings::new().expect("Failed to parse config");
     let auth = auth_client::client::Client::new(&cfg.auth_address)?;
     let repo = repo::PgRepo::new(&cfg).expect("Failed to initialize repo");
-    let service = service::Service::new(repo.clone(), auth);
+    let service = service::Service::new(&cfg, repo.clone(), auth);
     let server = server::Server::new(service);
-    let srv = Server::builder().add_service(ShopServer::new(server));
+    let srv = Server::builder()
+        .add_service(ShopServer::new(server.clone()))
+        .add_service(InventoryServer::new(server.clone()))
+        .add_service(CartServer::new(server.clone()))
+        .add_service(OrdersServer::new(server.clone()))
+        .add_service(PromotionsServer::new(server.clone()))
+        .add_service(ReviewsServer::new(server.clone()))
+        .add_service(WishlistServer::new(server));
+
+    let sweeper = sweeper::Sweeper::new(repo.clone(), &cfg);
+    tokio::spawn(async move { sweeper.run().await });
+
+    let mut relay = repo.outbox_relay(&cfg);
+    tokio::spawn(async move { relay.run().await });
 
     let bind_address = cfg.bind_address;
     let importer = tokio::task::spawn_blocking(|| {
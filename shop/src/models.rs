@@ -1,13 +1,46 @@
+use std::time::SystemTime;
+
 use serde::{Deserialize, Serialize};
 
-use crate::schema::products;
+use crate::money::Money;
+use crate::paging::PageToken;
+use crate::schema::{cart_items, categories, order_items, orders, product_revisions, products, promotions, reservation_items, reservations, reviews, stock, stock_adjustments, wishlist_items, wishlists};
 
-#[derive(Serialize, Queryable, Insertable, AsChangeset)]
+#[derive(Clone, Serialize, Queryable, QueryableByName, Insertable)]
+#[table_name = "products"]
 pub struct Product {
     pub id: i32,
     pub name: String,
     pub code: String,
     pub category: String,
+    pub price: Option<i64>,
+    pub currency: Option<String>,
+    pub category_id: Option<i32>,
+    pub description: String,
+    // Bumped on every change
+    pub version: i32,
+    // Set for products in the trash
+    pub deleted_at: Option<SystemTime>,
+    // Approved reviews and imported ratings
+    pub rating_sum: f64,
+    pub rating_count: i32,
+}
+
+impl Product {
+    pub fn price(&self) -> Option<Money> {
+        match (self.price, &self.currency) {
+            (Some(amount), Some(currency)) => Some(Money { amount, currency: currency.clone() }),
+            _ => None,
+        }
+    }
+
+    // Average rating, None without ratings
+    pub fn rating(&self) -> Option<f64> {
+        match self.rating_count {
+            0 => None,
+            count => Some(self.rating_sum / f64::from(count)),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Insertable)]
@@ -16,10 +49,787 @@ pub struct NewProduct {
     pub name: String,
     pub code: String,
     pub category: String,
+    pub price: Option<i64>,
+    pub currency: Option<String>,
+    pub category_id: Option<i32>,
+    pub description: String,
+}
+
+impl NewProduct {
+    pub fn set_price(&mut self, price: Option<Money>) {
+        match price {
+            Some(price) => {
+                self.price = Some(price.amount);
+                self.currency = Some(price.currency);
+            }
+            None => {
+                self.price = None;
+                self.currency = None;
+            }
+        }
+    }
+}
+
+pub const PRODUCT_UPDATE_FIELDS: &[&str] = &["name", "code", "category", "price", "description"];
+
+// Fields set to None are left as they are
+#[derive(Default, AsChangeset)]
+#[table_name = "products"]
+pub struct ProductChanges {
+    pub name: Option<String>,
+    pub code: Option<String>,
+    pub category: Option<String>,
+    pub price: Option<Option<i64>>,
+    pub currency: Option<Option<String>>,
+    pub category_id: Option<Option<i32>>,
+    pub description: Option<String>,
+}
+
+impl ProductChanges {
+    pub fn set_price(&mut self, price: Option<Money>) {
+        match price {
+            Some(price) => {
+                self.price = Some(Some(price.amount));
+                self.currency = Some(Some(price.currency));
+            }
+            None => {
+                self.price = Some(None);
+                self.currency = Some(None);
+            }
+        }
+    }
+}
+
+// Editable fields of a product, stored in revisions
+#[derive(Serialize, Deserialize)]
+pub struct ProductSnapshot {
+    pub name: String,
+    pub code: String,
+    pub category: String,
+    pub price: Option<i64>,
+    pub currency: Option<String>,
+    pub category_id: Option<i32>,
+    pub description: String,
+}
+
+impl From<&Product> for ProductSnapshot {
+    fn from(product: &Product) -> ProductSnapshot {
+        ProductSnapshot {
+            name: product.name.clone(),
+            code: product.code.clone(),
+            category: product.category.clone(),
+            price: product.price,
+            currency: product.currency.clone(),
+            category_id: product.category_id,
+            description: product.description.clone(),
+        }
+    }
+}
+
+impl From<ProductSnapshot> for ProductChanges {
+    fn from(snapshot: ProductSnapshot) -> ProductChanges {
+        ProductChanges {
+            name: Some(snapshot.name),
+            code: Some(snapshot.code),
+            category: Some(snapshot.category),
+            price: Some(snapshot.price),
+            currency: Some(snapshot.currency),
+            category_id: Some(snapshot.category_id),
+            description: Some(snapshot.description),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RevisionAction {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    Reverted,
+    Purged,
+}
+
+impl RevisionAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RevisionAction::Created => "created",
+            RevisionAction::Updated => "updated",
+            RevisionAction::Deleted => "deleted",
+            RevisionAction::Restored => "restored",
+            RevisionAction::Reverted => "reverted",
+            RevisionAction::Purged => "purged",
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<RevisionAction> {
+        match action {
+            "created" => Some(RevisionAction::Created),
+            "updated" => Some(RevisionAction::Updated),
+            "deleted" => Some(RevisionAction::Deleted),
+            "restored" => Some(RevisionAction::Restored),
+            "reverted" => Some(RevisionAction::Reverted),
+            "purged" => Some(RevisionAction::Purged),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RevisionSource {
+    Api,
+    Import,
+    Sweeper,
+}
+
+impl RevisionSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RevisionSource::Api => "api",
+            RevisionSource::Import => "import",
+            RevisionSource::Sweeper => "sweeper",
+        }
+    }
+
+    pub fn parse(source: &str) -> Option<RevisionSource> {
+        match source {
+            "api" => Some(RevisionSource::Api),
+            "import" => Some(RevisionSource::Import),
+            "sweeper" => Some(RevisionSource::Sweeper),
+            _ => None,
+        }
+    }
+}
+
+// Who made a change to a product
+#[derive(Clone, Copy)]
+pub struct Actor {
+    pub source: RevisionSource,
+    pub user_id: Option<i32>,
+}
+
+impl Actor {
+    pub fn user(user_id: i32) -> Actor {
+        Actor { source: RevisionSource::Api, user_id: Some(user_id) }
+    }
+
+    pub fn import() -> Actor {
+        Actor { source: RevisionSource::Import, user_id: None }
+    }
+
+    pub fn sweeper() -> Actor {
+        Actor { source: RevisionSource::Sweeper, user_id: None }
+    }
+}
+
+#[derive(Queryable)]
+pub struct ProductRevision {
+    pub id: i32,
+    pub product_id: i32,
+    pub action: String,
+    pub source: String,
+    pub user_id: Option<i32>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "product_revisions"]
+pub struct NewProductRevision {
+    pub product_id: i32,
+    pub action: String,
+    pub source: String,
+    pub user_id: Option<i32>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+pub struct ProductUpdate {
+    pub id: i32,
+    pub expected_version: Option<i32>,
+    pub changes: ProductChanges,
+}
+
+// Outcome for one product of a batch call, deletes have no product
+pub struct BatchResult {
+    pub id: i32,
+    pub result: Result<Option<Product>, errors::Error>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ProductOrder {
+    Id,
+    IdDesc,
+    PriceAsc,
+    PriceDesc,
+    NameAsc,
+    NameDesc,
+    CodeAsc,
+    CodeDesc,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CountMode {
+    Exact,
+    // Planner statistics for the whole catalog, exact when filtered
+    Estimated,
+    Skip,
 }
 
-#[derive(Deserialize)]
 pub struct ListQuery {
     pub limit: Option<i64>,
     pub offset: Option<i64>,
+    pub after: Option<PageToken>,
+    pub order: ProductOrder,
+    pub count: CountMode,
+    pub min_price: Option<Money>,
+    pub max_price: Option<Money>,
+    pub code: Option<String>,
+    pub name_prefix: Option<String>,
+    pub category_id: Option<i32>,
+    // Resolved by the service, matches the category and its descendants
+    pub category_path: Option<String>,
+}
+
+impl ListQuery {
+    pub fn is_filtered(&self) -> bool {
+        self.min_price.is_some()
+            || self.max_price.is_some()
+            || self.code.is_some()
+            || self.name_prefix.is_some()
+            || self.category_path.is_some()
+    }
+}
+
+pub struct ProductsPage {
+    pub count: Option<i64>,
+    pub products: Vec<Product>,
+    pub next_page_token: Option<String>,
+}
+
+pub struct SearchQuery {
+    pub query: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub min_price: Option<Money>,
+    pub max_price: Option<Money>,
+    pub category_id: Option<i32>,
+    pub category_path: Option<String>,
+}
+
+#[derive(QueryableByName)]
+pub struct SearchHit {
+    #[diesel(embed)]
+    pub product: Product,
+    #[sql_type = "diesel::sql_types::Float4"]
+    pub rank: f32,
+    // Name and description fragments with matches wrapped in <b></b>
+    #[sql_type = "diesel::sql_types::Text"]
+    pub snippet: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub total: i64,
+}
+
+#[derive(QueryableByName)]
+pub struct UpsertedProduct {
+    #[diesel(embed)]
+    pub product: Product,
+    #[sql_type = "diesel::sql_types::Bool"]
+    pub inserted: bool,
+}
+
+pub struct ImportStats {
+    pub inserted: i64,
+    pub updated: i64,
+    pub unchanged: i64,
+    // Codes of deleted products, imports do not touch them
+    pub trashed: Vec<String>,
+}
+
+#[derive(QueryableByName)]
+pub struct Count {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+}
+
+#[derive(Queryable, Insertable)]
+#[table_name = "stock"]
+pub struct Stock {
+    pub product_id: i32,
+    pub on_hand: i32,
+    pub reserved: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StockReason {
+    Restock,
+    Correction,
+    Damaged,
+    Returned,
+    Sold,
+    Imported,
+}
+
+impl StockReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StockReason::Restock => "restock",
+            StockReason::Correction => "correction",
+            StockReason::Damaged => "damaged",
+            StockReason::Returned => "returned",
+            StockReason::Sold => "sold",
+            StockReason::Imported => "imported",
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "stock_adjustments"]
+pub struct NewStockAdjustment {
+    pub product_id: i32,
+    pub delta: i32,
+    pub reason: String,
+    pub comment: Option<String>,
+    pub created_by: Option<i32>,
+}
+
+pub struct StockAdjustment {
+    pub product_id: i32,
+    pub delta: i32,
+    pub reason: StockReason,
+    pub comment: Option<String>,
+}
+
+pub struct StockLevel {
+    pub code: String,
+    pub on_hand: i32,
+}
+
+// Rating of a product from the import file, reviews behind it are unknown
+pub struct RatingSeed {
+    pub code: String,
+    pub average: f64,
+    pub count: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReservationStatus {
+    Active,
+    Released,
+    Committed,
+    Expired,
+}
+
+impl ReservationStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReservationStatus::Active => "active",
+            ReservationStatus::Released => "released",
+            ReservationStatus::Committed => "committed",
+            ReservationStatus::Expired => "expired",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<ReservationStatus> {
+        match status {
+            "active" => Some(ReservationStatus::Active),
+            "released" => Some(ReservationStatus::Released),
+            "committed" => Some(ReservationStatus::Committed),
+            "expired" => Some(ReservationStatus::Expired),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct Reservation {
+    pub id: i32,
+    pub user_id: i32,
+    pub status: String,
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "reservations"]
+pub struct NewReservation<'a> {
+    pub user_id: i32,
+    pub status: &'a str,
+    pub expires_at: SystemTime,
+}
+
+#[derive(Queryable, Insertable, Clone)]
+#[table_name = "reservation_items"]
+pub struct ReservationItem {
+    pub reservation_id: i32,
+    pub product_id: i32,
+    pub quantity: i32,
+}
+
+pub struct ReservationDetails {
+    pub reservation: Reservation,
+    pub items: Vec<ReservationItem>,
+}
+
+#[derive(Queryable)]
+pub struct CartItem {
+    pub user_id: i32,
+    pub product_id: i32,
+    pub quantity: i32,
+    pub added_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "cart_items"]
+pub struct NewCartItem {
+    pub user_id: i32,
+    pub product_id: i32,
+    pub quantity: i32,
+}
+
+pub struct CartLine {
+    pub item: CartItem,
+    // None if the product was deleted
+    pub product: Option<Product>,
+    pub subtotal: Option<Money>,
+}
+
+pub struct CartDetails {
+    pub lines: Vec<CartLine>,
+    pub totals: Vec<Money>,
+}
+
+// Products added without a list go to the list with this name, it is
+// created on demand
+pub const DEFAULT_WISHLIST: &str = "Favorites";
+
+#[derive(Queryable)]
+pub struct Wishlist {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub share_token: Option<String>,
+    pub created_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "wishlists"]
+pub struct NewWishlist {
+    pub user_id: i32,
+    pub name: String,
+}
+
+#[derive(Queryable)]
+pub struct WishlistItem {
+    pub wishlist_id: i32,
+    pub product_id: i32,
+    pub added_at: SystemTime,
+}
+
+pub struct WishlistLine {
+    pub item: WishlistItem,
+    // None if the product was deleted
+    pub product: Option<Product>,
+}
+
+// Items are empty when lists are listed
+pub struct WishlistDetails {
+    pub wishlist: Wishlist,
+    pub items: Vec<WishlistLine>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<OrderStatus> {
+        match status {
+            "pending" => Some(OrderStatus::Pending),
+            "paid" => Some(OrderStatus::Paid),
+            "shipped" => Some(OrderStatus::Shipped),
+            "delivered" => Some(OrderStatus::Delivered),
+            "cancelled" => Some(OrderStatus::Cancelled),
+            "refunded" => Some(OrderStatus::Refunded),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct Order {
+    pub id: i32,
+    pub user_id: i32,
+    pub status: String,
+    pub currency: String,
+    pub total: i64,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub reservation_id: Option<i32>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "orders"]
+pub struct NewOrder {
+    pub user_id: i32,
+    pub status: String,
+    pub currency: String,
+    pub total: i64,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    // Set by the repo from the reservation made with the order
+    pub reservation_id: Option<i32>,
+}
+
+#[derive(Queryable, Insertable, Clone)]
+#[table_name = "order_items"]
+pub struct OrderItem {
+    pub order_id: i32,
+    pub product_id: i32,
+    pub name: String,
+    pub code: String,
+    pub quantity: i32,
+    pub unit_price: i64,
+}
+
+pub struct OrderDetails {
+    pub order: Order,
+    pub items: Vec<OrderItem>,
+    // Until then the stock of a pending order is held, paying later only
+    // succeeds if the stock is still available
+    pub reserved_until: Option<SystemTime>,
+}
+
+// What happens to the stock reserved at checkout on a status change
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReservationAction {
+    Keep,
+    Commit,
+    Release,
+}
+
+pub struct OrdersQuery {
+    pub user_id: Option<i32>,
+    pub status: Option<OrderStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Queryable, Clone)]
+pub struct Category {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "categories"]
+pub struct NewCategory {
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub path: String,
+}
+
+pub struct CategoryTree {
+    pub category: Category,
+    pub children: Vec<CategoryTree>,
+}
+
+pub const MIN_RATING: i32 = 1;
+pub const MAX_RATING: i32 = 5;
+
+// New reviews wait for moderation, only approved ones are public
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Hidden,
+}
+
+impl ReviewStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "pending",
+            ReviewStatus::Approved => "approved",
+            ReviewStatus::Hidden => "hidden",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<ReviewStatus> {
+        match status {
+            "pending" => Some(ReviewStatus::Pending),
+            "approved" => Some(ReviewStatus::Approved),
+            "hidden" => Some(ReviewStatus::Hidden),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Queryable)]
+pub struct Review {
+    pub id: i32,
+    pub product_id: i32,
+    pub user_id: i32,
+    pub rating: i32,
+    pub text: String,
+    pub status: String,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+impl Review {
+    pub fn is_approved(&self) -> bool {
+        self.status == ReviewStatus::Approved.as_str()
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "reviews"]
+pub struct NewReview {
+    pub product_id: i32,
+    pub user_id: i32,
+    pub rating: i32,
+    pub text: String,
+}
+
+pub struct ReviewsQuery {
+    pub product_id: Option<i32>,
+    pub status: Option<ReviewStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PromotionKind {
+    Percent,
+    Amount,
+}
+
+impl PromotionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PromotionKind::Percent => "percent",
+            PromotionKind::Amount => "amount",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<PromotionKind> {
+        match kind {
+            "percent" => Some(PromotionKind::Percent),
+            "amount" => Some(PromotionKind::Amount),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PromotionScope {
+    // The category and its descendants
+    Category,
+    Products,
+    // The cart subtotal after product discounts
+    Cart,
+}
+
+impl PromotionScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PromotionScope::Category => "category",
+            PromotionScope::Products => "products",
+            PromotionScope::Cart => "cart",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<PromotionScope> {
+        match scope {
+            "category" => Some(PromotionScope::Category),
+            "products" => Some(PromotionScope::Products),
+            "cart" => Some(PromotionScope::Cart),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Queryable, Clone)]
+pub struct Promotion {
+    pub id: i32,
+    pub name: String,
+    pub kind: String,
+    pub value: i64,
+    pub currency: Option<String>,
+    pub scope: String,
+    pub category_id: Option<i32>,
+    pub product_ids: Vec<i32>,
+    pub min_subtotal: i64,
+    pub coupon_code: Option<String>,
+    pub starts_at: Option<SystemTime>,
+    pub ends_at: Option<SystemTime>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+// Updates replace all fields
+#[derive(Insertable, AsChangeset)]
+#[table_name = "promotions"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewPromotion {
+    pub name: String,
+    pub kind: String,
+    pub value: i64,
+    pub currency: Option<String>,
+    pub scope: String,
+    pub category_id: Option<i32>,
+    pub product_ids: Vec<i32>,
+    pub min_subtotal: i64,
+    pub coupon_code: Option<String>,
+    pub starts_at: Option<SystemTime>,
+    pub ends_at: Option<SystemTime>,
+}
+
+pub struct LineDiscount {
+    pub product_id: i32,
+    pub quantity: i32,
+    pub subtotal: i64,
+    // Product discount plus the share of the cart discount
+    pub discount: i64,
+    pub total: i64,
+    pub promotion_ids: Vec<i32>,
+}
+
+pub struct AppliedDiscount {
+    pub promotion_id: i32,
+    pub name: String,
+    pub amount: i64,
+}
+
+// Amounts are in minor units of the currency
+pub struct Evaluation {
+    pub currency: String,
+    pub lines: Vec<LineDiscount>,
+    // Ordered by promotion id
+    pub discounts: Vec<AppliedDiscount>,
+    pub subtotal: i64,
+    pub discount: i64,
+    pub total: i64,
 }
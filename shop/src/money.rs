@@ -0,0 +1,76 @@
+use errors::Error;
+use errors::prelude::*;
+
+// ISO 4217 codes with the number of digits after the decimal separator
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AUD", 2),
+    ("CAD", 2),
+    ("CHF", 2),
+    ("CNY", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("JPY", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("RUB", 2),
+    ("USD", 2),
+];
+
+pub fn exponent(currency: &str) -> Option<u32> {
+    CURRENCIES
+        .iter()
+        .find(|(code, _)| *code == currency)
+        .map(|(_, exponent)| *exponent)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    // Minor units of the currency, e.g. pence for GBP
+    pub amount: i64,
+    pub currency: String,
+}
+
+impl Money {
+    // Parses a non-negative decimal like "12.3" without going through floats
+    pub fn parse(amount: &str, currency: &str) -> Result<Money> {
+        let currency = currency.trim().to_uppercase();
+        let exponent = exponent(&currency).ok_or_else(|| Error::BadRequest(format!("Unknown currency {}", currency)))?;
+        let invalid = || Error::BadRequest(format!("Invalid amount {}", amount));
+
+        let trimmed = amount.trim();
+        let (whole, fraction) = match trimmed.find('.') {
+            Some(pos) if pos + 1 < trimmed.len() => (&trimmed[..pos], &trimmed[pos + 1..]),
+            Some(_) => return Err(invalid()),
+            None => (trimmed, ""),
+        };
+        if whole.is_empty() || !whole.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if fraction.len() > exponent as usize {
+            return Err(Error::BadRequest(format!("{} allows at most {} decimal places", currency, exponent)));
+        }
+
+        let whole: i64 = whole.parse().map_err(|_| invalid())?;
+        let fraction: i64 = match fraction {
+            "" => 0,
+            _ => format!("{:0<width$}", fraction, width = exponent as usize).parse().map_err(|_| invalid())?,
+        };
+        let amount = whole
+            .checked_mul(10i64.pow(exponent))
+            .and_then(|minor| minor.checked_add(fraction))
+            .ok_or_else(invalid)?;
+
+        Ok(Money { amount, currency })
+    }
+
+    // Formats the amount with exactly as many fraction digits as the currency has
+    pub fn format_amount(&self) -> String {
+        let exponent = exponent(&self.currency).unwrap_or(0);
+        if exponent == 0 {
+            return self.amount.to_string();
+        }
+
+        let scale = 10i64.pow(exponent);
+        format!("{}.{:0width$}", self.amount / scale, self.amount % scale, width = exponent as usize)
+    }
+}
@@ -0,0 +1,34 @@
+use diesel::PgConnection;
+
+use errors::prelude::*;
+use crate::models;
+
+// Notifications go to the queues consumed by the confirmation senders
+const EMAIL_QUEUE: &str = "confirmations_email";
+const PHONE_QUEUE: &str = "confirmations_phone";
+
+// Declared by the outbox relay, so notifications published before the
+// senders start are not dropped
+pub const QUEUES: &[&str] = &[EMAIL_QUEUE, PHONE_QUEUE];
+
+// Must be called in the transaction changing the status, the outbox
+// relay publishes the notifications after commit.
+pub fn order_status_changed(connection: &PgConnection, order: &models::Order) -> Result<()> {
+    // FIXME(sskvor)
+    let url = format!("https://hw.sskvor.dev/orders/{}", order.id);
+    let text = format!("Order #{} is {}", order.id, order.status);
+
+    for (queue, login) in &[(EMAIL_QUEUE, &order.email), (PHONE_QUEUE, &order.phone)] {
+        if let Some(login) = login {
+            let notification = pb::Confirmation {
+                login: login.clone(),
+                url: url.clone(),
+                kind: Some(pb::ConfirmationKind::OrderStatusChanged.into()),
+                text: Some(text.clone()),
+            };
+            amqp_outbox::enqueue(connection, "", queue, &notification)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use errors::Error;
+use errors::prelude::*;
+use crate::models;
+
+// Position after the last product of a page. Clients get it as an opaque
+// string and pass it back unchanged to fetch the next page.
+#[derive(Serialize, Deserialize)]
+pub struct PageToken {
+    pub order: models::ProductOrder,
+    pub id: i32,
+    // Sort key of the last product, which one is set depends on the order
+    pub price: Option<i64>,
+    // Price orders sort by currency first
+    #[serde(default)]
+    pub currency: Option<String>,
+    pub text: Option<String>,
+}
+
+impl PageToken {
+    pub fn after(order: models::ProductOrder, product: &models::Product) -> PageToken {
+        use models::ProductOrder::*;
+
+        let text = match order {
+            NameAsc | NameDesc => Some(product.name.clone()),
+            CodeAsc | CodeDesc => Some(product.code.clone()),
+            _ => None,
+        };
+        PageToken { order, id: product.id, price: product.price, currency: product.currency.clone(), text }
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("Page tokens are always serializable");
+        base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(raw: &str) -> Result<PageToken> {
+        let invalid = || Error::BadRequest("Invalid page token".into());
+        let json = base64::decode_config(raw, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        serde_json::from_slice(&json).map_err(|_| invalid())
+    }
+}
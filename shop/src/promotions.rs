@@ -0,0 +1,469 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use errors::prelude::*;
+use crate::categories;
+use crate::models;
+use crate::models::{PromotionKind, PromotionScope};
+
+// Promotion with the path of its category, the path is only set for the
+// category scope
+pub struct Rule {
+    pub promotion: models::Promotion,
+    pub category_path: Option<String>,
+}
+
+// Priced cart line, the category path is the one of the product
+pub struct Line {
+    pub product_id: i32,
+    pub category_path: Option<String>,
+    pub quantity: i32,
+    pub unit_price: i64,
+}
+
+// Coupons are matched case-insensitively
+pub fn normalize_coupon(raw: &str) -> String {
+    raw.trim().to_uppercase()
+}
+
+// Every line gets the largest of the product and category discounts
+// matching it. Then the largest cart discount is taken off what is left of
+// the subtotal and split between the lines in proportion to what is left of
+// them. Ties go to the promotion with the lowest id and amounts are rounded
+// down, so the result only depends on the arguments. Fails if the cart
+// amounts do not fit into minor units.
+pub fn evaluate(rules: &[Rule], lines: &[Line], currency: &str, coupon: Option<&str>, now: SystemTime) -> Result<models::Evaluation> {
+    let coupon = coupon.map(normalize_coupon);
+    let mut applicable: Vec<&Rule> = rules
+        .iter()
+        .filter(|rule| is_applicable(&rule.promotion, currency, coupon.as_deref(), now))
+        .collect();
+    applicable.sort_by_key(|rule| rule.promotion.id);
+
+    // Discounts never exceed the line subtotals, so once their sum fits
+    // neither the discounts nor their sums per promotion can overflow
+    let subtotals = lines
+        .iter()
+        .map(|line| line.unit_price.checked_mul(i64::from(line.quantity)).ok_or_else(too_large))
+        .collect::<Result<Vec<i64>>>()?;
+    let subtotal = checked_sum(subtotals.iter().cloned())?;
+
+    let mut applied: BTreeMap<i32, i64> = BTreeMap::new();
+    let mut breakdown: Vec<models::LineDiscount> = Vec::with_capacity(lines.len());
+    for (line, &line_subtotal) in lines.iter().zip(&subtotals) {
+        let candidates = applicable
+            .iter()
+            .filter(|rule| matches_line(rule, line))
+            .map(|rule| (rule.promotion.id, line_discount(&rule.promotion, line, line_subtotal)));
+
+        let mut promotion_ids = Vec::new();
+        let discount = match best(candidates) {
+            Some((id, discount)) => {
+                *applied.entry(id).or_insert(0) += discount;
+                promotion_ids.push(id);
+                discount
+            }
+            None => 0,
+        };
+        breakdown.push(models::LineDiscount {
+            product_id: line.product_id,
+            quantity: line.quantity,
+            subtotal: line_subtotal,
+            discount,
+            total: line_subtotal - discount,
+            promotion_ids,
+        });
+    }
+
+    let rest: i64 = breakdown.iter().map(|line| line.total).sum();
+    let candidates = applicable
+        .iter()
+        .filter(|rule| scope(&rule.promotion) == Some(PromotionScope::Cart))
+        .map(|rule| (rule.promotion.id, cart_discount(&rule.promotion, rest)));
+    if let Some((id, discount)) = best(candidates) {
+        applied.insert(id, discount);
+        let weights: Vec<i64> = breakdown.iter().map(|line| line.total).collect();
+        for (line, share) in breakdown.iter_mut().zip(split(discount, &weights)) {
+            if share > 0 {
+                line.discount += share;
+                line.total -= share;
+                line.promotion_ids.push(id);
+            }
+        }
+    }
+
+    let discounts = applied
+        .into_iter()
+        .filter_map(|(id, amount)| {
+            let rule = applicable.iter().find(|rule| rule.promotion.id == id)?;
+            Some(models::AppliedDiscount { promotion_id: id, name: rule.promotion.name.clone(), amount })
+        })
+        .collect();
+    let discount = breakdown.iter().map(|line| line.discount).sum();
+
+    Ok(models::Evaluation {
+        currency: currency.into(),
+        lines: breakdown,
+        discounts,
+        subtotal,
+        discount,
+        total: subtotal - discount,
+    })
+}
+
+pub fn is_active(promotion: &models::Promotion, now: SystemTime) -> bool {
+    let started = promotion.starts_at.map_or(true, |starts_at| starts_at <= now);
+    let ended = promotion.ends_at.map_or(false, |ends_at| ends_at <= now);
+    started && !ended
+}
+
+fn is_applicable(promotion: &models::Promotion, currency: &str, coupon: Option<&str>, now: SystemTime) -> bool {
+    let currency_matches = promotion.currency.as_ref().map_or(true, |promotion_currency| promotion_currency == currency);
+    let coupon_matches = match &promotion.coupon_code {
+        Some(code) => coupon == Some(code.as_str()),
+        None => true,
+    };
+    is_active(promotion, now) && currency_matches && coupon_matches
+}
+
+fn scope(promotion: &models::Promotion) -> Option<PromotionScope> {
+    PromotionScope::parse(&promotion.scope)
+}
+
+fn matches_line(rule: &Rule, line: &Line) -> bool {
+    match scope(&rule.promotion) {
+        Some(PromotionScope::Products) => rule.promotion.product_ids.contains(&line.product_id),
+        Some(PromotionScope::Category) => match (&line.category_path, &rule.category_path) {
+            (Some(path), Some(root)) => categories::is_within(path, root),
+            _ => false,
+        },
+        Some(PromotionScope::Cart) | None => false,
+    }
+}
+
+// Amount discounts are taken off every unit, but never more than its price
+fn line_discount(promotion: &models::Promotion, line: &Line, subtotal: i64) -> i64 {
+    match PromotionKind::parse(&promotion.kind) {
+        Some(PromotionKind::Percent) => percent_of(subtotal, promotion.value),
+        Some(PromotionKind::Amount) => promotion.value.min(line.unit_price) * i64::from(line.quantity),
+        None => 0,
+    }
+}
+
+fn cart_discount(promotion: &models::Promotion, subtotal: i64) -> i64 {
+    if subtotal < promotion.min_subtotal {
+        return 0;
+    }
+    match PromotionKind::parse(&promotion.kind) {
+        Some(PromotionKind::Percent) => percent_of(subtotal, promotion.value),
+        Some(PromotionKind::Amount) => promotion.value.min(subtotal),
+        None => 0,
+    }
+}
+
+fn checked_sum(mut amounts: impl Iterator<Item = i64>) -> Result<i64> {
+    amounts
+        .try_fold(0i64, |sum, amount| sum.checked_add(amount))
+        .ok_or_else(too_large)
+}
+
+fn too_large() -> errors::Error {
+    errors::Error::BadRequest("Cart total is too large".into())
+}
+
+fn percent_of(amount: i64, percent: i64) -> i64 {
+    (i128::from(amount) * i128::from(percent) / 100) as i64
+}
+
+// Candidates come ordered by promotion id, the first of the largest
+// positive discounts wins
+fn best(candidates: impl Iterator<Item = (i32, i64)>) -> Option<(i32, i64)> {
+    candidates.fold(None, |best, (id, discount)| match best {
+        Some((_, best_discount)) if best_discount >= discount => best,
+        _ if discount > 0 => Some((id, discount)),
+        _ => best,
+    })
+}
+
+// Splits the amount in proportion to the weights, which must add up to at
+// least the amount. Minor units left after rounding down go to the largest
+// remainders, earlier lines first.
+fn split(amount: i64, weights: &[i64]) -> Vec<i64> {
+    let total: i128 = weights.iter().map(|weight| i128::from(*weight)).sum();
+    if total == 0 {
+        return vec![0; weights.len()];
+    }
+
+    let mut shares = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (index, weight) in weights.iter().enumerate() {
+        let exact = i128::from(amount) * i128::from(*weight);
+        shares.push((exact / total) as i64);
+        remainders.push((exact % total, index));
+    }
+
+    let mut left = amount - shares.iter().sum::<i64>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, index) in remainders {
+        if left == 0 {
+            break;
+        }
+        shares[index] += 1;
+        left -= 1;
+    }
+
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    const NOW: u64 = 1_600_000_000;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn promotion(id: i32, kind: PromotionKind, value: i64, scope: PromotionScope) -> models::Promotion {
+        models::Promotion {
+            id,
+            name: format!("Promotion {}", id),
+            kind: kind.as_str().into(),
+            value,
+            currency: None,
+            scope: scope.as_str().into(),
+            category_id: None,
+            product_ids: vec![],
+            min_subtotal: 0,
+            coupon_code: None,
+            starts_at: None,
+            ends_at: None,
+            created_at: at(0),
+            updated_at: at(0),
+        }
+    }
+
+    fn for_products(id: i32, kind: PromotionKind, value: i64, product_ids: Vec<i32>) -> Rule {
+        let mut promotion = promotion(id, kind, value, PromotionScope::Products);
+        promotion.product_ids = product_ids;
+        Rule { promotion, category_path: None }
+    }
+
+    fn for_category(id: i32, kind: PromotionKind, value: i64, path: &str) -> Rule {
+        let mut promotion = promotion(id, kind, value, PromotionScope::Category);
+        promotion.category_id = Some(1);
+        Rule { promotion, category_path: Some(path.into()) }
+    }
+
+    fn for_cart(id: i32, kind: PromotionKind, value: i64) -> Rule {
+        Rule { promotion: promotion(id, kind, value, PromotionScope::Cart), category_path: None }
+    }
+
+    fn line(product_id: i32, category_path: Option<&str>, quantity: i32, unit_price: i64) -> Line {
+        Line { product_id, category_path: category_path.map(String::from), quantity, unit_price }
+    }
+
+    fn run(rules: &[Rule], lines: &[Line]) -> models::Evaluation {
+        evaluate(rules, lines, "USD", None, at(NOW)).unwrap()
+    }
+
+    fn run_with_coupon(rules: &[Rule], lines: &[Line], coupon: Option<&str>) -> models::Evaluation {
+        evaluate(rules, lines, "USD", coupon, at(NOW)).unwrap()
+    }
+
+    fn applied(evaluation: &models::Evaluation) -> Vec<(i32, i64)> {
+        evaluation.discounts.iter().map(|d| (d.promotion_id, d.amount)).collect()
+    }
+
+    #[test]
+    fn best_prefers_larger_discounts() {
+        assert_eq!(best(vec![(1, 5), (2, 6)].into_iter()), Some((2, 6)));
+        assert_eq!(best(vec![(1, 6), (2, 5)].into_iter()), Some((1, 6)));
+    }
+
+    #[test]
+    fn best_breaks_ties_by_first_candidate() {
+        assert_eq!(best(vec![(1, 5), (2, 5), (3, 4)].into_iter()), Some((1, 5)));
+    }
+
+    #[test]
+    fn best_skips_zero_discounts() {
+        assert_eq!(best(vec![(1, 0), (2, 0)].into_iter()), None);
+        assert_eq!(best(Vec::<(i32, i64)>::new().into_iter()), None);
+    }
+
+    #[test]
+    fn ties_go_to_lowest_promotion_id() {
+        let rules = vec![
+            for_products(7, PromotionKind::Percent, 10, vec![1]),
+            for_products(3, PromotionKind::Percent, 10, vec![1]),
+        ];
+        let evaluation = run(&rules, &[line(1, None, 1, 1000)]);
+
+        assert_eq!(evaluation.lines[0].promotion_ids, vec![3]);
+        assert_eq!(applied(&evaluation), vec![(3, 100)]);
+    }
+
+    #[test]
+    fn line_gets_largest_of_product_and_category_discounts() {
+        let rules = vec![
+            for_products(1, PromotionKind::Percent, 5, vec![10]),
+            for_category(2, PromotionKind::Percent, 20, "Toys"),
+        ];
+        let evaluation = run(&rules, &[line(10, Some("Toys > Trains"), 1, 1000)]);
+
+        assert_eq!(evaluation.lines[0].discount, 200);
+        assert_eq!(evaluation.lines[0].promotion_ids, vec![2]);
+        assert_eq!(applied(&evaluation), vec![(2, 200)]);
+    }
+
+    #[test]
+    fn category_discount_skips_other_categories() {
+        let rules = vec![for_category(1, PromotionKind::Percent, 20, "Toys")];
+        let evaluation = run(&rules, &[line(1, Some("Toys Store"), 1, 1000), line(2, None, 1, 1000)]);
+
+        assert_eq!(evaluation.discount, 0);
+        assert!(evaluation.discounts.is_empty());
+    }
+
+    #[test]
+    fn cart_discount_applies_after_line_discounts() {
+        let rules = vec![
+            for_category(1, PromotionKind::Percent, 20, "Toys"),
+            for_cart(2, PromotionKind::Percent, 10),
+        ];
+        let lines = vec![line(1, Some("Toys > Trains"), 1, 1000), line(2, Some("Books"), 1, 1000)];
+        let evaluation = run(&rules, &lines);
+
+        // 10% of 800 + 1000, split in proportion to what is left of the lines
+        assert_eq!(applied(&evaluation), vec![(1, 200), (2, 180)]);
+        assert_eq!(evaluation.lines[0].discount, 280);
+        assert_eq!(evaluation.lines[0].total, 720);
+        assert_eq!(evaluation.lines[0].promotion_ids, vec![1, 2]);
+        assert_eq!(evaluation.lines[1].discount, 100);
+        assert_eq!(evaluation.lines[1].total, 900);
+        assert_eq!(evaluation.lines[1].promotion_ids, vec![2]);
+        assert_eq!(evaluation.subtotal, 2000);
+        assert_eq!(evaluation.discount, 380);
+        assert_eq!(evaluation.total, 1620);
+    }
+
+    #[test]
+    fn amount_discount_is_capped_at_unit_price() {
+        let rules = vec![for_products(1, PromotionKind::Amount, 500, vec![1])];
+        let evaluation = run(&rules, &[line(1, None, 3, 300)]);
+
+        assert_eq!(evaluation.lines[0].discount, 900);
+        assert_eq!(evaluation.total, 0);
+    }
+
+    #[test]
+    fn amount_discount_is_taken_off_every_unit() {
+        let rules = vec![for_products(1, PromotionKind::Amount, 50, vec![1])];
+        let evaluation = run(&rules, &[line(1, None, 3, 300)]);
+
+        assert_eq!(evaluation.lines[0].discount, 150);
+        assert_eq!(evaluation.total, 750);
+    }
+
+    #[test]
+    fn cart_discount_needs_min_subtotal() {
+        let mut rule = for_cart(1, PromotionKind::Amount, 100);
+        rule.promotion.min_subtotal = 1000;
+        let rules = vec![rule];
+
+        assert_eq!(run(&rules, &[line(1, None, 1, 999)]).discount, 0);
+        assert_eq!(run(&rules, &[line(1, None, 1, 1000)]).discount, 100);
+    }
+
+    #[test]
+    fn min_subtotal_counts_line_discounts() {
+        let mut cart = for_cart(2, PromotionKind::Amount, 100);
+        cart.promotion.min_subtotal = 1000;
+        let rules = vec![for_products(1, PromotionKind::Amount, 1, vec![1]), cart];
+        let evaluation = run(&rules, &[line(1, None, 1, 1000)]);
+
+        assert_eq!(applied(&evaluation), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn promotions_in_other_currencies_are_skipped() {
+        let mut euro = for_cart(1, PromotionKind::Amount, 100);
+        euro.promotion.currency = Some("EUR".into());
+        assert_eq!(run(&[euro], &[line(1, None, 1, 1000)]).discount, 0);
+
+        let mut dollar = for_cart(1, PromotionKind::Amount, 100);
+        dollar.promotion.currency = Some("USD".into());
+        assert_eq!(run(&[dollar], &[line(1, None, 1, 1000)]).discount, 100);
+    }
+
+    #[test]
+    fn coupon_promotions_need_their_coupon() {
+        let mut rule = for_cart(1, PromotionKind::Percent, 10);
+        rule.promotion.coupon_code = Some("SAVE10".into());
+        let rules = vec![rule];
+        let lines = vec![line(1, None, 1, 1000)];
+
+        assert_eq!(run_with_coupon(&rules, &lines, None).discount, 0);
+        assert_eq!(run_with_coupon(&rules, &lines, Some("SAVE20")).discount, 0);
+        assert_eq!(run_with_coupon(&rules, &lines, Some(" save10 ")).discount, 100);
+    }
+
+    #[test]
+    fn inactive_promotions_are_skipped() {
+        let lines = vec![line(1, None, 1, 1000)];
+
+        let mut upcoming = for_cart(1, PromotionKind::Amount, 100);
+        upcoming.promotion.starts_at = Some(at(NOW + 1));
+        assert_eq!(run(&[upcoming], &lines).discount, 0);
+
+        let mut ended = for_cart(1, PromotionKind::Amount, 100);
+        ended.promotion.ends_at = Some(at(NOW));
+        assert_eq!(run(&[ended], &lines).discount, 0);
+
+        let mut running = for_cart(1, PromotionKind::Amount, 100);
+        running.promotion.starts_at = Some(at(NOW));
+        running.promotion.ends_at = Some(at(NOW + 1));
+        assert_eq!(run(&[running], &lines).discount, 100);
+    }
+
+    #[test]
+    fn split_is_proportional() {
+        assert_eq!(split(180, &[800, 1000]), vec![80, 100]);
+        assert_eq!(split(7, &[0, 5, 2]), vec![0, 5, 2]);
+    }
+
+    #[test]
+    fn split_gives_remainder_to_largest_remainders() {
+        // 5 * 3 / 4 = 3.75 and 5 * 1 / 4 = 1.25
+        assert_eq!(split(5, &[3, 1]), vec![4, 1]);
+        // 1 * 1 / 4 = 0.25 and 1 * 3 / 4 = 0.75
+        assert_eq!(split(1, &[1, 3]), vec![0, 1]);
+    }
+
+    #[test]
+    fn split_gives_equal_remainders_to_earlier_lines() {
+        assert_eq!(split(10, &[1, 1, 1]), vec![4, 3, 3]);
+        assert_eq!(split(2, &[1, 1, 1]), vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn split_of_zero_weights_is_zero() {
+        assert_eq!(split(0, &[0, 0]), vec![0, 0]);
+    }
+
+    #[test]
+    fn oversized_carts_are_rejected() {
+        assert!(evaluate(&[], &[line(1, None, 2, i64::MAX)], "USD", None, at(NOW)).is_err());
+
+        let half = i64::MAX / 2 + 1;
+        let lines = vec![line(1, None, 1, half), line(2, None, 1, half)];
+        assert!(evaluate(&[], &lines, "USD", None, at(NOW)).is_err());
+
+        let rules = vec![for_products(1, PromotionKind::Percent, 100, vec![1, 2])];
+        assert!(evaluate(&rules, &lines, "USD", None, at(NOW)).is_err());
+    }
+}
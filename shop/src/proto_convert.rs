@@ -1,11 +1,25 @@
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use errors::Error;
 use crate::models;
+use crate::money::Money;
+use crate::paging::PageToken;
+use crate::promotions;
+
+impl TryFrom<pb::Money> for Money {
+    type Error = Error;
+
+    fn try_from(money: pb::Money) -> Result<Money, Error> {
+        Money::parse(&money.amount, &money.currency)
+    }
+}
 
-impl From<pb::AddProductRequest> for models::NewProduct {
-    fn from(req: pb::AddProductRequest) -> models::NewProduct {
-        return models::NewProduct {
-            code: req.product.code,
-            name: req.product.name,
-            category: req.product.category,
+impl From<Money> for pb::Money {
+    fn from(money: Money) -> pb::Money {
+        return pb::Money {
+            amount: money.format_amount(),
+            currency: money.currency,
         }
     }
 }
@@ -14,28 +28,617 @@ impl From<models::Product> for pb::Product {
     fn from(res: models::Product) -> pb::Product {
         return pb::Product {
             id: Some(res.id),
-            code: res.code,
-            name: res.name,
-            category: res.category,
+            price: res.price().map(|price| price.into()),
+            category_id: res.category_id,
+            code: Some(res.code),
+            name: Some(res.name),
+            category: Some(res.category),
+            description: Some(res.description),
+            version: Some(res.version),
+            deleted_at: res.deleted_at.map(unix_seconds),
+            rating: res.rating(),
+            rating_count: Some(res.rating_count),
+        }
+    }
+}
+
+impl TryFrom<pb::Product> for models::NewProduct {
+    type Error = Error;
+
+    fn try_from(res: pb::Product) -> Result<models::NewProduct, Error> {
+        let mut product = models::NewProduct {
+            code: required(res.code, "code")?,
+            name: required(res.name, "name")?,
+            category: required(res.category, "category")?,
+            price: None,
+            currency: None,
+            category_id: None,
+            description: res.description.unwrap_or_default(),
+        };
+        product.set_price(res.price.map(Money::try_from).transpose()?);
+        Ok(product)
+    }
+}
+
+impl TryFrom<pb::UpdateProductRequest> for models::ProductUpdate {
+    type Error = Error;
+
+    fn try_from(req: pb::UpdateProductRequest) -> Result<models::ProductUpdate, Error> {
+        let product = req.product;
+        let id = product.id.ok_or_else(|| Error::BadRequest("Product id is required".into()))?;
+        let mask = match req.update_mask.is_empty() {
+            true => models::PRODUCT_UPDATE_FIELDS.iter().map(|field| field.to_string()).collect(),
+            false => req.update_mask,
+        };
+
+        let mut changes = models::ProductChanges::default();
+        for field in mask {
+            match field.as_str() {
+                "name" => changes.name = Some(required(product.name.clone(), "name")?),
+                "code" => changes.code = Some(required(product.code.clone(), "code")?),
+                "category" => changes.category = Some(required(product.category.clone(), "category")?),
+                "price" => changes.set_price(product.price.clone().map(Money::try_from).transpose()?),
+                "description" => changes.description = Some(product.description.clone().unwrap_or_default()),
+                _ => return Err(Error::BadRequest(format!("Field {} can't be updated", field))),
+            }
+        }
+
+        Ok(models::ProductUpdate { id, expected_version: product.version, changes })
+    }
+}
+
+fn required(value: Option<String>, field: &str) -> Result<String, Error> {
+    value.ok_or_else(|| Error::BadRequest(format!("Product {} is required", field)))
+}
+
+impl From<models::ProductRevision> for pb::ProductRevision {
+    fn from(res: models::ProductRevision) -> pb::ProductRevision {
+        let action = match models::RevisionAction::parse(&res.action) {
+            Some(models::RevisionAction::Created) => pb::RevisionAction::Created,
+            Some(models::RevisionAction::Deleted) => pb::RevisionAction::Deleted,
+            Some(models::RevisionAction::Restored) => pb::RevisionAction::Restored,
+            Some(models::RevisionAction::Reverted) => pb::RevisionAction::Reverted,
+            Some(models::RevisionAction::Purged) => pb::RevisionAction::Purged,
+            _ => pb::RevisionAction::Updated,
+        };
+        let source = match models::RevisionSource::parse(&res.source) {
+            Some(models::RevisionSource::Import) => pb::RevisionSource::ImportJob,
+            Some(models::RevisionSource::Sweeper) => pb::RevisionSource::Sweeper,
+            _ => pb::RevisionSource::Api,
+        };
+        let product_id = res.product_id;
+        let snapshot = |value: serde_json::Value| {
+            serde_json::from_value::<models::ProductSnapshot>(value)
+                .ok()
+                .map(|snapshot| snapshot_to_product(product_id, snapshot))
+        };
+
+        return pb::ProductRevision {
+            id: res.id,
+            product_id,
+            action: action.into(),
+            source: source.into(),
+            user_id: res.user_id,
+            before: res.before.and_then(snapshot),
+            after: res.after.and_then(snapshot),
+            created_at: unix_seconds(res.created_at),
+        }
+    }
+}
+
+impl From<models::BatchResult> for pb::BatchItemResult {
+    fn from(res: models::BatchResult) -> pb::BatchItemResult {
+        let (product, error) = match res.result {
+            Ok(product) => (product.map(|product| product.into()), None),
+            Err(e) => {
+                let status = tonic::Status::from(e);
+                (None, Some(pb::BatchItemError { code: status.code() as i32, message: status.message().into() }))
+            }
+        };
+
+        return pb::BatchItemResult {
+            id: res.id,
+            product,
+            error,
+        }
+    }
+}
+
+fn snapshot_to_product(id: i32, snapshot: models::ProductSnapshot) -> pb::Product {
+    let price = match (snapshot.price, snapshot.currency) {
+        (Some(amount), Some(currency)) => Some(Money { amount, currency }.into()),
+        _ => None,
+    };
+
+    pb::Product {
+        id: Some(id),
+        name: Some(snapshot.name),
+        code: Some(snapshot.code),
+        category: Some(snapshot.category),
+        price,
+        category_id: snapshot.category_id,
+        description: Some(snapshot.description),
+        version: None,
+        deleted_at: None,
+        rating: None,
+        rating_count: None,
+    }
+}
+
+impl TryFrom<pb::ListProductsRequest> for models::ListQuery {
+    type Error = Error;
+
+    fn try_from(req: pb::ListProductsRequest) -> Result<models::ListQuery, Error> {
+        let order = match req.order.and_then(pb::ProductOrder::from_i32) {
+            Some(pb::ProductOrder::ByPriceAsc) => models::ProductOrder::PriceAsc,
+            Some(pb::ProductOrder::ByPriceDesc) => models::ProductOrder::PriceDesc,
+            Some(pb::ProductOrder::ByNameAsc) => models::ProductOrder::NameAsc,
+            Some(pb::ProductOrder::ByNameDesc) => models::ProductOrder::NameDesc,
+            Some(pb::ProductOrder::ByCodeAsc) => models::ProductOrder::CodeAsc,
+            Some(pb::ProductOrder::ByCodeDesc) => models::ProductOrder::CodeDesc,
+            Some(pb::ProductOrder::ByIdDesc) => models::ProductOrder::IdDesc,
+            _ => models::ProductOrder::Id,
+        };
+        let count = match req.total_count.and_then(pb::TotalCount::from_i32) {
+            Some(pb::TotalCount::EstimatedCount) => models::CountMode::Estimated,
+            Some(pb::TotalCount::NoCount) => models::CountMode::Skip,
+            _ => models::CountMode::Exact,
+        };
+
+        Ok(models::ListQuery {
+            limit: req.limit,
+            offset: req.offset,
+            after: req.page_token.as_deref().map(PageToken::decode).transpose()?,
+            order,
+            count,
+            min_price: req.min_price.map(Money::try_from).transpose()?,
+            max_price: req.max_price.map(Money::try_from).transpose()?,
+            code: req.code,
+            name_prefix: req.name_prefix,
+            category_id: req.category_id,
+            category_path: None,
+        })
+    }
+}
+
+impl TryFrom<pb::ExportProductsRequest> for models::ListQuery {
+    type Error = Error;
+
+    fn try_from(req: pb::ExportProductsRequest) -> Result<models::ListQuery, Error> {
+        Ok(models::ListQuery {
+            limit: None,
+            offset: None,
+            after: None,
+            order: models::ProductOrder::Id,
+            count: models::CountMode::Skip,
+            min_price: req.min_price.map(Money::try_from).transpose()?,
+            max_price: req.max_price.map(Money::try_from).transpose()?,
+            code: req.code,
+            name_prefix: req.name_prefix,
+            category_id: req.category_id,
+            category_path: None,
+        })
+    }
+}
+
+impl TryFrom<pb::SearchProductsRequest> for models::SearchQuery {
+    type Error = Error;
+
+    fn try_from(req: pb::SearchProductsRequest) -> Result<models::SearchQuery, Error> {
+        Ok(models::SearchQuery {
+            query: req.query,
+            limit: req.limit,
+            offset: req.offset,
+            min_price: req.min_price.map(Money::try_from).transpose()?,
+            max_price: req.max_price.map(Money::try_from).transpose()?,
+            category_id: req.category_id,
+            category_path: None,
+        })
+    }
+}
+
+impl From<models::SearchHit> for pb::SearchHit {
+    fn from(hit: models::SearchHit) -> pb::SearchHit {
+        return pb::SearchHit {
+            product: hit.product.into(),
+            rank: hit.rank,
+            snippet: hit.snippet,
+        }
+    }
+}
+
+impl From<models::Stock> for pb::Stock {
+    fn from(stock: models::Stock) -> pb::Stock {
+        return pb::Stock {
+            product_id: stock.product_id,
+            on_hand: stock.on_hand,
+            reserved: stock.reserved,
+            available: stock.on_hand - stock.reserved,
+        }
+    }
+}
+
+impl TryFrom<pb::AdjustStockRequest> for models::StockAdjustment {
+    type Error = Error;
+
+    fn try_from(req: pb::AdjustStockRequest) -> Result<models::StockAdjustment, Error> {
+        let reason = match pb::StockReason::from_i32(req.reason) {
+            Some(pb::StockReason::Restock) => models::StockReason::Restock,
+            Some(pb::StockReason::Correction) => models::StockReason::Correction,
+            Some(pb::StockReason::Damaged) => models::StockReason::Damaged,
+            Some(pb::StockReason::Returned) => models::StockReason::Returned,
+            Some(pb::StockReason::Sold) => models::StockReason::Sold,
+            Some(pb::StockReason::Imported) => models::StockReason::Imported,
+            None => return Err(Error::BadRequest("Unknown stock reason".into())),
+        };
+
+        Ok(models::StockAdjustment {
+            product_id: req.product_id,
+            delta: req.delta,
+            reason,
+            comment: req.comment,
+        })
+    }
+}
+
+impl From<pb::ReservationItem> for models::ReservationItem {
+    fn from(item: pb::ReservationItem) -> models::ReservationItem {
+        return models::ReservationItem {
+            reservation_id: 0,
+            product_id: item.product_id,
+            quantity: item.quantity,
+        }
+    }
+}
+
+impl From<models::ReservationItem> for pb::ReservationItem {
+    fn from(item: models::ReservationItem) -> pb::ReservationItem {
+        return pb::ReservationItem {
+            product_id: item.product_id,
+            quantity: item.quantity,
+        }
+    }
+}
+
+impl From<models::ReservationDetails> for pb::Reservation {
+    fn from(res: models::ReservationDetails) -> pb::Reservation {
+        let status = match models::ReservationStatus::parse(&res.reservation.status) {
+            Some(models::ReservationStatus::Released) => pb::ReservationStatus::Released,
+            Some(models::ReservationStatus::Committed) => pb::ReservationStatus::Committed,
+            Some(models::ReservationStatus::Expired) => pb::ReservationStatus::Expired,
+            _ => pb::ReservationStatus::Active,
+        };
+
+        return pb::Reservation {
+            id: res.reservation.id,
+            items: res.items.into_iter().map(|item| item.into()).collect(),
+            status: status.into(),
+            expires_at: unix_seconds(res.reservation.expires_at),
+        }
+    }
+}
+
+impl From<pb::StockLevel> for models::StockLevel {
+    fn from(level: pb::StockLevel) -> models::StockLevel {
+        return models::StockLevel {
+            code: level.code,
+            on_hand: level.on_hand,
         }
     }
 }
 
-impl From<pb::Product> for models::NewProduct {
-    fn from(res: pb::Product) -> models::NewProduct {
-        return models::NewProduct {
-            code: res.code,
-            name: res.name,
-            category: res.category,
+impl From<pb::RatingSeed> for models::RatingSeed {
+    fn from(seed: pb::RatingSeed) -> models::RatingSeed {
+        return models::RatingSeed {
+            code: seed.code,
+            average: seed.average,
+            count: seed.count,
+        }
+    }
+}
+
+impl From<models::CartDetails> for pb::ShoppingCart {
+    fn from(cart: models::CartDetails) -> pb::ShoppingCart {
+        return pb::ShoppingCart {
+            items: cart.lines.into_iter().map(|line| pb::CartItem {
+                product_id: line.item.product_id,
+                quantity: line.item.quantity,
+                stale: line.product.is_none(),
+                product: line.product.map(|product| product.into()),
+                subtotal: line.subtotal.map(|subtotal| subtotal.into()),
+            }).collect(),
+            totals: cart.totals.into_iter().map(|total| total.into()).collect(),
+        }
+    }
+}
+
+impl From<models::WishlistDetails> for pb::SavedList {
+    fn from(details: models::WishlistDetails) -> pb::SavedList {
+        return pb::SavedList {
+            id: details.wishlist.id,
+            name: details.wishlist.name,
+            share_token: details.wishlist.share_token,
+            created_at: unix_seconds(details.wishlist.created_at),
+            items: details.items.into_iter().map(|line| pb::SavedItem {
+                product_id: line.item.product_id,
+                stale: line.product.is_none(),
+                product: line.product.map(|product| product.into()),
+                added_at: unix_seconds(line.item.added_at),
+            }).collect(),
+        }
+    }
+}
+
+impl From<models::Category> for pb::Category {
+    fn from(category: models::Category) -> pb::Category {
+        return pb::Category {
+            id: category.id,
+            parent_id: category.parent_id,
+            name: category.name,
+            path: category.path,
+        }
+    }
+}
+
+impl From<models::CategoryTree> for pb::CategoryNode {
+    fn from(tree: models::CategoryTree) -> pb::CategoryNode {
+        return pb::CategoryNode {
+            category: tree.category.into(),
+            children: tree.children.into_iter().map(|child| child.into()).collect(),
+        }
+    }
+}
+
+pub fn parse_order_status(status: i32) -> Result<models::OrderStatus, Error> {
+    match pb::OrderStatus::from_i32(status) {
+        Some(pb::OrderStatus::Pending) => Ok(models::OrderStatus::Pending),
+        Some(pb::OrderStatus::Paid) => Ok(models::OrderStatus::Paid),
+        Some(pb::OrderStatus::Shipped) => Ok(models::OrderStatus::Shipped),
+        Some(pb::OrderStatus::Delivered) => Ok(models::OrderStatus::Delivered),
+        Some(pb::OrderStatus::Cancelled) => Ok(models::OrderStatus::Cancelled),
+        Some(pb::OrderStatus::Refunded) => Ok(models::OrderStatus::Refunded),
+        None => Err(Error::BadRequest("Unknown order status".into())),
+    }
+}
+
+pub fn format_order_status(status: models::OrderStatus) -> i32 {
+    match status {
+        models::OrderStatus::Pending => pb::OrderStatus::Pending.into(),
+        models::OrderStatus::Paid => pb::OrderStatus::Paid.into(),
+        models::OrderStatus::Shipped => pb::OrderStatus::Shipped.into(),
+        models::OrderStatus::Delivered => pb::OrderStatus::Delivered.into(),
+        models::OrderStatus::Cancelled => pb::OrderStatus::Cancelled.into(),
+        models::OrderStatus::Refunded => pb::OrderStatus::Refunded.into(),
+    }
+}
+
+impl From<models::OrderDetails> for pb::Order {
+    fn from(res: models::OrderDetails) -> pb::Order {
+        let currency = res.order.currency;
+        let money = |amount: i64| -> pb::Money { Money { amount, currency: currency.clone() }.into() };
+
+        return pb::Order {
+            id: res.order.id,
+            user_id: res.order.user_id,
+            status: format_order_status(models::OrderStatus::parse(&res.order.status).unwrap_or(models::OrderStatus::Pending)),
+            items: res.items.iter().map(|item| pb::OrderItem {
+                product_id: item.product_id,
+                name: item.name.clone(),
+                code: item.code.clone(),
+                quantity: item.quantity,
+                unit_price: money(item.unit_price),
+                subtotal: money(item.unit_price * i64::from(item.quantity)),
+            }).collect(),
+            total: money(res.order.total),
+            created_at: unix_seconds(res.order.created_at),
+            updated_at: unix_seconds(res.order.updated_at),
+            reserved_until: res.reserved_until.map(unix_seconds),
+        }
+    }
+}
+
+impl TryFrom<pb::ListOrdersRequest> for models::OrdersQuery {
+    type Error = Error;
+
+    fn try_from(req: pb::ListOrdersRequest) -> Result<models::OrdersQuery, Error> {
+        Ok(models::OrdersQuery {
+            user_id: None,
+            status: req.status.map(parse_order_status).transpose()?,
+            limit: req.limit,
+            offset: req.offset,
+        })
+    }
+}
+
+pub fn parse_review_status(status: i32) -> Result<models::ReviewStatus, Error> {
+    match pb::ReviewStatus::from_i32(status) {
+        Some(pb::ReviewStatus::AwaitingModeration) => Ok(models::ReviewStatus::Pending),
+        Some(pb::ReviewStatus::Approved) => Ok(models::ReviewStatus::Approved),
+        Some(pb::ReviewStatus::Hidden) => Ok(models::ReviewStatus::Hidden),
+        None => Err(Error::BadRequest("Unknown review status".into())),
+    }
+}
+
+pub fn format_review_status(status: models::ReviewStatus) -> i32 {
+    match status {
+        models::ReviewStatus::Pending => pb::ReviewStatus::AwaitingModeration.into(),
+        models::ReviewStatus::Approved => pb::ReviewStatus::Approved.into(),
+        models::ReviewStatus::Hidden => pb::ReviewStatus::Hidden.into(),
+    }
+}
+
+impl From<models::Review> for pb::Review {
+    fn from(review: models::Review) -> pb::Review {
+        return pb::Review {
+            id: review.id,
+            product_id: review.product_id,
+            user_id: review.user_id,
+            rating: review.rating,
+            text: Some(review.text).filter(|text| !text.is_empty()),
+            status: format_review_status(models::ReviewStatus::parse(&review.status).unwrap_or(models::ReviewStatus::Pending)),
+            created_at: unix_seconds(review.created_at),
+            updated_at: unix_seconds(review.updated_at),
         }
     }
 }
 
-impl From<pb::ListProductsRequest> for models::ListQuery {
-    fn from(req: pb::ListProductsRequest) -> models::ListQuery {
-        return models::ListQuery {
+impl TryFrom<pb::ListReviewsRequest> for models::ReviewsQuery {
+    type Error = Error;
+
+    fn try_from(req: pb::ListReviewsRequest) -> Result<models::ReviewsQuery, Error> {
+        Ok(models::ReviewsQuery {
+            product_id: req.product_id,
+            status: req.status.map(parse_review_status).transpose()?,
             limit: req.limit,
             offset: req.offset,
+        })
+    }
+}
+
+impl TryFrom<pb::Promotion> for models::NewPromotion {
+    type Error = Error;
+
+    fn try_from(req: pb::Promotion) -> Result<models::NewPromotion, Error> {
+        let name = req.name.trim().to_string();
+        if name.is_empty() {
+            return Err(Error::BadRequest("Promotion name must not be empty".into()));
+        }
+
+        let (kind, value, mut currency) = match pb::DiscountKind::from_i32(req.kind) {
+            Some(pb::DiscountKind::PercentOff) => match req.percent {
+                Some(percent) if percent >= 1 && percent <= 100 => (models::PromotionKind::Percent, i64::from(percent), None),
+                _ => return Err(Error::BadRequest("Percent must be from 1 to 100".into())),
+            },
+            Some(pb::DiscountKind::AmountOff) => {
+                let amount = Money::try_from(req.amount.ok_or_else(|| Error::BadRequest("Amount is required".into()))?)?;
+                if amount.amount <= 0 {
+                    return Err(Error::BadRequest("Amount must be positive".into()));
+                }
+                (models::PromotionKind::Amount, amount.amount, Some(amount.currency))
+            }
+            None => return Err(Error::BadRequest("Unknown discount kind".into())),
+        };
+
+        let mut promotion = models::NewPromotion {
+            name,
+            kind: kind.as_str().into(),
+            value,
+            currency: None,
+            scope: models::PromotionScope::Cart.as_str().into(),
+            category_id: None,
+            product_ids: Vec::new(),
+            min_subtotal: 0,
+            coupon_code: req.coupon_code.as_deref().map(promotions::normalize_coupon).filter(|code| !code.is_empty()),
+            starts_at: req.starts_at.map(from_unix_seconds),
+            ends_at: req.ends_at.map(from_unix_seconds),
+        };
+        match req.scope {
+            Some(pb::promotion::Scope::CategoryId(category_id)) => {
+                promotion.scope = models::PromotionScope::Category.as_str().into();
+                promotion.category_id = Some(category_id);
+            }
+            Some(pb::promotion::Scope::Products(products)) => {
+                if products.product_ids.is_empty() {
+                    return Err(Error::BadRequest("Product scope must not be empty".into()));
+                }
+                let mut product_ids = products.product_ids;
+                product_ids.sort();
+                product_ids.dedup();
+                promotion.scope = models::PromotionScope::Products.as_str().into();
+                promotion.product_ids = product_ids;
+            }
+            Some(pb::promotion::Scope::Cart(cart)) => {
+                if let Some(min_subtotal) = cart.min_subtotal {
+                    let min_subtotal = Money::try_from(min_subtotal)?;
+                    if currency.as_ref().map_or(false, |currency| *currency != min_subtotal.currency) {
+                        return Err(Error::BadRequest("Amount and minimal subtotal must have the same currency".into()));
+                    }
+                    currency = Some(min_subtotal.currency);
+                    promotion.min_subtotal = min_subtotal.amount;
+                }
+            }
+            None => return Err(Error::BadRequest("Promotion scope is required".into())),
         }
+        promotion.currency = currency;
+
+        if let (Some(starts_at), Some(ends_at)) = (promotion.starts_at, promotion.ends_at) {
+            if starts_at >= ends_at {
+                return Err(Error::BadRequest("Promotion must start before it ends".into()));
+            }
+        }
+        Ok(promotion)
+    }
+}
+
+impl From<models::Promotion> for pb::Promotion {
+    fn from(promotion: models::Promotion) -> pb::Promotion {
+        let money = |amount: i64| -> Option<pb::Money> {
+            promotion.currency.clone().map(|currency| Money { amount, currency }.into())
+        };
+        let (kind, percent, amount) = match models::PromotionKind::parse(&promotion.kind) {
+            Some(models::PromotionKind::Amount) => (pb::DiscountKind::AmountOff, None, money(promotion.value)),
+            _ => (pb::DiscountKind::PercentOff, Some(promotion.value as i32), None),
+        };
+        let scope = match models::PromotionScope::parse(&promotion.scope) {
+            Some(models::PromotionScope::Category) => promotion.category_id.map(pb::promotion::Scope::CategoryId),
+            Some(models::PromotionScope::Products) => Some(pb::promotion::Scope::Products(pb::ProductScope {
+                product_ids: promotion.product_ids.clone(),
+            })),
+            _ => Some(pb::promotion::Scope::Cart(pb::CartScope {
+                min_subtotal: Some(promotion.min_subtotal).filter(|min_subtotal| *min_subtotal > 0).and_then(money),
+            })),
+        };
+
+        return pb::Promotion {
+            id: Some(promotion.id),
+            name: promotion.name,
+            kind: kind.into(),
+            percent,
+            amount,
+            scope,
+            coupon_code: promotion.coupon_code,
+            starts_at: promotion.starts_at.map(unix_seconds),
+            ends_at: promotion.ends_at.map(unix_seconds),
+        }
+    }
+}
+
+impl From<models::Evaluation> for pb::EvaluateCartResponse {
+    fn from(res: models::Evaluation) -> pb::EvaluateCartResponse {
+        let currency = res.currency;
+        let money = |amount: i64| -> pb::Money { Money { amount, currency: currency.clone() }.into() };
+
+        return pb::EvaluateCartResponse {
+            lines: res.lines.into_iter().map(|line| pb::DiscountLine {
+                product_id: line.product_id,
+                quantity: line.quantity,
+                subtotal: money(line.subtotal),
+                discount: money(line.discount),
+                total: money(line.total),
+                promotion_ids: line.promotion_ids,
+            }).collect(),
+            discounts: res.discounts.into_iter().map(|discount| pb::AppliedDiscount {
+                promotion_id: discount.promotion_id,
+                name: discount.name,
+                amount: money(discount.amount),
+            }).collect(),
+            subtotal: money(res.subtotal),
+            discount: money(res.discount),
+            total: money(res.total),
+        }
+    }
+}
+
+pub fn from_unix_seconds(seconds: i64) -> SystemTime {
+    match seconds {
+        seconds if seconds >= 0 => UNIX_EPOCH + Duration::from_secs(seconds as u64),
+        seconds => UNIX_EPOCH - Duration::from_secs(seconds.wrapping_neg() as u64),
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
     }
 }
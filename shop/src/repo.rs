@@ -1,15 +1,26 @@
-use log::info;
+use std::collections::HashMap;
+use std::time::SystemTime;
 
+use log::{info, warn};
+
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::r2d2::ConnectionManager;
 
 use errors::prelude::*;
+use crate::categories as category_paths;
 use crate::models;
 use crate::config;
+use crate::events;
+use crate::notifications;
+use crate::schema;
+use crate::search;
 
 type ConnectionPool = diesel::r2d2::Pool<ConnectionManager<PgConnection>>;
 type Connection = diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
+const EXPORT_CURSOR: &str = "product_export";
+
 #[derive(Clone)]
 pub struct PgRepo {
     pool: ConnectionPool,
@@ -46,27 +57,122 @@ impl PgRepo {
         self.pool.get().map_err(errors::Error::DbConnection)
     }
 
-    pub fn add_product(&self, new_product: models::NewProduct) -> Result<models::Product> {
+    pub fn outbox_relay(&self, cfg: &config::Settings) -> amqp_outbox::Relay {
+        amqp_outbox::Relay::new(
+            self.pool.clone(),
+            &cfg.amqp_address,
+            cfg.outbox_batch_size,
+            std::time::Duration::from_millis(cfg.outbox_poll_interval),
+        )
+        .declare_queues(notifications::QUEUES)
+    }
+
+    pub fn add_product(&self, new_product: models::NewProduct, actor: models::Actor) -> Result<models::Product> {
         use crate::schema::products::dsl::*;
         let connection = self.open_connection()?;
 
-        let product: models::Product = diesel::insert_into(products)
-            .values(&new_product)
-            .get_result(&connection)?;
+        connection.transaction::<_, errors::Error, _>(|| {
+            let product: models::Product = diesel::insert_into(products)
+                .values(&new_product)
+                .get_result(&connection)?;
+            record_change(&connection, actor, models::RevisionAction::Created, None, &product)?;
 
-        Ok(product)
+            Ok(product)
+        })
     }
 
-    pub fn add_products(&self, new_products: &[models::NewProduct]) -> Result<()> {
-        use crate::schema::products::dsl::*;
+    // Inserts new codes and updates products whose fields changed, the
+    // last product wins if a code repeats
+    pub fn upsert_products(&self, new_products: &[models::NewProduct], actor: models::Actor) -> Result<models::ImportStats> {
+        use diesel::sql_types::{Array, BigInt, Int4, Nullable, Text};
         let connection = self.open_connection()?;
 
-        diesel::insert_into(products)
-            .values(new_products)
-            .on_conflict_do_nothing()
-            .execute(&connection)?;
+        let mut by_code: HashMap<&str, &models::NewProduct> = HashMap::new();
+        for product in new_products {
+            by_code.insert(&product.code, product);
+        }
+        let unique: Vec<&models::NewProduct> = by_code.into_iter().map(|(_, product)| product).collect();
 
-        Ok(())
+        // All parts of the statement see the products as they were before
+        // it, so previous holds the old fields of updated products. xmax is
+        // zero only for freshly inserted rows.
+        let sql = format!(
+            "WITH input (code, name, category, price, currency, category_id, description) AS ( \
+                 SELECT * FROM unnest($1::text[], $2::text[], $3::text[], $4::bigint[], $5::text[], $6::int[], $7::text[]) \
+             ), \
+             previous AS ( \
+                 SELECT p.* FROM products p JOIN input i ON i.code = p.code \
+             ), \
+             upserted AS ( \
+                 INSERT INTO products (code, name, category, price, currency, category_id, description) \
+                 SELECT * FROM input \
+                 ON CONFLICT (code) DO UPDATE \
+                 SET name = excluded.name, \
+                     category = excluded.category, \
+                     price = excluded.price, \
+                     currency = excluded.currency, \
+                     category_id = excluded.category_id, \
+                     description = excluded.description, \
+                     version = products.version + 1 \
+                 WHERE products.deleted_at IS NULL \
+                   AND (products.name, products.category, products.price, products.currency, products.category_id, products.description) \
+                     IS DISTINCT FROM (excluded.name, excluded.category, excluded.price, excluded.currency, excluded.category_id, excluded.description) \
+                 RETURNING products.*, xmax = 0 AS inserted \
+             ), \
+             revisions AS ( \
+                 INSERT INTO product_revisions (product_id, action, source, user_id, before, after) \
+                 SELECT u.id, \
+                        CASE WHEN u.inserted THEN '{created}' ELSE '{updated}' END, \
+                        $8, $9, \
+                        CASE WHEN u.inserted THEN NULL ELSE {before} END, \
+                        {after} \
+                 FROM upserted u \
+                 LEFT JOIN previous prev ON prev.id = u.id \
+             ) \
+             SELECT * FROM upserted u \
+             ORDER BY u.id",
+            created = models::RevisionAction::Created.as_str(),
+            updated = models::RevisionAction::Updated.as_str(),
+            before = snapshot_json("prev"),
+            after = snapshot_json("u"),
+        );
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let changed: Vec<models::UpsertedProduct> = diesel::sql_query(sql)
+                .bind::<Array<Text>, _>(unique.iter().map(|p| p.code.clone()).collect::<Vec<_>>())
+                .bind::<Array<Text>, _>(unique.iter().map(|p| p.name.clone()).collect::<Vec<_>>())
+                .bind::<Array<Text>, _>(unique.iter().map(|p| p.category.clone()).collect::<Vec<_>>())
+                .bind::<Array<Nullable<BigInt>>, _>(unique.iter().map(|p| p.price).collect::<Vec<_>>())
+                .bind::<Array<Nullable<Text>>, _>(unique.iter().map(|p| p.currency.clone()).collect::<Vec<_>>())
+                .bind::<Array<Nullable<Int4>>, _>(unique.iter().map(|p| p.category_id).collect::<Vec<_>>())
+                .bind::<Array<Text>, _>(unique.iter().map(|p| p.description.clone()).collect::<Vec<_>>())
+                .bind::<Text, _>(actor.source.as_str())
+                .bind::<Nullable<Int4>, _>(actor.user_id)
+                .load(&connection)?;
+
+            // Products in the trash are left alone until they are restored
+            let trashed: Vec<String> = schema::products::table
+                .filter(schema::products::code.eq_any(unique.iter().map(|p| p.code.as_str()).collect::<Vec<_>>()))
+                .filter(schema::products::deleted_at.is_not_null())
+                .select(schema::products::code)
+                .order(schema::products::code)
+                .load(&connection)?;
+
+            let mut stats = models::ImportStats { inserted: 0, updated: 0, unchanged: 0, trashed };
+            for upserted in &changed {
+                let action = if upserted.inserted {
+                    stats.inserted += 1;
+                    models::RevisionAction::Created
+                } else {
+                    stats.updated += 1;
+                    models::RevisionAction::Updated
+                };
+                events::emit(&connection, events::product_changed(action, &upserted.product))?;
+            }
+            stats.unchanged = unique.len() as i64 - stats.inserted - stats.updated - stats.trashed.len() as i64;
+
+            Ok(stats)
+        })
     }
 
     pub fn get_product(&self, product_id: i32) -> Result<Option<models::Product>> {
@@ -75,62 +181,1604 @@ impl PgRepo {
 
         let product = products
             .filter(id.eq(product_id))
+            .filter(deleted_at.is_null())
             .first::<models::Product>(&connection)
             .optional()?;
 
         Ok(product)
     }
 
+    // Products in the trash are skipped
+    pub fn get_products(&self, product_ids: &[i32]) -> Result<Vec<models::Product>> {
+        use crate::schema::products::dsl::*;
+        let connection = self.open_connection()?;
+
+        let found = products
+            .filter(id.eq_any(product_ids))
+            .filter(deleted_at.is_null())
+            .load(&connection)?;
+
+        Ok(found)
+    }
+
     pub fn update_product(
         &self,
         product_id: i32,
-        new_product: models::NewProduct,
-    ) -> Result<models::Product> {
+        expected_version: Option<i32>,
+        changes: models::ProductChanges,
+        actor: models::Actor,
+    ) -> Result<Option<models::Product>> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            apply_product_update(&connection, product_id, expected_version, &changes, actor)
+        })
+    }
+
+    // Every update runs in its own savepoint, so a failed one doesn't
+    // roll back the others
+    pub fn update_products(
+        &self,
+        updates: &[models::ProductUpdate],
+        actor: models::Actor,
+    ) -> Result<Vec<Result<Option<models::Product>>>> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let results = updates
+                .iter()
+                .map(|update| connection.transaction(|| {
+                    apply_product_update(&connection, update.id, update.expected_version, &update.changes, actor)
+                }))
+                .collect();
+            Ok(results)
+        })
+    }
+
+    // Brings the fields back to the state after the revision
+    pub fn revert_product(
+        &self,
+        product_id: i32,
+        revision: i32,
+        expected_version: Option<i32>,
+        actor: models::Actor,
+    ) -> Result<Option<models::Product>> {
+        use crate::schema::product_revisions::dsl as rev;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let current = match lock_product(&connection, product_id, expected_version)? {
+                Some(current) => current,
+                None => return Ok(None),
+            };
+
+            let target: models::ProductRevision = rev::product_revisions
+                .filter(rev::id.eq(revision))
+                .filter(rev::product_id.eq(product_id))
+                .first(&connection)
+                .optional()?
+                .ok_or_else(|| errors::Error::NotFound("Revision not found".into()))?;
+            let snapshot: models::ProductSnapshot = target.after
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(anyhow::Error::from)?
+                .ok_or_else(|| errors::Error::BadRequest("Revision has no snapshot to revert to".into()))?;
+
+            let product = change_locked(&connection, &current, &snapshot.into(), actor, models::RevisionAction::Reverted)?;
+            Ok(Some(product))
+        })
+    }
+
+    pub fn remove_product(&self, product_id: i32, expected_version: Option<i32>, actor: models::Actor) -> Result<usize> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            trash_product(&connection, product_id, expected_version, actor)
+        })
+    }
+
+    // Product ids with expected versions, every removal runs in its own
+    // savepoint
+    pub fn remove_products(&self, removals: &[(i32, Option<i32>)], actor: models::Actor) -> Result<Vec<Result<usize>>> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let results = removals
+                .iter()
+                .map(|(product_id, expected_version)| connection.transaction(|| {
+                    trash_product(&connection, *product_id, *expected_version, actor)
+                }))
+                .collect();
+            Ok(results)
+        })
+    }
+
+    pub fn list_deleted_products(&self, limit: i64, offset: i64) -> Result<(i64, Vec<models::Product>)> {
         use crate::schema::products::dsl::*;
         let connection = self.open_connection()?;
 
-        let product = models::Product {
-            id: product_id,
-            code: new_product.code,
-            name: new_product.name,
-            category: new_product.category,
+        let result = products
+            .filter(deleted_at.is_not_null())
+            .order((deleted_at.desc(), id))
+            .limit(limit)
+            .offset(offset)
+            .load(&connection)?;
+
+        let count = products
+            .filter(deleted_at.is_not_null())
+            .select(diesel::dsl::count_star())
+            .first(&connection)?;
+
+        Ok((count, result))
+    }
+
+    pub fn restore_product(&self, product_id: i32, actor: models::Actor) -> Result<Option<models::Product>> {
+        use crate::schema::products::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let restored: Option<models::Product> = diesel::update(products.filter(id.eq(product_id)).filter(deleted_at.is_not_null()))
+                .set((deleted_at.eq(None::<SystemTime>), version.eq(version + 1)))
+                .get_result(&connection)
+                .optional()?;
+
+            if let Some(product) = &restored {
+                record_change(&connection, actor, models::RevisionAction::Restored, Some(product), product)?;
+            }
+            Ok(restored)
+        })
+    }
+
+    // Newest first
+    pub fn get_product_history(&self, product: i32, limit: i64, offset: i64) -> Result<(i64, Vec<models::ProductRevision>)> {
+        use crate::schema::product_revisions::dsl::*;
+        let connection = self.open_connection()?;
+
+        let result = product_revisions
+            .filter(product_id.eq(product))
+            .order(id.desc())
+            .limit(limit)
+            .offset(offset)
+            .load(&connection)?;
+
+        let count = product_revisions
+            .filter(product_id.eq(product))
+            .select(diesel::dsl::count_star())
+            .first(&connection)?;
+
+        Ok((count, result))
+    }
+
+    // Only products in the trash can be purged
+    pub fn purge_product(&self, product_id: i32, actor: models::Actor) -> Result<usize> {
+        use crate::schema::products::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let purged: Vec<models::Product> = diesel::delete(products.filter(id.eq(product_id)).filter(deleted_at.is_not_null()))
+                .get_results(&connection)?;
+            record_purges(&connection, actor, &purged)
+        })
+    }
+
+    pub fn purge_deleted_products(&self, deleted_before: SystemTime, batch_size: i64, actor: models::Actor) -> Result<usize> {
+        use crate::schema::products::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let expired = products
+                .select(id)
+                .filter(deleted_at.lt(deleted_before))
+                .order(deleted_at)
+                .limit(batch_size);
+            let purged: Vec<models::Product> = diesel::delete(products.filter(id.eq_any(expired)))
+                .get_results(&connection)?;
+            record_purges(&connection, actor, &purged)
+        })
+    }
+
+    pub fn list_products(&self, query: models::ListQuery) -> Result<(Option<i64>, Vec<models::Product>)> {
+        use crate::schema::products::dsl::*;
+        use models::ProductOrder::*;
+        let connection = self.open_connection()?;
+
+        let filtered = filtered_products(&query);
+        let ordered = match query.order {
+            Id => filtered.order(id),
+            IdDesc => filtered.order(id.desc()),
+            PriceAsc => filtered.order((currency.asc().nulls_last(), price.asc().nulls_last(), id)),
+            PriceDesc => filtered.order((currency.asc().nulls_last(), price.desc().nulls_last(), id)),
+            NameAsc => filtered.order((name, id)),
+            NameDesc => filtered.order((name.desc(), id)),
+            CodeAsc => filtered.order((code, id)),
+            CodeDesc => filtered.order((code.desc(), id)),
         };
+        let result = ordered
+            .limit(query.limit.unwrap_or(std::i64::MAX))
+            .offset(query.offset.unwrap_or(0i64))
+            .load(&connection)?;
 
-        let result: models::Product = diesel::insert_into(products)
-            .values(&product)
-            .on_conflict(id)
-            .do_update()
-            .set(&product)
-            .get_result(&connection)?;
+        let count = match query.count {
+            models::CountMode::Skip => None,
+            models::CountMode::Estimated if !query.is_filtered() => {
+                let estimate: models::Count = diesel::sql_query(
+                    "SELECT greatest(reltuples, 0)::bigint AS count FROM pg_class WHERE oid = 'products'::regclass",
+                ).get_result(&connection)?;
+                Some(estimate.count)
+            }
+            _ => {
+                let count = matching_products(&query)
+                    .select(diesel::dsl::count_star())
+                    .first(&connection)?;
+                Some(count)
+            }
+        };
 
-        Ok(result)
+        Ok((count, result))
     }
 
-    pub fn remove_product(&self, product_id: i32) -> Result<usize> {
+    // Reads through a server side cursor in chunks, so the export sees a
+    // consistent snapshot without holding the whole catalog in memory.
+    // The cursor lives in the transaction, the sink must not block for
+    // long. Stops once the sink returns false.
+    pub fn export_products(
+        &self,
+        query: models::ListQuery,
+        chunk_size: i64,
+        mut sink: impl FnMut(models::Product) -> bool,
+    ) -> Result<()> {
         use crate::schema::products::dsl::*;
         let connection = self.open_connection()?;
 
-        let result = diesel::delete(products)
-            .filter(id.eq(product_id))
+        connection.build_transaction().read_only().run::<_, errors::Error, _>(|| {
+            DeclareCursor { name: EXPORT_CURSOR, query: matching_products(&query).order(id) }.execute(&connection)?;
+
+            loop {
+                let chunk: Vec<models::Product> = diesel::sql_query(format!("FETCH {} FROM {}", chunk_size, EXPORT_CURSOR))
+                    .load(&connection)?;
+                let done = (chunk.len() as i64) < chunk_size;
+
+                for product in chunk {
+                    if !sink(product) {
+                        return Ok(());
+                    }
+                }
+                if done {
+                    return Ok(());
+                }
+            }
+        })
+    }
+
+    pub fn search_products(&self, query: models::SearchQuery) -> Result<(i64, Vec<models::SearchHit>)> {
+        use diesel::sql_types::{BigInt, Nullable, Text};
+        let connection = self.open_connection()?;
+
+        let tsquery = match search::tsquery(&query.query) {
+            Some(tsquery) => tsquery,
+            None => return Ok((0, Vec::new())),
+        };
+        let currency = query.min_price.as_ref().or(query.max_price.as_ref()).map(|bound| bound.currency.clone());
+        let descendants = query.category_path.as_ref().map(|root| category_paths::descendants_pattern(root));
+        let min_price = query.min_price.as_ref().map(|bound| bound.amount);
+        let max_price = query.max_price.as_ref().map(|bound| bound.amount);
+
+        let sql = format!(
+            "SELECT p.id, p.name, p.code, p.category, p.price, p.currency, p.category_id, p.description, p.version, p.deleted_at, \
+                    ts_rank(p.search_vector, q.query) AS rank, \
+                    ts_headline('{config}', p.name || ' ' || p.description, q.query, '{headline}') AS snippet, \
+                    count(*) OVER () AS total \
+             {from} \
+             ORDER BY rank DESC, p.id \
+             LIMIT $7 OFFSET $8",
+            config = search::CONFIG,
+            headline = search::HEADLINE_OPTIONS,
+            from = search_matches(),
+        );
+        let hits: Vec<models::SearchHit> = diesel::sql_query(sql)
+            .bind::<Text, _>(&tsquery)
+            .bind::<Nullable<Text>, _>(&query.category_path)
+            .bind::<Nullable<Text>, _>(&descendants)
+            .bind::<Nullable<Text>, _>(&currency)
+            .bind::<Nullable<BigInt>, _>(min_price)
+            .bind::<Nullable<BigInt>, _>(max_price)
+            .bind::<BigInt, _>(query.limit.unwrap_or(std::i64::MAX))
+            .bind::<BigInt, _>(query.offset.unwrap_or(0i64))
+            .load(&connection)?;
+
+        if let Some(hit) = hits.first() {
+            return Ok((hit.total, hits));
+        }
+
+        // The page is past the last hit, so the window count is missing
+        let count: models::Count = diesel::sql_query(format!("SELECT count(*) AS count {}", search_matches()))
+            .bind::<Text, _>(&tsquery)
+            .bind::<Nullable<Text>, _>(&query.category_path)
+            .bind::<Nullable<Text>, _>(&descendants)
+            .bind::<Nullable<Text>, _>(&currency)
+            .bind::<Nullable<BigInt>, _>(min_price)
+            .bind::<Nullable<BigInt>, _>(max_price)
+            .get_result(&connection)?;
+
+        Ok((count.count, hits))
+    }
+
+    pub fn get_stock(&self, product: i32) -> Result<Option<models::Stock>> {
+        use crate::schema::stock::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = stock
+            .filter(product_id.eq(product))
+            .first(&connection)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    // The on-hand quantity may never drop below the reserved one
+    pub fn adjust_stock(&self, adjustment: models::NewStockAdjustment) -> Result<models::Stock> {
+        use crate::schema::stock::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            ensure_stock(&connection, adjustment.product_id)?;
+
+            let updated: Option<models::Stock> = diesel::update(
+                stock
+                    .filter(product_id.eq(adjustment.product_id))
+                    .filter((on_hand + adjustment.delta).ge(reserved)),
+            )
+                .set(on_hand.eq(on_hand + adjustment.delta))
+                .get_result(&connection)
+                .optional()?;
+
+            let updated = match updated {
+                Some(updated) => updated,
+                None => return Err(errors::Error::BadRequest("Stock cannot go below the reserved quantity".into())),
+            };
+
+            diesel::insert_into(schema::stock_adjustments::table)
+                .values(&adjustment)
+                .execute(&connection)?;
+
+            Ok(updated)
+        })
+    }
+
+    // Sets on-hand quantities of all products with the given codes,
+    // levels below the reserved quantity are skipped.
+    pub fn set_stock_levels(&self, levels: &[models::StockLevel]) -> Result<usize> {
+        use crate::schema::stock::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let mut updated = 0;
+            for level in levels {
+                let ids: Vec<i32> = schema::products::table
+                    .filter(schema::products::code.eq(&level.code))
+                    .select(schema::products::id)
+                    .load(&connection)?;
+
+                for product in ids {
+                    ensure_stock(&connection, product)?;
+                    let current: models::Stock = stock
+                        .filter(product_id.eq(product))
+                        .for_update()
+                        .get_result(&connection)?;
+
+                    if level.on_hand < current.reserved {
+                        warn!("Skipping stock level {} of product {}: {} units are reserved", level.on_hand, product, current.reserved);
+                        continue;
+                    }
+                    if level.on_hand == current.on_hand {
+                        continue;
+                    }
+
+                    diesel::update(stock.filter(product_id.eq(product)))
+                        .set(on_hand.eq(level.on_hand))
+                        .execute(&connection)?;
+                    diesel::insert_into(schema::stock_adjustments::table)
+                        .values(&models::NewStockAdjustment {
+                            product_id: product,
+                            delta: level.on_hand - current.on_hand,
+                            reason: models::StockReason::Imported.as_str().into(),
+                            comment: None,
+                            created_by: None,
+                        })
+                        .execute(&connection)?;
+                    updated += 1;
+                }
+            }
+
+            Ok(updated)
+        })
+    }
+
+    pub fn reserve_stock(&self, user: i32, mut items: Vec<models::ReservationItem>, expires: SystemTime) -> Result<models::ReservationDetails> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let reservation = reserve_items(&connection, user, &mut items, expires)?;
+            Ok(models::ReservationDetails { reservation, items })
+        })
+    }
+
+    pub fn get_reservation(&self, reservation: i32) -> Result<Option<models::Reservation>> {
+        use crate::schema::reservations::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = reservations
+            .filter(id.eq(reservation))
+            .first(&connection)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    // Returns false if the reservation is no longer active
+    pub fn release_reservation(&self, reservation: i32) -> Result<bool> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            match lock_active_reservation(&connection, reservation)? {
+                Some(locked) => {
+                    release_locked(&connection, &locked, models::ReservationStatus::Released)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+    }
+
+    // Turns reserved units into sold ones. Returns false if the
+    // reservation is no longer active or has expired.
+    pub fn commit_reservation(&self, reservation: i32, committed_by: i32) -> Result<bool> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            match lock_unexpired_reservation(&connection, reservation)? {
+                Some(locked) => {
+                    commit_locked(&connection, &locked, committed_by)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+    }
+
+    // Releases stock held by expired reservations. Reservations locked by
+    // another transaction are left for the next run.
+    pub fn expire_reservations(&self, batch_size: i64) -> Result<usize> {
+        use crate::schema::reservations::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let expired: Vec<models::Reservation> = reservations
+                .filter(status.eq(models::ReservationStatus::Active.as_str()))
+                .filter(expires_at.le(SystemTime::now()))
+                .order(expires_at)
+                .limit(batch_size)
+                .for_update()
+                .skip_locked()
+                .load(&connection)?;
+
+            for reservation in &expired {
+                release_locked(&connection, reservation, models::ReservationStatus::Expired)?;
+            }
+
+            Ok(expired.len())
+        })
+    }
+
+    pub fn get_cart(&self, user: i32) -> Result<Vec<(models::CartItem, Option<models::Product>)>> {
+        use crate::schema::cart_items::dsl::*;
+        let connection = self.open_connection()?;
+
+        let items = cart_items
+            .left_join(schema::products::table.on(
+                schema::products::id.eq(product_id).and(schema::products::deleted_at.is_null()),
+            ))
+            .filter(user_id.eq(user))
+            .order((added_at, product_id))
+            .load(&connection)?;
+
+        Ok(items)
+    }
+
+    // Adding a product which is already in the cart increases its quantity,
+    // nothing is changed if it would exceed the maximum
+    pub fn add_cart_item(&self, item: models::NewCartItem, max_quantity: i32) -> Result<()> {
+        use crate::schema::cart_items::dsl::*;
+        use diesel::upsert::excluded;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let total: i32 = diesel::insert_into(cart_items)
+                .values(&item)
+                .on_conflict((user_id, product_id))
+                .do_update()
+                .set(quantity.eq(quantity + excluded(quantity)))
+                .returning(quantity)
+                .get_result(&connection)?;
+            if total > max_quantity {
+                return Err(errors::Error::BadRequest(format!("At most {} units of a product fit in the cart", max_quantity)));
+            }
+            Ok(())
+        })
+    }
+
+    pub fn set_cart_item_quantity(&self, user: i32, product: i32, new_quantity: i32) -> Result<usize> {
+        use crate::schema::cart_items::dsl::*;
+        let connection = self.open_connection()?;
+
+        let count = diesel::update(cart_items.filter(user_id.eq(user)).filter(product_id.eq(product)))
+            .set(quantity.eq(new_quantity))
             .execute(&connection)?;
 
-        Ok(result)
+        Ok(count)
     }
 
-    pub fn list_products(&self, query: models::ListQuery) -> Result<(i64, Vec<models::Product>)> {
-        use crate::schema::products::dsl::*;
+    pub fn remove_cart_item(&self, user: i32, product: i32) -> Result<usize> {
+        use crate::schema::cart_items::dsl::*;
         let connection = self.open_connection()?;
 
-        let result = products
-            .order(id)
+        let count = diesel::delete(cart_items.filter(user_id.eq(user)).filter(product_id.eq(product)))
+            .execute(&connection)?;
+
+        Ok(count)
+    }
+
+    pub fn clear_cart(&self, user: i32) -> Result<usize> {
+        use crate::schema::cart_items::dsl::*;
+        let connection = self.open_connection()?;
+
+        let count = diesel::delete(cart_items.filter(user_id.eq(user)))
+            .execute(&connection)?;
+
+        Ok(count)
+    }
+
+    pub fn create_wishlist(&self, wishlist: models::NewWishlist) -> Result<models::Wishlist> {
+        let connection = self.open_connection()?;
+
+        let created = diesel::insert_into(schema::wishlists::table)
+            .values(&wishlist)
+            .get_result(&connection)?;
+
+        Ok(created)
+    }
+
+    // Creates the list if the user has no list with the name yet
+    pub fn ensure_wishlist(&self, wishlist: models::NewWishlist) -> Result<models::Wishlist> {
+        use crate::schema::wishlists::dsl::*;
+        let connection = self.open_connection()?;
+
+        diesel::insert_into(wishlists)
+            .values(&wishlist)
+            .on_conflict((user_id, name))
+            .do_nothing()
+            .execute(&connection)?;
+
+        let found = wishlists
+            .filter(user_id.eq(wishlist.user_id))
+            .filter(name.eq(&wishlist.name))
+            .first(&connection)?;
+
+        Ok(found)
+    }
+
+    pub fn get_wishlist(&self, wishlist: i32) -> Result<Option<models::Wishlist>> {
+        use crate::schema::wishlists::dsl::*;
+        let connection = self.open_connection()?;
+
+        let found = wishlists
+            .filter(id.eq(wishlist))
+            .first(&connection)
+            .optional()?;
+
+        Ok(found)
+    }
+
+    pub fn get_shared_wishlist(&self, token: &str) -> Result<Option<models::Wishlist>> {
+        use crate::schema::wishlists::dsl::*;
+        let connection = self.open_connection()?;
+
+        let found = wishlists
+            .filter(share_token.eq(token))
+            .first(&connection)
+            .optional()?;
+
+        Ok(found)
+    }
+
+    pub fn list_wishlists(&self, user: i32) -> Result<Vec<models::Wishlist>> {
+        use crate::schema::wishlists::dsl::*;
+        let connection = self.open_connection()?;
+
+        let found = wishlists
+            .filter(user_id.eq(user))
+            .order((created_at, id))
+            .load(&connection)?;
+
+        Ok(found)
+    }
+
+    pub fn remove_wishlist(&self, wishlist: i32) -> Result<usize> {
+        use crate::schema::wishlists::dsl::*;
+        let connection = self.open_connection()?;
+
+        let count = diesel::delete(wishlists.filter(id.eq(wishlist)))
+            .execute(&connection)?;
+
+        Ok(count)
+    }
+
+    // None stops sharing the list
+    pub fn set_wishlist_share_token(&self, wishlist: i32, token: Option<&str>) -> Result<Option<models::Wishlist>> {
+        use crate::schema::wishlists::dsl::*;
+        let connection = self.open_connection()?;
+
+        let updated = diesel::update(wishlists.filter(id.eq(wishlist)))
+            .set(share_token.eq(token))
+            .get_result(&connection)
+            .optional()?;
+
+        Ok(updated)
+    }
+
+    // Products in the trash or purged ones come without a product
+    pub fn get_wishlist_items(&self, wishlist: i32) -> Result<Vec<(models::WishlistItem, Option<models::Product>)>> {
+        use crate::schema::wishlist_items::dsl::*;
+        let connection = self.open_connection()?;
+
+        let items = wishlist_items
+            .left_join(schema::products::table.on(
+                schema::products::id.eq(product_id).and(schema::products::deleted_at.is_null()),
+            ))
+            .filter(wishlist_id.eq(wishlist))
+            .order((added_at, product_id))
+            .load(&connection)?;
+
+        Ok(items)
+    }
+
+    // Adding a product which is already on the list does nothing
+    pub fn add_wishlist_item(&self, wishlist: i32, product: i32) -> Result<()> {
+        use crate::schema::wishlist_items::dsl::*;
+        let connection = self.open_connection()?;
+
+        diesel::insert_into(wishlist_items)
+            .values((wishlist_id.eq(wishlist), product_id.eq(product)))
+            .on_conflict_do_nothing()
+            .execute(&connection)?;
+
+        Ok(())
+    }
+
+    pub fn remove_wishlist_item(&self, wishlist: i32, product: i32) -> Result<usize> {
+        use crate::schema::wishlist_items::dsl::*;
+        let connection = self.open_connection()?;
+
+        let count = diesel::delete(wishlist_items.filter(wishlist_id.eq(wishlist)).filter(product_id.eq(product)))
+            .execute(&connection)?;
+
+        Ok(count)
+    }
+
+    // Reserves the stock, stores the order and removes the ordered
+    // products from the cart in one transaction
+    pub fn create_order(&self, mut order: models::NewOrder, mut items: Vec<models::OrderItem>, expires: SystemTime) -> Result<models::OrderDetails> {
+        use crate::schema::orders::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let mut reserved_items: Vec<models::ReservationItem> = items
+                .iter()
+                .map(|item| models::ReservationItem { reservation_id: 0, product_id: item.product_id, quantity: item.quantity })
+                .collect();
+            let reservation = reserve_items(&connection, order.user_id, &mut reserved_items, expires)?;
+
+            order.reservation_id = Some(reservation.id);
+            let created: models::Order = diesel::insert_into(orders)
+                .values(&order)
+                .get_result(&connection)?;
+
+            for item in items.iter_mut() {
+                item.order_id = created.id;
+            }
+            diesel::insert_into(schema::order_items::table)
+                .values(&items)
+                .execute(&connection)?;
+
+            let ordered: Vec<i32> = items.iter().map(|item| item.product_id).collect();
+            diesel::delete(
+                schema::cart_items::table
+                    .filter(schema::cart_items::user_id.eq(order.user_id))
+                    .filter(schema::cart_items::product_id.eq_any(&ordered)),
+            )
+                .execute(&connection)?;
+
+            Ok(models::OrderDetails { order: created, items, reserved_until: Some(reservation.expires_at) })
+        })
+    }
+
+    pub fn get_order(&self, order: i32) -> Result<Option<models::OrderDetails>> {
+        use crate::schema::orders::dsl::*;
+        let connection = self.open_connection()?;
+
+        let found: Option<models::Order> = orders
+            .filter(id.eq(order))
+            .first(&connection)
+            .optional()?;
+
+        match found {
+            Some(found) => Ok(Some(with_order_items(&connection, vec![found])?.remove(0))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list_orders(&self, query: models::OrdersQuery) -> Result<(i64, Vec<models::OrderDetails>)> {
+        let connection = self.open_connection()?;
+
+        let found: Vec<models::Order> = filtered_orders(&query)
+            .order(schema::orders::id.desc())
             .limit(query.limit.unwrap_or(std::i64::MAX))
             .offset(query.offset.unwrap_or(0i64))
             .load(&connection)?;
 
-        let count = products
+        let count = filtered_orders(&query)
+            .select(diesel::dsl::count_star())
+            .first(&connection)?;
+
+        Ok((count, with_order_items(&connection, found)?))
+    }
+
+    // The status is only changed if it is still `from`, so concurrent
+    // updates cannot skip the state machine. Returns None otherwise.
+    pub fn set_order_status(
+        &self,
+        order: i32,
+        from: models::OrderStatus,
+        to: models::OrderStatus,
+        action: models::ReservationAction,
+        changed_by: i32,
+    ) -> Result<Option<models::OrderDetails>> {
+        use crate::schema::orders::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let locked: Option<models::Order> = orders
+                .filter(id.eq(order))
+                .filter(status.eq(from.as_str()))
+                .for_update()
+                .first(&connection)
+                .optional()?;
+
+            let locked = match locked {
+                Some(locked) => locked,
+                None => return Ok(None),
+            };
+
+            match (action, locked.reservation_id) {
+                (models::ReservationAction::Commit, Some(reservation)) => {
+                    match lock_unexpired_reservation(&connection, reservation)? {
+                        Some(reservation) => commit_locked(&connection, &reservation, changed_by)?,
+                        None => {
+                            let renewed = rereserve_order(&connection, &locked, reservation)?;
+                            commit_locked(&connection, &renewed, changed_by)?;
+                        }
+                    }
+                }
+                (models::ReservationAction::Release, Some(reservation)) => {
+                    if let Some(reservation) = lock_active_reservation(&connection, reservation)? {
+                        release_locked(&connection, &reservation, models::ReservationStatus::Released)?;
+                    }
+                }
+                _ => (),
+            }
+
+            let updated: models::Order = diesel::update(orders.filter(id.eq(locked.id)))
+                .set((status.eq(to.as_str()), updated_at.eq(SystemTime::now())))
+                .get_result(&connection)?;
+            notifications::order_status_changed(&connection, &updated)?;
+
+            Ok(Some(with_order_items(&connection, vec![updated])?.remove(0)))
+        })
+    }
+
+    // Creates missing nodes of the category path, returns the id of the
+    // leaf or None for an empty path
+    pub fn ensure_category(&self, raw: &str) -> Result<Option<i32>> {
+        let connection = self.open_connection()?;
+        let segments = category_paths::split(raw);
+
+        connection.transaction::<_, errors::Error, _>(|| upsert_category_path(&connection, &segments))
+    }
+
+    // Same as ensure_category for a batch, keyed by the raw category
+    pub fn ensure_categories(&self, raws: &[String]) -> Result<HashMap<String, i32>> {
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let mut ids = HashMap::new();
+            for raw in raws {
+                if ids.contains_key(raw) {
+                    continue;
+                }
+                if let Some(leaf) = upsert_category_path(&connection, &category_paths::split(raw))? {
+                    ids.insert(raw.clone(), leaf);
+                }
+            }
+            Ok(ids)
+        })
+    }
+
+    pub fn get_category(&self, category: i32) -> Result<Option<models::Category>> {
+        use crate::schema::categories::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = categories
+            .filter(id.eq(category))
+            .first(&connection)
+            .optional()?;
+
+        Ok(res)
+    }
+
+    // Direct children of the parent, root categories if there is no parent
+    pub fn list_categories(&self, parent: Option<i32>) -> Result<Vec<models::Category>> {
+        use crate::schema::categories::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = match parent {
+            Some(parent) => categories.filter(parent_id.eq(parent)).order(name).load(&connection)?,
+            None => categories.filter(parent_id.is_null()).order(name).load(&connection)?,
+        };
+
+        Ok(res)
+    }
+
+    // The category with all of its descendants, or every category
+    pub fn category_subtree(&self, root: Option<&models::Category>) -> Result<Vec<models::Category>> {
+        use crate::schema::categories::dsl::*;
+        let connection = self.open_connection()?;
+
+        let res = match root {
+            Some(root) => categories
+                .filter(path.eq(&root.path).or(path.like(category_paths::descendants_pattern(&root.path))))
+                .order(path)
+                .load(&connection)?,
+            None => categories.order(path).load(&connection)?,
+        };
+
+        Ok(res)
+    }
+
+    // Replaces the previous review of the user, the new one waits for
+    // moderation again
+    pub fn upsert_review(&self, review: models::NewReview) -> Result<models::Review> {
+        use crate::schema::reviews::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let previous: Option<models::Review> = reviews
+                .filter(product_id.eq(review.product_id))
+                .filter(user_id.eq(review.user_id))
+                .for_update()
+                .first(&connection)
+                .optional()?;
+            if let Some(previous) = previous.filter(|previous| previous.is_approved()) {
+                add_rating(&connection, previous.product_id, -previous.rating)?;
+            }
+
+            let saved = diesel::insert_into(reviews)
+                .values(&review)
+                .on_conflict((product_id, user_id))
+                .do_update()
+                .set((
+                    rating.eq(review.rating),
+                    text.eq(&review.text),
+                    status.eq(models::ReviewStatus::Pending.as_str()),
+                    updated_at.eq(diesel::dsl::now),
+                ))
+                .get_result(&connection)?;
+
+            Ok(saved)
+        })
+    }
+
+    pub fn remove_review(&self, product: i32, user: i32) -> Result<Option<models::Review>> {
+        use crate::schema::reviews::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let removed: Option<models::Review> = diesel::delete(reviews.filter(product_id.eq(product)).filter(user_id.eq(user)))
+                .get_result(&connection)
+                .optional()?;
+            if let Some(review) = removed.as_ref().filter(|review| review.is_approved()) {
+                add_rating(&connection, review.product_id, -review.rating)?;
+            }
+
+            Ok(removed)
+        })
+    }
+
+    // Keeps the rating of the product in sync when a review becomes
+    // approved or stops being approved
+    pub fn set_review_status(&self, review: i32, new_status: models::ReviewStatus) -> Result<Option<models::Review>> {
+        use crate::schema::reviews::dsl::*;
+        let connection = self.open_connection()?;
+
+        connection.transaction::<_, errors::Error, _>(|| {
+            let current: models::Review = match reviews.filter(id.eq(review)).for_update().first(&connection).optional()? {
+                Some(current) => current,
+                None => return Ok(None),
+            };
+
+            let approved = new_status == models::ReviewStatus::Approved;
+            if current.is_approved() != approved {
+                let delta = if approved { current.rating } else { -current.rating };
+                add_rating(&connection, current.product_id, delta)?;
+            }
+
+            let updated = diesel::update(reviews.filter(id.eq(review)))
+                .set((status.eq(new_status.as_str()), updated_at.eq(diesel::dsl::now)))
+                .get_result(&connection)?;
+            Ok(Some(updated))
+        })
+    }
+
+    // Newest first
+    pub fn list_reviews(&self, query: models::ReviewsQuery) -> Result<(i64, Vec<models::Review>)> {
+        let connection = self.open_connection()?;
+
+        let found = filtered_reviews(&query)
+            .order(schema::reviews::id.desc())
+            .limit(query.limit.unwrap_or(std::i64::MAX))
+            .offset(query.offset.unwrap_or(0i64))
+            .load(&connection)?;
+
+        let count = filtered_reviews(&query)
+            .select(diesel::dsl::count_star())
+            .first(&connection)?;
+
+        Ok((count, found))
+    }
+
+    // Sets the ratings of products with the given codes to the seeds plus
+    // their approved reviews, so importing the same file again changes
+    // nothing
+    pub fn seed_ratings(&self, seeds: &[models::RatingSeed]) -> Result<usize> {
+        use diesel::sql_types::{Array, Double, Int4, Text};
+        let connection = self.open_connection()?;
+
+        let updated = diesel::sql_query(
+            "WITH seeds (code, average, count) AS ( \
+                 SELECT * FROM unnest($1::text[], $2::float8[], $3::int[]) \
+             ) \
+             UPDATE products p \
+             SET rating_sum = s.average * s.count + coalesce(( \
+                     SELECT sum(r.rating) FROM reviews r WHERE r.product_id = p.id AND r.status = $4 \
+                 ), 0), \
+                 rating_count = s.count + ( \
+                     SELECT count(*) FROM reviews r WHERE r.product_id = p.id AND r.status = $4 \
+                 )::int \
+             FROM seeds s \
+             WHERE p.code = s.code",
+        )
+            .bind::<Array<Text>, _>(seeds.iter().map(|seed| seed.code.clone()).collect::<Vec<_>>())
+            .bind::<Array<Double>, _>(seeds.iter().map(|seed| seed.average).collect::<Vec<_>>())
+            .bind::<Array<Int4>, _>(seeds.iter().map(|seed| seed.count).collect::<Vec<_>>())
+            .bind::<Text, _>(models::ReviewStatus::Approved.as_str())
+            .execute(&connection)?;
+
+        Ok(updated)
+    }
+
+    pub fn add_promotion(&self, promotion: models::NewPromotion) -> Result<models::Promotion> {
+        let connection = self.open_connection()?;
+
+        let created = diesel::insert_into(schema::promotions::table)
+            .values(&promotion)
+            .get_result(&connection)?;
+
+        Ok(created)
+    }
+
+    pub fn update_promotion(&self, promotion: i32, changes: models::NewPromotion) -> Result<Option<models::Promotion>> {
+        use crate::schema::promotions::dsl::*;
+        let connection = self.open_connection()?;
+
+        let updated = diesel::update(promotions.filter(id.eq(promotion)))
+            .set((&changes, updated_at.eq(diesel::dsl::now)))
+            .get_result(&connection)
+            .optional()?;
+
+        Ok(updated)
+    }
+
+    pub fn get_promotion(&self, promotion: i32) -> Result<Option<models::Promotion>> {
+        use crate::schema::promotions::dsl::*;
+        let connection = self.open_connection()?;
+
+        let found = promotions
+            .filter(id.eq(promotion))
+            .first(&connection)
+            .optional()?;
+
+        Ok(found)
+    }
+
+    // Newest first
+    pub fn list_promotions(&self, limit: i64, offset: i64) -> Result<(i64, Vec<models::Promotion>)> {
+        use crate::schema::promotions::dsl::*;
+        let connection = self.open_connection()?;
+
+        let result = promotions
+            .order(id.desc())
+            .limit(limit)
+            .offset(offset)
+            .load(&connection)?;
+
+        let count = promotions
             .select(diesel::dsl::count_star())
             .first(&connection)?;
 
         Ok((count, result))
     }
+
+    pub fn remove_promotion(&self, promotion: i32) -> Result<usize> {
+        use crate::schema::promotions::dsl::*;
+        let connection = self.open_connection()?;
+
+        let count = diesel::delete(promotions.filter(id.eq(promotion)))
+            .execute(&connection)?;
+
+        Ok(count)
+    }
+
+    // Promotions which have not ended yet, without a coupon or with the
+    // given one, along with their categories
+    pub fn applicable_promotions(
+        &self,
+        coupon: Option<&str>,
+        now: SystemTime,
+    ) -> Result<Vec<(models::Promotion, Option<models::Category>)>> {
+        use crate::schema::promotions::dsl::*;
+        let connection = self.open_connection()?;
+
+        let mut query = promotions
+            .left_join(schema::categories::table)
+            .filter(ends_at.is_null().or(ends_at.gt(now)))
+            .into_boxed();
+        query = match coupon {
+            Some(code) => query.filter(coupon_code.is_null().or(coupon_code.eq(code))),
+            None => query.filter(coupon_code.is_null()),
+        };
+        let found = query
+            .order(id)
+            .load(&connection)?;
+
+        Ok(found)
+    }
 }
+
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+// Filters of the query without the page position
+fn matching_products(query: &models::ListQuery) -> schema::products::BoxedQuery<'static, Pg> {
+    use crate::schema::products::dsl::*;
+
+    let mut filtered = products.filter(deleted_at.is_null()).into_boxed();
+    if let Some(root) = &query.category_path {
+        use crate::schema::categories::dsl as cat;
+        let descendants = cat::categories
+            .select(cat::id.nullable())
+            .filter(cat::path.eq(root.clone()).or(cat::path.like(category_paths::descendants_pattern(root))));
+        filtered = filtered.filter(category_id.eq_any(descendants));
+    }
+    if let Some(min_price) = &query.min_price {
+        filtered = filtered
+            .filter(currency.eq(min_price.currency.clone()))
+            .filter(price.ge(min_price.amount));
+    }
+    if let Some(max_price) = &query.max_price {
+        filtered = filtered
+            .filter(currency.eq(max_price.currency.clone()))
+            .filter(price.le(max_price.amount));
+    }
+    if let Some(product_code) = &query.code {
+        filtered = filtered.filter(code.eq(product_code.clone()));
+    }
+    if let Some(prefix) = &query.name_prefix {
+        let pattern = format!("{}%", search::escape_like(&prefix.to_lowercase()));
+        filtered = filtered.filter(lower(name).like(pattern));
+    }
+
+    filtered
+}
+
+// Matching products after the page token, in the order of the token.
+// Amounts in different currencies are not comparable, so price orders
+// group products by currency and products without a price go last.
+fn filtered_products(query: &models::ListQuery) -> schema::products::BoxedQuery<'static, Pg> {
+    use crate::schema::products::dsl::*;
+    use models::ProductOrder::*;
+
+    let filtered = matching_products(query);
+    let after = match &query.after {
+        Some(after) => after,
+        None => return filtered,
+    };
+    let last_text = after.text.clone().unwrap_or_default();
+    let last_currency = after.currency.clone().unwrap_or_default();
+
+    match (after.order, after.price) {
+        (Id, _) => filtered.filter(id.gt(after.id)),
+        (IdDesc, _) => filtered.filter(id.lt(after.id)),
+        (PriceAsc, Some(last)) => filtered.filter(
+            currency.gt(last_currency.clone())
+                .or(currency.eq(last_currency).and(price.gt(last).or(price.eq(last).and(id.gt(after.id)))))
+                .or(price.is_null()),
+        ),
+        (PriceDesc, Some(last)) => filtered.filter(
+            currency.gt(last_currency.clone())
+                .or(currency.eq(last_currency).and(price.lt(last).or(price.eq(last).and(id.gt(after.id)))))
+                .or(price.is_null()),
+        ),
+        (PriceAsc, None) | (PriceDesc, None) => filtered.filter(price.is_null().and(id.gt(after.id))),
+        (NameAsc, _) => filtered.filter(name.gt(last_text.clone()).or(name.eq(last_text).and(id.gt(after.id)))),
+        (NameDesc, _) => filtered.filter(name.lt(last_text.clone()).or(name.eq(last_text).and(id.gt(after.id)))),
+        (CodeAsc, _) => filtered.filter(code.gt(last_text.clone()).or(code.eq(last_text).and(id.gt(after.id)))),
+        (CodeDesc, _) => filtered.filter(code.lt(last_text.clone()).or(code.eq(last_text).and(id.gt(after.id)))),
+    }
+}
+
+// Products matching the search query $1 with optional category ($2, $3),
+// currency ($4) and price range ($5, $6) filters
+fn search_matches() -> String {
+    format!(
+        "FROM products p, to_tsquery('{config}', $1) q(query) \
+         WHERE p.search_vector @@ q.query \
+           AND p.deleted_at IS NULL \
+           AND ($2::text IS NULL OR p.category_id IN ( \
+               SELECT c.id FROM categories c WHERE c.path = $2 OR c.path LIKE $3)) \
+           AND ($4::text IS NULL OR p.currency = $4) \
+           AND ($5::bigint IS NULL OR p.price >= $5) \
+           AND ($6::bigint IS NULL OR p.price <= $6)",
+        config = search::CONFIG,
+    )
+}
+
+fn apply_product_update(
+    connection: &PgConnection,
+    product_id: i32,
+    expected_version: Option<i32>,
+    changes: &models::ProductChanges,
+    actor: models::Actor,
+) -> Result<Option<models::Product>> {
+    let current = match lock_product(connection, product_id, expected_version)? {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+
+    let product = change_locked(connection, &current, changes, actor, models::RevisionAction::Updated)?;
+    Ok(Some(product))
+}
+
+// Moves the product to the trash
+fn trash_product(connection: &PgConnection, product_id: i32, expected_version: Option<i32>, actor: models::Actor) -> Result<usize> {
+    use crate::schema::products::dsl::*;
+
+    let current = match lock_product(connection, product_id, expected_version)? {
+        Some(current) => current,
+        None => return Ok(0),
+    };
+
+    let product: models::Product = diesel::update(products.filter(id.eq(product_id)))
+        .set((deleted_at.eq(Some(SystemTime::now())), version.eq(version + 1)))
+        .get_result(connection)?;
+    record_change(connection, actor, models::RevisionAction::Deleted, Some(&current), &product)?;
+
+    Ok(1)
+}
+
+fn change_locked(
+    connection: &PgConnection,
+    current: &models::Product,
+    changes: &models::ProductChanges,
+    actor: models::Actor,
+    action: models::RevisionAction,
+) -> Result<models::Product> {
+    use crate::schema::products::dsl::*;
+
+    let product = diesel::update(products.filter(id.eq(current.id)))
+        .set((changes, version.eq(version + 1)))
+        .get_result(connection)?;
+    record_change(connection, actor, action, Some(current), &product)?;
+
+    Ok(product)
+}
+
+// Revisions of purged products are kept, the last one only has the state
+// before the purge
+fn record_purges(connection: &PgConnection, actor: models::Actor, purged: &[models::Product]) -> Result<usize> {
+    for product in purged {
+        diesel::insert_into(schema::product_revisions::table)
+            .values(&models::NewProductRevision {
+                product_id: product.id,
+                action: models::RevisionAction::Purged.as_str().into(),
+                source: actor.source.as_str().into(),
+                user_id: actor.user_id,
+                before: Some(snapshot(product)),
+                after: None,
+            })
+            .execute(connection)?;
+        events::emit(connection, events::product_changed(models::RevisionAction::Purged, product))?;
+    }
+
+    Ok(purged.len())
+}
+
+// Stores the revision and the event about the change
+fn record_change(
+    connection: &PgConnection,
+    actor: models::Actor,
+    action: models::RevisionAction,
+    before: Option<&models::Product>,
+    after: &models::Product,
+) -> Result<()> {
+    diesel::insert_into(schema::product_revisions::table)
+        .values(&models::NewProductRevision {
+            product_id: after.id,
+            action: action.as_str().into(),
+            source: actor.source.as_str().into(),
+            user_id: actor.user_id,
+            before: before.map(snapshot),
+            after: Some(snapshot(after)),
+        })
+        .execute(connection)?;
+    events::emit(connection, events::product_changed(action, after))?;
+
+    Ok(())
+}
+
+fn snapshot(product: &models::Product) -> serde_json::Value {
+    serde_json::to_value(models::ProductSnapshot::from(product)).expect("Snapshots are always serializable")
+}
+
+// The same object as models::ProductSnapshot serializes to
+fn snapshot_json(alias: &str) -> String {
+    format!(
+        "jsonb_build_object('name', {0}.name, 'code', {0}.code, 'category', {0}.category, \
+         'price', {0}.price, 'currency', {0}.currency, 'category_id', {0}.category_id, \
+         'description', {0}.description)",
+        alias,
+    )
+}
+
+// Locks a product which is not in the trash, fails with a conflict if it
+// has another version than expected
+fn lock_product(connection: &PgConnection, product: i32, expected_version: Option<i32>) -> Result<Option<models::Product>> {
+    use crate::schema::products::dsl::*;
+
+    let current: Option<models::Product> = products
+        .filter(id.eq(product))
+        .filter(deleted_at.is_null())
+        .for_update()
+        .first(connection)
+        .optional()?;
+
+    match (&current, expected_version) {
+        (Some(current), Some(expected)) if current.version != expected => Err(errors::Error::Conflict(format!(
+            "Product {} was changed concurrently, its version is {}",
+            product, current.version,
+        ))),
+        _ => Ok(current),
+    }
+}
+
+fn ensure_stock(connection: &PgConnection, product: i32) -> Result<()> {
+    diesel::insert_into(schema::stock::table)
+        .values(&models::Stock { product_id: product, on_hand: 0, reserved: 0 })
+        .on_conflict_do_nothing()
+        .execute(connection)?;
+
+    Ok(())
+}
+
+fn load_reservation_items(connection: &PgConnection, reservation: i32) -> Result<Vec<models::ReservationItem>> {
+    use crate::schema::reservation_items::dsl::*;
+
+    let items = reservation_items
+        .filter(reservation_id.eq(reservation))
+        .order(product_id)
+        .load(connection)?;
+
+    Ok(items)
+}
+
+// The reservation row must be locked by the caller
+fn release_locked(connection: &PgConnection, reservation: &models::Reservation, new_status: models::ReservationStatus) -> Result<()> {
+    use crate::schema::reservations::dsl::*;
+    use crate::schema::stock::dsl as st;
+
+    for item in load_reservation_items(connection, reservation.id)? {
+        diesel::update(st::stock.filter(st::product_id.eq(item.product_id)))
+            .set(st::reserved.eq(st::reserved - item.quantity))
+            .execute(connection)?;
+    }
+
+    diesel::update(reservations.filter(id.eq(reservation.id)))
+        .set(status.eq(new_status.as_str()))
+        .execute(connection)?;
+
+    Ok(())
+}
+
+fn filtered_orders(query: &models::OrdersQuery) -> schema::orders::BoxedQuery<'static, Pg> {
+    use crate::schema::orders::dsl::*;
+
+    let mut filtered = orders.into_boxed();
+    if let Some(user) = query.user_id {
+        filtered = filtered.filter(user_id.eq(user));
+    }
+    if let Some(order_status) = query.status {
+        filtered = filtered.filter(status.eq(order_status.as_str()));
+    }
+
+    filtered
+}
+
+fn filtered_reviews(query: &models::ReviewsQuery) -> schema::reviews::BoxedQuery<'static, Pg> {
+    use crate::schema::reviews::dsl::*;
+
+    let mut filtered = reviews.into_boxed();
+    if let Some(product) = query.product_id {
+        filtered = filtered.filter(product_id.eq(product));
+    }
+    if let Some(review_status) = query.status {
+        filtered = filtered.filter(status.eq(review_status.as_str()));
+    }
+
+    filtered
+}
+
+// Adds a rating to the product or takes it back with a negative one
+fn add_rating(connection: &PgConnection, product: i32, rating: i32) -> Result<()> {
+    use crate::schema::products::dsl::*;
+
+    diesel::update(products.filter(id.eq(product)))
+        .set((
+            rating_sum.eq(rating_sum + f64::from(rating)),
+            rating_count.eq(rating_count + rating.signum()),
+        ))
+        .execute(connection)?;
+
+    Ok(())
+}
+
+fn with_order_items(connection: &PgConnection, found: Vec<models::Order>) -> Result<Vec<models::OrderDetails>> {
+    use crate::schema::order_items::dsl::*;
+    use crate::schema::reservations::dsl as res;
+
+    let ids: Vec<i32> = found.iter().map(|order| order.id).collect();
+    let all_items: Vec<models::OrderItem> = order_items
+        .filter(order_id.eq_any(&ids))
+        .order((order_id, product_id))
+        .load(connection)?;
+
+    let mut by_order: HashMap<i32, Vec<models::OrderItem>> = HashMap::new();
+    for item in all_items {
+        by_order.entry(item.order_id).or_insert_with(Vec::new).push(item);
+    }
+
+    let reservation_ids: Vec<i32> = found.iter().filter_map(|order| order.reservation_id).collect();
+    let holds: HashMap<i32, SystemTime> = res::reservations
+        .filter(res::id.eq_any(&reservation_ids))
+        .filter(res::status.eq(models::ReservationStatus::Active.as_str()))
+        .filter(res::expires_at.gt(SystemTime::now()))
+        .select((res::id, res::expires_at))
+        .load::<(i32, SystemTime)>(connection)?
+        .into_iter()
+        .collect();
+
+    Ok(found
+        .into_iter()
+        .map(|order| {
+            let items = by_order.remove(&order.id).unwrap_or_default();
+            let reserved_until = order.reservation_id.and_then(|reservation| holds.get(&reservation).cloned());
+            models::OrderDetails { order, items, reserved_until }
+        })
+        .collect())
+}
+
+// Reserves the items of an order again once its reservation has lapsed,
+// fails if the stock is no longer available. The order row must be
+// locked by the caller.
+fn rereserve_order(connection: &PgConnection, order: &models::Order, lapsed: i32) -> Result<models::Reservation> {
+    use crate::schema::order_items::dsl::*;
+
+    // Expired reservations the sweeper has not got to yet still hold stock
+    if let Some(reservation) = lock_active_reservation(connection, lapsed)? {
+        release_locked(connection, &reservation, models::ReservationStatus::Expired)?;
+    }
+
+    let mut items: Vec<models::ReservationItem> = order_items
+        .filter(order_id.eq(order.id))
+        .load::<models::OrderItem>(connection)?
+        .into_iter()
+        .map(|item| models::ReservationItem { reservation_id: 0, product_id: item.product_id, quantity: item.quantity })
+        .collect();
+    let reservation = match reserve_items(connection, order.user_id, &mut items, SystemTime::now()) {
+        Ok(reservation) => reservation,
+        Err(errors::Error::BadRequest(reason)) | Err(errors::Error::NotFound(reason)) => {
+            return Err(errors::Error::BadRequest(format!("Stock reservation of the order has expired: {}", reason)));
+        }
+        Err(e) => return Err(e),
+    };
+
+    diesel::update(schema::orders::table.filter(schema::orders::id.eq(order.id)))
+        .set(schema::orders::reservation_id.eq(reservation.id))
+        .execute(connection)?;
+
+    Ok(reservation)
+}
+
+// Conditional updates make parallel reservations safe, items are
+// updated in product order to avoid deadlocks.
+fn reserve_items(
+    connection: &PgConnection,
+    user: i32,
+    items: &mut [models::ReservationItem],
+    expires: SystemTime,
+) -> Result<models::Reservation> {
+    use crate::schema::stock::dsl::*;
+
+    items.sort_by_key(|item| item.product_id);
+
+    // Shared locks keep the products from being trashed until commit
+    let product_ids: Vec<i32> = items.iter().map(|item| item.product_id).collect();
+    let available: Vec<i32> = schema::products::table
+        .select(schema::products::id)
+        .filter(schema::products::id.eq_any(&product_ids))
+        .filter(schema::products::deleted_at.is_null())
+        .order(schema::products::id)
+        .for_share()
+        .load(connection)?;
+    if let Some(item) = items.iter().find(|item| available.binary_search(&item.product_id).is_err()) {
+        return Err(errors::Error::NotFound(format!("Product {} not found", item.product_id)));
+    }
+
+    let reservation: models::Reservation = diesel::insert_into(schema::reservations::table)
+        .values(&models::NewReservation {
+            user_id: user,
+            status: models::ReservationStatus::Active.as_str(),
+            expires_at: expires,
+        })
+        .get_result(connection)?;
+
+    for item in items.iter_mut() {
+        let count = diesel::update(
+            stock
+                .filter(product_id.eq(item.product_id))
+                .filter((on_hand - reserved).ge(item.quantity)),
+        )
+            .set(reserved.eq(reserved + item.quantity))
+            .execute(connection)?;
+
+        if count == 0 {
+            return Err(errors::Error::BadRequest(format!("Not enough stock for product {}", item.product_id)));
+        }
+        item.reservation_id = reservation.id;
+    }
+
+    diesel::insert_into(schema::reservation_items::table)
+        .values(&*items)
+        .execute(connection)?;
+
+    Ok(reservation)
+}
+
+fn lock_active_reservation(connection: &PgConnection, reservation: i32) -> Result<Option<models::Reservation>> {
+    use crate::schema::reservations::dsl::*;
+
+    let locked = reservations
+        .filter(id.eq(reservation))
+        .filter(status.eq(models::ReservationStatus::Active.as_str()))
+        .for_update()
+        .first(connection)
+        .optional()?;
+
+    Ok(locked)
+}
+
+fn lock_unexpired_reservation(connection: &PgConnection, reservation: i32) -> Result<Option<models::Reservation>> {
+    use crate::schema::reservations::dsl::*;
+
+    let locked = reservations
+        .filter(id.eq(reservation))
+        .filter(status.eq(models::ReservationStatus::Active.as_str()))
+        .filter(expires_at.gt(SystemTime::now()))
+        .for_update()
+        .first(connection)
+        .optional()?;
+
+    Ok(locked)
+}
+
+// Turns reserved units into sold ones, the reservation row must be
+// locked by the caller
+fn commit_locked(connection: &PgConnection, reservation: &models::Reservation, committed_by: i32) -> Result<()> {
+    use crate::schema::reservations::dsl::*;
+    use crate::schema::stock::dsl as st;
+
+    for item in load_reservation_items(connection, reservation.id)? {
+        diesel::update(st::stock.filter(st::product_id.eq(item.product_id)))
+            .set((st::on_hand.eq(st::on_hand - item.quantity), st::reserved.eq(st::reserved - item.quantity)))
+            .execute(connection)?;
+        diesel::insert_into(schema::stock_adjustments::table)
+            .values(&models::NewStockAdjustment {
+                product_id: item.product_id,
+                delta: -item.quantity,
+                reason: models::StockReason::Sold.as_str().into(),
+                comment: Some(format!("Reservation {}", reservation.id)),
+                created_by: Some(committed_by),
+            })
+            .execute(connection)?;
+    }
+
+    diesel::update(reservations.filter(id.eq(reservation.id)))
+        .set(status.eq(models::ReservationStatus::Committed.as_str()))
+        .execute(connection)?;
+
+    Ok(())
+}
+
+fn upsert_category_path(connection: &PgConnection, segments: &[String]) -> Result<Option<i32>> {
+    use crate::schema::categories::dsl::*;
+
+    let mut parent = None;
+    for depth in 1..=segments.len() {
+        let node_path = category_paths::join(&segments[..depth]);
+        diesel::insert_into(categories)
+            .values(&models::NewCategory {
+                parent_id: parent,
+                name: segments[depth - 1].clone(),
+                path: node_path.clone(),
+            })
+            .on_conflict(path)
+            .do_nothing()
+            .execute(connection)?;
+
+        let node: i32 = categories
+            .filter(path.eq(&node_path))
+            .select(id)
+            .get_result(connection)?;
+        parent = Some(node);
+    }
+
+    Ok(parent)
+}
+
+// DECLARE ... CURSOR FOR a diesel query, binds of the query are kept
+struct DeclareCursor<Q> {
+    name: &'static str,
+    query: Q,
+}
+
+impl<Q> diesel::query_builder::QueryId for DeclareCursor<Q> {
+    type QueryId = ();
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<Q: diesel::query_builder::QueryFragment<Pg>> diesel::query_builder::QueryFragment<Pg> for DeclareCursor<Q> {
+    fn walk_ast(&self, mut out: diesel::query_builder::AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql("DECLARE ");
+        out.push_identifier(self.name)?;
+        out.push_sql(" NO SCROLL CURSOR FOR ");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+impl<Q> RunQueryDsl<PgConnection> for DeclareCursor<Q> {}
@@ -1,8 +1,190 @@
+table! {
+    cart_items (user_id, product_id) {
+        user_id -> Int4,
+        product_id -> Int4,
+        quantity -> Int4,
+        added_at -> Timestamp,
+    }
+}
+
+table! {
+    categories (id) {
+        id -> Int4,
+        parent_id -> Nullable<Int4>,
+        name -> Text,
+        path -> Text,
+    }
+}
+
+table! {
+    order_items (order_id, product_id) {
+        order_id -> Int4,
+        product_id -> Int4,
+        name -> Text,
+        code -> Text,
+        quantity -> Int4,
+        unit_price -> Int8,
+    }
+}
+
+table! {
+    orders (id) {
+        id -> Int4,
+        user_id -> Int4,
+        status -> Text,
+        currency -> Text,
+        total -> Int8,
+        email -> Nullable<Text>,
+        phone -> Nullable<Text>,
+        reservation_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    product_revisions (id) {
+        id -> Int4,
+        product_id -> Int4,
+        action -> Text,
+        source -> Text,
+        user_id -> Nullable<Int4>,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     products (id) {
         id -> Int4,
         name -> Text,
         code -> Text,
         category -> Text,
+        price -> Nullable<Int8>,
+        currency -> Nullable<Text>,
+        category_id -> Nullable<Int4>,
+        description -> Text,
+        version -> Int4,
+        deleted_at -> Nullable<Timestamp>,
+        rating_sum -> Float8,
+        rating_count -> Int4,
+    }
+}
+
+table! {
+    promotions (id) {
+        id -> Int4,
+        name -> Text,
+        kind -> Text,
+        value -> Int8,
+        currency -> Nullable<Text>,
+        scope -> Text,
+        category_id -> Nullable<Int4>,
+        product_ids -> Array<Int4>,
+        min_subtotal -> Int8,
+        coupon_code -> Nullable<Text>,
+        starts_at -> Nullable<Timestamp>,
+        ends_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    reservation_items (reservation_id, product_id) {
+        reservation_id -> Int4,
+        product_id -> Int4,
+        quantity -> Int4,
+    }
+}
+
+table! {
+    reservations (id) {
+        id -> Int4,
+        user_id -> Int4,
+        status -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    reviews (id) {
+        id -> Int4,
+        product_id -> Int4,
+        user_id -> Int4,
+        rating -> Int4,
+        text -> Text,
+        status -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    stock (product_id) {
+        product_id -> Int4,
+        on_hand -> Int4,
+        reserved -> Int4,
+    }
+}
+
+table! {
+    stock_adjustments (id) {
+        id -> Int4,
+        product_id -> Int4,
+        delta -> Int4,
+        reason -> Text,
+        comment -> Nullable<Text>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    wishlist_items (wishlist_id, product_id) {
+        wishlist_id -> Int4,
+        product_id -> Int4,
+        added_at -> Timestamp,
+    }
+}
+
+table! {
+    wishlists (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        share_token -> Nullable<Text>,
+        created_at -> Timestamp,
     }
 }
+
+joinable!(product_revisions -> products (product_id));
+joinable!(products -> categories (category_id));
+joinable!(promotions -> categories (category_id));
+joinable!(order_items -> orders (order_id));
+joinable!(orders -> reservations (reservation_id));
+joinable!(reservation_items -> products (product_id));
+joinable!(reservation_items -> reservations (reservation_id));
+joinable!(reviews -> products (product_id));
+joinable!(stock -> products (product_id));
+joinable!(stock_adjustments -> products (product_id));
+joinable!(wishlist_items -> wishlists (wishlist_id));
+
+allow_tables_to_appear_in_same_query!(
+    cart_items,
+    categories,
+    order_items,
+    orders,
+    product_revisions,
+    products,
+    promotions,
+    reservation_items,
+    reservations,
+    reviews,
+    stock,
+    stock_adjustments,
+    wishlist_items,
+    wishlists,
+);
@@ -0,0 +1,27 @@
+// Text search configuration used by the products.search_vector column
+pub const CONFIG: &str = "english";
+
+pub const HEADLINE_OPTIONS: &str = "StartSel=<b>, StopSel=</b>, MaxWords=20, MinWords=5, MaxFragments=2";
+
+// "red  model-train" -> "red & model & train:*"
+//
+// Only letters and digits reach to_tsquery, so user input can't produce
+// a syntax error. The last word matches as a prefix for search as you type.
+pub fn tsquery(raw: &str) -> Option<String> {
+    let words: Vec<&str> = raw
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    let (last, rest) = words.split_last()?;
+
+    let mut terms: Vec<String> = rest.iter().map(|word| word.to_string()).collect();
+    terms.push(format!("{}:*", last));
+    Some(terms.join(" & "))
+}
+
+// Escapes LIKE wildcards, the default escape character is a backslash
+pub fn escape_like(raw: &str) -> String {
+    raw.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
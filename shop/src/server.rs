@@ -1,22 +1,45 @@
+use std::convert::TryInto;
+
+use tokio::sync::mpsc;
 use tonic::{Request, Response, Status};
 
 use errors::Error;
 use errors::prelude::*;
+use crate::access::Credentials;
+use crate::proto_convert;
 use crate::service;
+use pb::cart_server::Cart;
+use pb::inventory_server::Inventory;
+use pb::orders_server::Orders;
+use pb::promotions_server::Promotions;
+use pb::reviews_server::Reviews;
 use pb::shop_server::Shop;
+use pb::wishlist_server::Wishlist;
 
+#[derive(Clone)]
 pub struct Server {
     shop: service::Service
 }
 
-fn parse_token<T>(request: &Request<T>) -> Result<String> {
+// Requests without a token are anonymous, the access policy decides
+// whether they are allowed
+fn credentials<T>(request: &Request<T>) -> Result<Credentials> {
     let token = match request.metadata().get("authorization") {
-        Some(token) => token.to_str().map_err(|_| Error::Unauthorized("Invalid token value".into()))?,
-        None => return Err(Error::Unauthorized("Token not found".into())),
+        Some(token) => {
+            let token = token.to_str().map_err(|_| Error::Unauthorized("Invalid token value".into()))?;
+            Some(token.trim_start_matches("Bearer ").into())
+        },
+        None => None,
     };
 
-    let token = token.trim_start_matches("Bearer ");
-    Ok(token.into())
+    let forwarded_for = request
+        .metadata()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let peer = request.remote_addr().map(|address| address.ip());
+
+    Ok(Credentials { token, peer, forwarded_for })
 }
 
 impl Server {
@@ -25,16 +48,24 @@ impl Server {
     }
 }
 
+// Products buffered between the export thread and the stream
+const EXPORT_BUFFER_SIZE: usize = 64;
+// The export holds a connection and a snapshot, so clients which stop
+// reading for this long are dropped
+const EXPORT_SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
 #[tonic::async_trait]
 impl Shop for Server {
+    type ExportProductsStream = mpsc::Receiver<std::result::Result<pb::Product, Status>>;
+
     async fn add_product(
         &self,
         request: Request<pb::AddProductRequest>,
     ) -> std::result::Result<Response<pb::AddProductResponse>, Status> {
-        let token = parse_token(&request)?;
+        let credentials = credentials(&request)?;
         let product = self.shop
-            .auth(token).await?
-            .add_product(request.into_inner().product.into())?;
+            .auth("add_product", credentials).await?
+            .add_product(request.into_inner().product.try_into()?)?;
         Ok(Response::new(pb::AddProductResponse{ product: product.into() }))
     }
 
@@ -42,11 +73,10 @@ impl Shop for Server {
         &self,
         request: Request<pb::UpdateProductRequest>,
     ) -> std::result::Result<Response<pb::UpdateProductResponse>, Status> {
-        let token = parse_token(&request)?;
-        let id = request.get_ref().product.id.unwrap_or(0);
+        let credentials = credentials(&request)?;
         let product = self.shop
-            .auth(token).await?
-            .update_product(id, request.into_inner().product.into())?;
+            .auth("update_product", credentials).await?
+            .update_product(request.into_inner().try_into()?)?;
         Ok(Response::new(pb::UpdateProductResponse{ product: product.into() }))
     }
 
@@ -54,10 +84,10 @@ impl Shop for Server {
         &self,
         request: Request<pb::GetProductRequest>,
     ) -> std::result::Result<Response<pb::GetProductResponse>, Status> {
-        let token = parse_token(&request)?;
+        let credentials = credentials(&request)?;
         let id = request.get_ref().id;
         let product = self.shop
-            .auth(token).await?
+            .auth("get_product", credentials).await?
             .get_product(id)?
             .ok_or(errors::Error::NotFound("Product not found".into()))?;
         Ok(Response::new(pb::GetProductResponse{ product: product.into() }))
@@ -67,26 +97,598 @@ impl Shop for Server {
         &self,
         request: Request<pb::DeleteProductRequest>,
     ) -> std::result::Result<Response<pb::DeleteProductResponse>, Status> {
-        let token = parse_token(&request)?;
-        let id = request.get_ref().id;
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
         let cnt = self.shop
-            .auth(token).await?
-            .remove_product(id)?;
+            .auth("delete_product", credentials).await?
+            .remove_product(req.id, req.version)?;
         match cnt {
             0 => Err(errors::Error::NotFound("Product not found".into()).into()),
             _ => Ok(Response::new(pb::DeleteProductResponse::default()))
         }
     }
 
+    async fn batch_get_products(
+        &self,
+        request: Request<pb::BatchGetProductsRequest>,
+    ) -> std::result::Result<Response<pb::BatchProductsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let res = self.shop
+            .auth("batch_get_products", credentials).await?
+            .batch_get_products(request.into_inner().ids)?;
+        Ok(Response::new(pb::BatchProductsResponse{ results: res.into_iter().map(|r| r.into()).collect() }))
+    }
+
+    async fn batch_update_products(
+        &self,
+        request: Request<pb::BatchUpdateProductsRequest>,
+    ) -> std::result::Result<Response<pb::BatchProductsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let items = request
+            .into_inner()
+            .requests
+            .into_iter()
+            .map(|req| (req.product.id.unwrap_or(0), req.try_into()))
+            .collect();
+        let res = self.shop
+            .auth("batch_update_products", credentials).await?
+            .batch_update_products(items)?;
+        Ok(Response::new(pb::BatchProductsResponse{ results: res.into_iter().map(|r| r.into()).collect() }))
+    }
+
+    async fn batch_delete_products(
+        &self,
+        request: Request<pb::BatchDeleteProductsRequest>,
+    ) -> std::result::Result<Response<pb::BatchProductsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let removals = request
+            .into_inner()
+            .requests
+            .into_iter()
+            .map(|req| (req.id, req.version))
+            .collect();
+        let res = self.shop
+            .auth("batch_delete_products", credentials).await?
+            .batch_remove_products(removals)?;
+        Ok(Response::new(pb::BatchProductsResponse{ results: res.into_iter().map(|r| r.into()).collect() }))
+    }
+
+    async fn list_deleted_products(
+        &self,
+        request: Request<pb::ListDeletedProductsRequest>,
+    ) -> std::result::Result<Response<pb::ListDeletedProductsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let (cnt, res) = self.shop
+            .auth("list_deleted_products", credentials).await?
+            .list_deleted_products(req.limit, req.offset)?;
+        Ok(Response::new(pb::ListDeletedProductsResponse{ count: cnt, products: res.into_iter().map(|p| p.into()).collect() }))
+    }
+
+    async fn restore_product(
+        &self,
+        request: Request<pb::RestoreProductRequest>,
+    ) -> std::result::Result<Response<pb::RestoreProductResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let product = self.shop
+            .auth("restore_product", credentials).await?
+            .restore_product(request.get_ref().id)?;
+        Ok(Response::new(pb::RestoreProductResponse{ product: product.into() }))
+    }
+
+    async fn purge_product(
+        &self,
+        request: Request<pb::PurgeProductRequest>,
+    ) -> std::result::Result<Response<pb::PurgeProductResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("purge_product", credentials).await?
+            .purge_product(request.get_ref().id)?;
+        Ok(Response::new(pb::PurgeProductResponse::default()))
+    }
+
+    async fn get_product_history(
+        &self,
+        request: Request<pb::GetProductHistoryRequest>,
+    ) -> std::result::Result<Response<pb::GetProductHistoryResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let (cnt, res) = self.shop
+            .auth("get_product_history", credentials).await?
+            .get_product_history(req.id, req.limit, req.offset)?;
+        Ok(Response::new(pb::GetProductHistoryResponse{ count: cnt, revisions: res.into_iter().map(|r| r.into()).collect() }))
+    }
+
+    async fn revert_product(
+        &self,
+        request: Request<pb::RevertProductRequest>,
+    ) -> std::result::Result<Response<pb::RevertProductResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let product = self.shop
+            .auth("revert_product", credentials).await?
+            .revert_product(req.id, req.revision_id, req.version)?;
+        Ok(Response::new(pb::RevertProductResponse{ product: product.into() }))
+    }
+
     async fn list_products(
         &self,
         request: Request<pb::ListProductsRequest>,
     ) -> std::result::Result<Response<pb::ListProductsResponse>, Status> {
-        let token = parse_token(&request)?;
+        let credentials = credentials(&request)?;
+        let page = self.shop
+            .auth("list_products", credentials).await?
+            .list_products(request.into_inner().try_into()?)?;
+        Ok(Response::new(pb::ListProductsResponse{
+            count: page.count,
+            products: page.products.into_iter().map(|p| p.into()).collect(),
+            next_page_token: page.next_page_token,
+        }))
+    }
+
+    async fn export_products(
+        &self,
+        request: Request<pb::ExportProductsRequest>,
+    ) -> std::result::Result<Response<Self::ExportProductsStream>, Status> {
+        let credentials = credentials(&request)?;
+        let export = self.shop
+            .auth("export_products", credentials).await?
+            .export_products(request.into_inner().try_into()?)?;
+
+        let (mut tx, rx) = mpsc::channel(EXPORT_BUFFER_SIZE);
+        tokio::task::spawn_blocking(move || {
+            // Sending fails once the client is gone, which stops the export
+            let result = export.run(|product| {
+                match futures::executor::block_on(tokio::time::timeout(EXPORT_SEND_TIMEOUT, tx.send(Ok(product.into())))) {
+                    Ok(sent) => sent.is_ok(),
+                    Err(_) => {
+                        log::warn!("Export aborted, the client stopped reading");
+                        false
+                    }
+                }
+            });
+            if let Err(e) = result {
+                log::error!("Export failed: {}", e.to_string());
+                let _ = futures::executor::block_on(tx.send(Err(e.into())));
+            }
+        });
+
+        Ok(Response::new(rx))
+    }
+
+    async fn search_products(
+        &self,
+        request: Request<pb::SearchProductsRequest>,
+    ) -> std::result::Result<Response<pb::SearchProductsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let (cnt, res) = self.shop
+            .auth("search_products", credentials).await?
+            .search_products(request.into_inner().try_into()?)?;
+        Ok(Response::new(pb::SearchProductsResponse{ count: cnt, hits: res.into_iter().map(|h| h.into()).collect() }))
+    }
+
+    async fn list_categories(
+        &self,
+        request: Request<pb::ListCategoriesRequest>,
+    ) -> std::result::Result<Response<pb::ListCategoriesResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let res = self.shop
+            .auth("list_categories", credentials).await?
+            .list_categories(request.get_ref().parent_id)?;
+        Ok(Response::new(pb::ListCategoriesResponse{ categories: res.into_iter().map(|c| c.into()).collect() }))
+    }
+
+    async fn get_category_tree(
+        &self,
+        request: Request<pb::GetCategoryTreeRequest>,
+    ) -> std::result::Result<Response<pb::GetCategoryTreeResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let res = self.shop
+            .auth("get_category_tree", credentials).await?
+            .get_category_tree(request.get_ref().root_id)?;
+        Ok(Response::new(pb::GetCategoryTreeResponse{ roots: res.into_iter().map(|c| c.into()).collect() }))
+    }
+}
+
+#[tonic::async_trait]
+impl Inventory for Server {
+    async fn get_stock(
+        &self,
+        request: Request<pb::GetStockRequest>,
+    ) -> std::result::Result<Response<pb::GetStockResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let stock = self.shop
+            .auth("get_stock", credentials).await?
+            .get_stock(request.get_ref().product_id)?;
+        Ok(Response::new(pb::GetStockResponse{ stock: stock.into() }))
+    }
+
+    async fn adjust_stock(
+        &self,
+        request: Request<pb::AdjustStockRequest>,
+    ) -> std::result::Result<Response<pb::AdjustStockResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let stock = self.shop
+            .auth("adjust_stock", credentials).await?
+            .adjust_stock(request.into_inner().try_into()?)?;
+        Ok(Response::new(pb::AdjustStockResponse{ stock: stock.into() }))
+    }
+
+    async fn reserve_stock(
+        &self,
+        request: Request<pb::ReserveStockRequest>,
+    ) -> std::result::Result<Response<pb::ReserveStockResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let items = request.into_inner().items.into_iter().map(|item| item.into()).collect();
+        let reservation = self.shop
+            .auth("reserve_stock", credentials).await?
+            .reserve_stock(items)?;
+        Ok(Response::new(pb::ReserveStockResponse{ reservation: reservation.into() }))
+    }
+
+    async fn release_stock(
+        &self,
+        request: Request<pb::ReleaseStockRequest>,
+    ) -> std::result::Result<Response<pb::ReleaseStockResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("release_stock", credentials).await?
+            .release_stock(request.get_ref().reservation_id)?;
+        Ok(Response::new(pb::ReleaseStockResponse::default()))
+    }
+
+    async fn commit_reservation(
+        &self,
+        request: Request<pb::CommitReservationRequest>,
+    ) -> std::result::Result<Response<pb::CommitReservationResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("commit_reservation", credentials).await?
+            .commit_reservation(request.get_ref().reservation_id)?;
+        Ok(Response::new(pb::CommitReservationResponse::default()))
+    }
+}
+
+#[tonic::async_trait]
+impl Cart for Server {
+    async fn get_cart(
+        &self,
+        request: Request<pb::GetCartRequest>,
+    ) -> std::result::Result<Response<pb::GetCartResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let cart = self.shop
+            .auth("get_cart", credentials).await?
+            .get_cart()?;
+        Ok(Response::new(pb::GetCartResponse{ cart: cart.into() }))
+    }
+
+    async fn add_item(
+        &self,
+        request: Request<pb::AddItemRequest>,
+    ) -> std::result::Result<Response<pb::AddItemResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let cart = self.shop
+            .auth("add_item", credentials).await?
+            .add_cart_item(req.product_id, req.quantity)?;
+        Ok(Response::new(pb::AddItemResponse{ cart: cart.into() }))
+    }
+
+    async fn update_quantity(
+        &self,
+        request: Request<pb::UpdateQuantityRequest>,
+    ) -> std::result::Result<Response<pb::UpdateQuantityResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let cart = self.shop
+            .auth("update_quantity", credentials).await?
+            .update_cart_item(req.product_id, req.quantity)?;
+        Ok(Response::new(pb::UpdateQuantityResponse{ cart: cart.into() }))
+    }
+
+    async fn remove_item(
+        &self,
+        request: Request<pb::RemoveItemRequest>,
+    ) -> std::result::Result<Response<pb::RemoveItemResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let cart = self.shop
+            .auth("remove_item", credentials).await?
+            .remove_cart_item(request.get_ref().product_id)?;
+        Ok(Response::new(pb::RemoveItemResponse{ cart: cart.into() }))
+    }
+
+    async fn clear_cart(
+        &self,
+        request: Request<pb::ClearCartRequest>,
+    ) -> std::result::Result<Response<pb::ClearCartResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("clear_cart", credentials).await?
+            .clear_cart()?;
+        Ok(Response::new(pb::ClearCartResponse::default()))
+    }
+}
+
+#[tonic::async_trait]
+impl Orders for Server {
+    async fn checkout(
+        &self,
+        request: Request<pb::CheckoutRequest>,
+    ) -> std::result::Result<Response<pb::CheckoutResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let order = self.shop
+            .auth("checkout", credentials).await?
+            .checkout()?;
+        Ok(Response::new(pb::CheckoutResponse{ order: order.into() }))
+    }
+
+    async fn list_my_orders(
+        &self,
+        request: Request<pb::ListMyOrdersRequest>,
+    ) -> std::result::Result<Response<pb::ListMyOrdersResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
         let (cnt, res) = self.shop
-            .auth(token).await?
-            .list_products(request.into_inner().into())?;
-        Ok(Response::new(pb::ListProductsResponse{ count: cnt, products: res.into_iter().map(|p| p.into()).collect() }))
+            .auth("list_my_orders", credentials).await?
+            .list_my_orders(req.offset, req.limit)?;
+        Ok(Response::new(pb::ListMyOrdersResponse{ count: cnt, orders: res.into_iter().map(|o| o.into()).collect() }))
+    }
+
+    async fn get_order(
+        &self,
+        request: Request<pb::GetOrderRequest>,
+    ) -> std::result::Result<Response<pb::GetOrderResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let order = self.shop
+            .auth("get_order", credentials).await?
+            .get_order(request.get_ref().id)?;
+        Ok(Response::new(pb::GetOrderResponse{ order: order.into() }))
+    }
+
+    async fn list_orders(
+        &self,
+        request: Request<pb::ListOrdersRequest>,
+    ) -> std::result::Result<Response<pb::ListOrdersResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let (cnt, res) = self.shop
+            .auth("list_orders", credentials).await?
+            .list_orders(request.into_inner().try_into()?)?;
+        Ok(Response::new(pb::ListOrdersResponse{ count: cnt, orders: res.into_iter().map(|o| o.into()).collect() }))
+    }
+
+    async fn update_order_status(
+        &self,
+        request: Request<pb::UpdateOrderStatusRequest>,
+    ) -> std::result::Result<Response<pb::UpdateOrderStatusResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let order = self.shop
+            .auth("update_order_status", credentials).await?
+            .update_order_status(req.id, proto_convert::parse_order_status(req.status)?)?;
+        Ok(Response::new(pb::UpdateOrderStatusResponse{ order: order.into() }))
+    }
+}
+
+#[tonic::async_trait]
+impl Reviews for Server {
+    async fn add_review(
+        &self,
+        request: Request<pb::AddReviewRequest>,
+    ) -> std::result::Result<Response<pb::AddReviewResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let review = self.shop
+            .auth("add_review", credentials).await?
+            .add_review(req.product_id, req.rating, req.text)?;
+        Ok(Response::new(pb::AddReviewResponse{ review: review.into() }))
+    }
+
+    async fn list_reviews(
+        &self,
+        request: Request<pb::ListReviewsRequest>,
+    ) -> std::result::Result<Response<pb::ListReviewsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let (cnt, res) = self.shop
+            .auth("list_reviews", credentials).await?
+            .list_reviews(request.into_inner().try_into()?)?;
+        Ok(Response::new(pb::ListReviewsResponse{ count: cnt, reviews: res.into_iter().map(|r| r.into()).collect() }))
+    }
+
+    async fn delete_my_review(
+        &self,
+        request: Request<pb::DeleteMyReviewRequest>,
+    ) -> std::result::Result<Response<pb::DeleteMyReviewResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("delete_my_review", credentials).await?
+            .remove_my_review(request.get_ref().product_id)?;
+        Ok(Response::new(pb::DeleteMyReviewResponse::default()))
+    }
+
+    async fn moderate_review(
+        &self,
+        request: Request<pb::ModerateReviewRequest>,
+    ) -> std::result::Result<Response<pb::ModerateReviewResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let review = self.shop
+            .auth("moderate_review", credentials).await?
+            .moderate_review(req.id, proto_convert::parse_review_status(req.status)?)?;
+        Ok(Response::new(pb::ModerateReviewResponse{ review: review.into() }))
+    }
+}
+
+#[tonic::async_trait]
+impl Wishlist for Server {
+    async fn list_wishlists(
+        &self,
+        request: Request<pb::ListWishlistsRequest>,
+    ) -> std::result::Result<Response<pb::ListWishlistsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let wishlists = self.shop
+            .auth("list_wishlists", credentials).await?
+            .list_wishlists()?;
+        Ok(Response::new(pb::ListWishlistsResponse{ wishlists: wishlists.into_iter().map(|w| w.into()).collect() }))
+    }
+
+    async fn create_wishlist(
+        &self,
+        request: Request<pb::CreateWishlistRequest>,
+    ) -> std::result::Result<Response<pb::CreateWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let wishlist = self.shop
+            .auth("create_wishlist", credentials).await?
+            .create_wishlist(request.into_inner().name)?;
+        Ok(Response::new(pb::CreateWishlistResponse{ wishlist: wishlist.into() }))
+    }
+
+    async fn get_wishlist(
+        &self,
+        request: Request<pb::GetWishlistRequest>,
+    ) -> std::result::Result<Response<pb::GetWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let wishlist = self.shop
+            .auth("get_wishlist", credentials).await?
+            .get_wishlist(request.get_ref().id)?;
+        Ok(Response::new(pb::GetWishlistResponse{ wishlist: wishlist.into() }))
+    }
+
+    async fn delete_wishlist(
+        &self,
+        request: Request<pb::DeleteWishlistRequest>,
+    ) -> std::result::Result<Response<pb::DeleteWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("delete_wishlist", credentials).await?
+            .remove_wishlist(request.get_ref().id)?;
+        Ok(Response::new(pb::DeleteWishlistResponse::default()))
+    }
+
+    async fn add_to_wishlist(
+        &self,
+        request: Request<pb::AddToWishlistRequest>,
+    ) -> std::result::Result<Response<pb::AddToWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let wishlist = self.shop
+            .auth("add_to_wishlist", credentials).await?
+            .add_to_wishlist(req.wishlist_id, req.product_id)?;
+        Ok(Response::new(pb::AddToWishlistResponse{ wishlist: wishlist.into() }))
+    }
+
+    async fn remove_from_wishlist(
+        &self,
+        request: Request<pb::RemoveFromWishlistRequest>,
+    ) -> std::result::Result<Response<pb::RemoveFromWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let wishlist = self.shop
+            .auth("remove_from_wishlist", credentials).await?
+            .remove_from_wishlist(req.wishlist_id, req.product_id)?;
+        Ok(Response::new(pb::RemoveFromWishlistResponse{ wishlist: wishlist.into() }))
+    }
+
+    async fn share_wishlist(
+        &self,
+        request: Request<pb::ShareWishlistRequest>,
+    ) -> std::result::Result<Response<pb::ShareWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let wishlist = self.shop
+            .auth("share_wishlist", credentials).await?
+            .share_wishlist(request.get_ref().id)?;
+        Ok(Response::new(pb::ShareWishlistResponse{ wishlist: wishlist.into() }))
+    }
+
+    async fn unshare_wishlist(
+        &self,
+        request: Request<pb::UnshareWishlistRequest>,
+    ) -> std::result::Result<Response<pb::UnshareWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let wishlist = self.shop
+            .auth("unshare_wishlist", credentials).await?
+            .unshare_wishlist(request.get_ref().id)?;
+        Ok(Response::new(pb::UnshareWishlistResponse{ wishlist: wishlist.into() }))
+    }
+
+    async fn get_shared_wishlist(
+        &self,
+        request: Request<pb::GetSharedWishlistRequest>,
+    ) -> std::result::Result<Response<pb::GetSharedWishlistResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let wishlist = self.shop
+            .auth("get_shared_wishlist", credentials).await?
+            .get_shared_wishlist(&request.get_ref().share_token)?;
+        Ok(Response::new(pb::GetSharedWishlistResponse{ wishlist: wishlist.into() }))
     }
 }
 
+#[tonic::async_trait]
+impl Promotions for Server {
+    async fn create_promotion(
+        &self,
+        request: Request<pb::CreatePromotionRequest>,
+    ) -> std::result::Result<Response<pb::CreatePromotionResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let promotion = self.shop
+            .auth("create_promotion", credentials).await?
+            .add_promotion(request.into_inner().promotion.try_into()?)?;
+        Ok(Response::new(pb::CreatePromotionResponse{ promotion: promotion.into() }))
+    }
+
+    async fn update_promotion(
+        &self,
+        request: Request<pb::UpdatePromotionRequest>,
+    ) -> std::result::Result<Response<pb::UpdatePromotionResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let promotion = self.shop
+            .auth("update_promotion", credentials).await?
+            .update_promotion(req.id, req.promotion.try_into()?)?;
+        Ok(Response::new(pb::UpdatePromotionResponse{ promotion: promotion.into() }))
+    }
+
+    async fn get_promotion(
+        &self,
+        request: Request<pb::GetPromotionRequest>,
+    ) -> std::result::Result<Response<pb::GetPromotionResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let promotion = self.shop
+            .auth("get_promotion", credentials).await?
+            .get_promotion(request.get_ref().id)?;
+        Ok(Response::new(pb::GetPromotionResponse{ promotion: promotion.into() }))
+    }
+
+    async fn list_promotions(
+        &self,
+        request: Request<pb::ListPromotionsRequest>,
+    ) -> std::result::Result<Response<pb::ListPromotionsResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let req = request.into_inner();
+        let (cnt, res) = self.shop
+            .auth("list_promotions", credentials).await?
+            .list_promotions(req.limit, req.offset)?;
+        Ok(Response::new(pb::ListPromotionsResponse{ count: cnt, promotions: res.into_iter().map(|p| p.into()).collect() }))
+    }
+
+    async fn delete_promotion(
+        &self,
+        request: Request<pb::DeletePromotionRequest>,
+    ) -> std::result::Result<Response<pb::DeletePromotionResponse>, Status> {
+        let credentials = credentials(&request)?;
+        self.shop
+            .auth("delete_promotion", credentials).await?
+            .remove_promotion(request.get_ref().id)?;
+        Ok(Response::new(pb::DeletePromotionResponse::default()))
+    }
+
+    async fn evaluate_cart(
+        &self,
+        request: Request<pb::EvaluateCartRequest>,
+    ) -> std::result::Result<Response<pb::EvaluateCartResponse>, Status> {
+        let credentials = credentials(&request)?;
+        let evaluation = self.shop
+            .auth("evaluate_cart", credentials).await?
+            .evaluate_cart(request.into_inner().coupon_code)?;
+        Ok(Response::new(evaluation.into()))
+    }
+}
@@ -1,64 +1,897 @@
-use {crate::models, crate::repo, errors::prelude::*};
+use {
+    std::collections::{BTreeMap, HashMap},
+    std::net::IpAddr,
+    std::sync::Arc,
+    std::time::{Duration, SystemTime},
+
+    rand::prelude::*,
+    rand::distributions::Alphanumeric,
+
+    crate::access::{Access, Caller, Credentials, RateLimiter, METHODS},
+    crate::categories,
+    crate::config,
+    crate::models,
+    crate::money::Money,
+    crate::paging::PageToken,
+    crate::promotions,
+    crate::repo,
+    errors::prelude::*,
+};
+
+const EXPORT_CHUNK_SIZE: i64 = 500;
+const SHARE_TOKEN_LENGTH: usize = 32;
 
 #[derive(Clone)]
 pub struct Service {
     repo: repo::PgRepo,
     auth: auth_client::client::Client,
+    reservation_ttl: u32,
+    default_page_size: i64,
+    max_page_size: i64,
+    max_batch_size: usize,
+    max_cart_quantity: i32,
+    access: HashMap<String, Access>,
+    anonymous_limiter: Arc<RateLimiter>,
+    trusted_proxies: Vec<IpAddr>,
 }
 
 impl Service {
-    pub fn new(repo: repo::PgRepo, auth: auth_client::client::Client) -> Self {
-        Service { repo, auth }
+    pub fn new(cfg: &config::Settings, repo: repo::PgRepo, auth: auth_client::client::Client) -> Self {
+        Service {
+            repo,
+            auth,
+            reservation_ttl: cfg.reservation_ttl,
+            default_page_size: cfg.default_page_size,
+            max_page_size: cfg.max_page_size,
+            max_batch_size: cfg.max_batch_size,
+            max_cart_quantity: cfg.max_cart_quantity.into(),
+            access: cfg.access.clone(),
+            anonymous_limiter: Arc::new(RateLimiter::new(cfg.anonymous_rate_limit, cfg.anonymous_burst)),
+            trusted_proxies: cfg.trusted_proxies.clone(),
+        }
     }
 
-    pub async fn auth(&self, token: String) -> Result<ServiceHandler> {
-        let role = match self.auth.validate(token).await? {
-            Some(role) => role,
-            _ => return Err(errors::Error::Unauthorized("Permission denied".into())),
+    // Checks the caller against the access policy of the method, a token
+    // is validated even if the method allows anonymous calls
+    pub async fn auth(&self, method: &str, credentials: Credentials) -> Result<ServiceHandler> {
+        debug_assert!(METHODS.contains(&method), "{} is missing from access::METHODS", method);
+        let caller = match &credentials.token {
+            Some(token) => match self.auth.validate(token.clone()).await? {
+                Some(identity) => Caller::User(identity),
+                None => return Err(errors::Error::Unauthorized("Permission denied".into())),
+            },
+            None => Caller::Anonymous { client: credentials.client(&self.trusted_proxies) },
         };
-        Ok(ServiceHandler { repo: self.repo.clone(), role })
+
+        let access = self.access.get(method).cloned().unwrap_or(Access::User);
+        if let Caller::Anonymous { client } = &caller {
+            if access != Access::Anonymous {
+                return Err(errors::Error::Unauthorized("Token not found".into()));
+            }
+            if !self.anonymous_limiter.acquire(client) {
+                log::warn!("{} {}: rate limit exceeded", caller, method);
+                return Err(errors::Error::RateLimited("Too many requests, sign in or retry later".into()));
+            }
+        }
+        caller.assert_access(access)?;
+        log::info!("{} {}", caller, method);
+
+        Ok(ServiceHandler {
+            repo: self.repo.clone(),
+            caller,
+            reservation_ttl: self.reservation_ttl,
+            default_page_size: self.default_page_size,
+            max_page_size: self.max_page_size,
+            max_batch_size: self.max_batch_size,
+            max_cart_quantity: self.max_cart_quantity,
+        })
+    }
+}
+
+// Checked export, run it on a blocking thread
+pub struct Export {
+    repo: repo::PgRepo,
+    query: models::ListQuery,
+}
+
+impl Export {
+    pub fn run(self, sink: impl FnMut(models::Product) -> bool) -> Result<()> {
+        self.repo.export_products(self.query, EXPORT_CHUNK_SIZE, sink)
     }
 }
 
+// Catalog reads are only guarded by the access policy, the other methods
+// check the caller on their own
 pub struct ServiceHandler {
     repo: repo::PgRepo,
-    role: auth_client::Role,
+    caller: Caller,
+    reservation_ttl: u32,
+    default_page_size: i64,
+    max_page_size: i64,
+    max_batch_size: usize,
+    max_cart_quantity: i32,
 }
 
 impl ServiceHandler {
-    pub fn add_product(&self, new_product: models::NewProduct) -> Result<models::Product> {
+    pub fn add_product(&self, mut new_product: models::NewProduct) -> Result<models::Product> {
         self.assert_role(auth_client::Role::Admin)?;
-        self.repo.add_product(new_product)
+        new_product.category_id = self.repo.ensure_category(&new_product.category)?;
+        self.repo.add_product(new_product, self.actor()?).map_err(code_conflict)
     }
 
     pub fn get_product(&self, product_id: i32) -> Result<Option<models::Product>> {
-        self.assert_role(auth_client::Role::User)?;
         self.repo.get_product(product_id)
     }
 
-    pub fn update_product(
-        &self,
-        product_id: i32,
-        new_product: models::NewProduct,
-    ) -> Result<models::Product> {
+    pub fn update_product(&self, mut update: models::ProductUpdate) -> Result<models::Product> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.resolve_category(&mut update)?;
+        self.repo
+            .update_product(update.id, update.expected_version, update.changes, self.actor()?)
+            .map_err(code_conflict)?
+            .ok_or_else(|| errors::Error::NotFound("Product not found".into()))
+    }
+
+    pub fn remove_product(&self, product_id: i32, expected_version: Option<i32>) -> Result<usize> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo.remove_product(product_id, expected_version, self.actor()?)
+    }
+
+    pub fn get_product_history(&self, product_id: i32, limit: Option<i64>, offset: Option<i64>) -> Result<(i64, Vec<models::ProductRevision>)> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo.get_product_history(product_id, self.page_size(limit)?, offset.unwrap_or(0))
+    }
+
+    pub fn revert_product(&self, product_id: i32, revision: i32, expected_version: Option<i32>) -> Result<models::Product> {
         self.assert_role(auth_client::Role::Admin)?;
-        self.repo.update_product(product_id, new_product)
+        self.repo
+            .revert_product(product_id, revision, expected_version, self.actor()?)
+            .map_err(code_conflict)?
+            .ok_or_else(|| errors::Error::NotFound("Product not found".into()))
     }
 
-    pub fn remove_product(&self, product_id: i32) -> Result<usize> {
+    pub fn batch_get_products(&self, product_ids: Vec<i32>) -> Result<Vec<models::BatchResult>> {
+        self.check_batch_size(product_ids.len())?;
+
+        let mut found: HashMap<i32, models::Product> = self.repo
+            .get_products(&product_ids)?
+            .into_iter()
+            .map(|product| (product.id, product))
+            .collect();
+
+        Ok(product_ids
+            .into_iter()
+            .map(|id| {
+                // Repeated ids get the product once and NotFound after that
+                let result = found
+                    .remove(&id)
+                    .map(Some)
+                    .ok_or_else(|| errors::Error::NotFound("Product not found".into()));
+                models::BatchResult { id, result }
+            })
+            .collect())
+    }
+
+    // Invalid items fail alone, the valid ones are applied in one
+    // transaction
+    pub fn batch_update_products(&self, items: Vec<(i32, Result<models::ProductUpdate>)>) -> Result<Vec<models::BatchResult>> {
         self.assert_role(auth_client::Role::Admin)?;
-        self.repo.remove_product(product_id)
+        self.check_batch_size(items.len())?;
+
+        let mut results = Vec::with_capacity(items.len());
+        let mut updates = Vec::new();
+        for (id, item) in items {
+            let prepared = item.and_then(|mut update| {
+                self.resolve_category(&mut update)?;
+                Ok(update)
+            });
+            match prepared {
+                Ok(update) => {
+                    // Replaced with the outcome once the batch is applied
+                    results.push(models::BatchResult { id, result: Ok(None) });
+                    updates.push(update);
+                }
+                Err(e) => results.push(models::BatchResult { id, result: Err(e) }),
+            }
+        }
+
+        let mut applied = self.repo.update_products(&updates, self.actor()?)?.into_iter();
+        for item in results.iter_mut().filter(|item| item.result.is_ok()) {
+            item.result = match applied.next() {
+                Some(Ok(Some(product))) => Ok(Some(product)),
+                Some(Ok(None)) | None => Err(errors::Error::NotFound("Product not found".into())),
+                Some(Err(e)) => Err(code_conflict(e)),
+            };
+        }
+
+        Ok(results)
     }
 
-    pub fn list_products(&self, query: models::ListQuery) -> Result<(i64, Vec<models::Product>)> {
+    // Product ids with expected versions
+    pub fn batch_remove_products(&self, removals: Vec<(i32, Option<i32>)>) -> Result<Vec<models::BatchResult>> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.check_batch_size(removals.len())?;
+
+        let removed = self.repo.remove_products(&removals, self.actor()?)?;
+        Ok(removals
+            .into_iter()
+            .zip(removed)
+            .map(|((id, _), result)| {
+                let result = match result {
+                    Ok(0) => Err(errors::Error::NotFound("Product not found".into())),
+                    Ok(_) => Ok(None),
+                    Err(e) => Err(e),
+                };
+                models::BatchResult { id, result }
+            })
+            .collect())
+    }
+
+    pub fn list_deleted_products(&self, limit: Option<i64>, offset: Option<i64>) -> Result<(i64, Vec<models::Product>)> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo.list_deleted_products(self.page_size(limit)?, offset.unwrap_or(0))
+    }
+
+    pub fn restore_product(&self, product_id: i32) -> Result<models::Product> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo
+            .restore_product(product_id, self.actor()?)?
+            .ok_or_else(|| errors::Error::NotFound("Product is not in the trash".into()))
+    }
+
+    pub fn purge_product(&self, product_id: i32) -> Result<()> {
+        self.assert_role(auth_client::Role::Admin)?;
+        match self.repo.purge_product(product_id, self.actor()?)? {
+            0 => Err(errors::Error::NotFound("Product is not in the trash".into())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn list_products(&self, mut query: models::ListQuery) -> Result<models::ProductsPage> {
+        check_price_range(&query.min_price, &query.max_price)?;
+        if let Some(after) = &query.after {
+            if query.offset.is_some() {
+                return Err(errors::Error::BadRequest("Offset can't be combined with a page token".into()));
+            }
+            if after.order != query.order {
+                return Err(errors::Error::BadRequest("Page token was issued for another order".into()));
+            }
+        }
+        if let Some(category_id) = query.category_id {
+            query.category_path = Some(self.find_category(category_id)?.path);
+        }
+
+        // One extra product tells whether there is a next page
+        let page_size = self.page_size(query.limit)?;
+        query.limit = Some(page_size + 1);
+        let order = query.order;
+        let (count, mut products) = self.repo.list_products(query)?;
+
+        let mut next_page_token = None;
+        if products.len() as i64 > page_size {
+            products.truncate(page_size as usize);
+            next_page_token = products.last().map(|last| PageToken::after(order, last).encode());
+        }
+
+        Ok(models::ProductsPage { count, products, next_page_token })
+    }
+
+    pub fn export_products(&self, mut query: models::ListQuery) -> Result<Export> {
+        check_price_range(&query.min_price, &query.max_price)?;
+        if let Some(category_id) = query.category_id {
+            query.category_path = Some(self.find_category(category_id)?.path);
+        }
+        Ok(Export { repo: self.repo.clone(), query })
+    }
+
+    pub fn search_products(&self, mut query: models::SearchQuery) -> Result<(i64, Vec<models::SearchHit>)> {
+        if query.query.trim().is_empty() {
+            return Err(errors::Error::BadRequest("Search query must not be empty".into()));
+        }
+        check_price_range(&query.min_price, &query.max_price)?;
+        if let Some(category_id) = query.category_id {
+            query.category_path = Some(self.find_category(category_id)?.path);
+        }
+        query.limit = Some(self.page_size(query.limit)?);
+        self.repo.search_products(query)
+    }
+
+    pub fn get_stock(&self, product_id: i32) -> Result<models::Stock> {
         self.assert_role(auth_client::Role::User)?;
-        self.repo.list_products(query)
+        self.find_product(product_id)?;
+        let stock = self.repo.get_stock(product_id)?;
+        Ok(stock.unwrap_or(models::Stock { product_id, on_hand: 0, reserved: 0 }))
     }
 
-    fn assert_role(&self, minimal_role: auth_client::Role) -> Result<()> {
-        if self.role < minimal_role {
-            return Err(errors::Error::Unauthorized("Permission denied".into()))
+    pub fn adjust_stock(&self, adjustment: models::StockAdjustment) -> Result<models::Stock> {
+        self.assert_role(auth_client::Role::Admin)?;
+        if adjustment.delta == 0 {
+            return Err(errors::Error::BadRequest("Stock delta must not be zero".into()));
+        }
+        self.find_product(adjustment.product_id)?;
+
+        self.repo.adjust_stock(models::NewStockAdjustment {
+            product_id: adjustment.product_id,
+            delta: adjustment.delta,
+            reason: adjustment.reason.as_str().into(),
+            comment: adjustment.comment,
+            created_by: Some(self.identity()?.user_id),
+        })
+    }
+
+    pub fn reserve_stock(&self, items: Vec<models::ReservationItem>) -> Result<models::ReservationDetails> {
+        self.assert_role(auth_client::Role::User)?;
+        if items.is_empty() {
+            return Err(errors::Error::BadRequest("Nothing to reserve".into()));
+        }
+
+        // Duplicate products are merged into one item
+        let mut quantities: BTreeMap<i32, i32> = BTreeMap::new();
+        for item in items {
+            if item.quantity <= 0 {
+                return Err(errors::Error::BadRequest(format!("Invalid quantity for product {}", item.product_id)));
+            }
+            let quantity = quantities.entry(item.product_id).or_insert(0);
+            *quantity = quantity
+                .checked_add(item.quantity)
+                .ok_or_else(|| errors::Error::BadRequest(format!("Invalid quantity for product {}", item.product_id)))?;
+        }
+
+        let items = quantities
+            .into_iter()
+            .map(|(product_id, quantity)| models::ReservationItem { reservation_id: 0, product_id, quantity })
+            .collect();
+        let expires_at = SystemTime::now() + Duration::new(self.reservation_ttl.into(), 0);
+        self.repo.reserve_stock(self.identity()?.user_id, items, expires_at)
+    }
+
+    pub fn release_stock(&self, reservation_id: i32) -> Result<()> {
+        self.find_reservation(reservation_id)?;
+        match self.repo.release_reservation(reservation_id)? {
+            true => Ok(()),
+            false => Err(errors::Error::BadRequest("Reservation is not active".into())),
+        }
+    }
+
+    pub fn commit_reservation(&self, reservation_id: i32) -> Result<()> {
+        self.find_reservation(reservation_id)?;
+        match self.repo.commit_reservation(reservation_id, self.identity()?.user_id)? {
+            true => Ok(()),
+            false => Err(errors::Error::BadRequest("Reservation is not active or has expired".into())),
+        }
+    }
+
+    pub fn get_cart(&self) -> Result<models::CartDetails> {
+        self.assert_role(auth_client::Role::User)?;
+
+        let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+        let mut lines = Vec::new();
+        for (item, product) in self.repo.get_cart(self.identity()?.user_id)? {
+            let subtotal = match product.as_ref().and_then(|product| product.price()) {
+                Some(price) => {
+                    let amount = price.amount
+                        .checked_mul(item.quantity.into())
+                        .ok_or_else(|| errors::Error::BadRequest("Cart total is too large".into()))?;
+                    let total = totals.entry(price.currency.clone()).or_insert(0);
+                    *total = total
+                        .checked_add(amount)
+                        .ok_or_else(|| errors::Error::BadRequest("Cart total is too large".into()))?;
+                    Some(Money { amount, currency: price.currency })
+                }
+                None => None,
+            };
+            lines.push(models::CartLine { item, product, subtotal });
+        }
+
+        Ok(models::CartDetails {
+            lines,
+            totals: totals.into_iter().map(|(currency, amount)| Money { amount, currency }).collect(),
+        })
+    }
+
+    pub fn add_cart_item(&self, product_id: i32, quantity: i32) -> Result<models::CartDetails> {
+        self.assert_role(auth_client::Role::User)?;
+        validate_quantity(quantity, self.max_cart_quantity)?;
+        self.find_product(product_id)?;
+
+        self.repo.add_cart_item(
+            models::NewCartItem {
+                user_id: self.identity()?.user_id,
+                product_id,
+                quantity,
+            },
+            self.max_cart_quantity,
+        )?;
+        self.get_cart()
+    }
+
+    pub fn update_cart_item(&self, product_id: i32, quantity: i32) -> Result<models::CartDetails> {
+        self.assert_role(auth_client::Role::User)?;
+        validate_quantity(quantity, self.max_cart_quantity)?;
+        self.find_product(product_id)?;
+
+        match self.repo.set_cart_item_quantity(self.identity()?.user_id, product_id, quantity)? {
+            0 => Err(errors::Error::NotFound("Product is not in the cart".into())),
+            _ => self.get_cart(),
+        }
+    }
+
+    // Stale items are removed the same way, so there is no product check
+    pub fn remove_cart_item(&self, product_id: i32) -> Result<models::CartDetails> {
+        self.assert_role(auth_client::Role::User)?;
+
+        match self.repo.remove_cart_item(self.identity()?.user_id, product_id)? {
+            0 => Err(errors::Error::NotFound("Product is not in the cart".into())),
+            _ => self.get_cart(),
+        }
+    }
+
+    pub fn clear_cart(&self) -> Result<()> {
+        self.assert_role(auth_client::Role::User)?;
+        self.repo.clear_cart(self.identity()?.user_id)?;
+        Ok(())
+    }
+
+    // Every cart item has to be priced in the same currency
+    pub fn checkout(&self) -> Result<models::OrderDetails> {
+        self.assert_role(auth_client::Role::User)?;
+
+        let cart = self.get_cart()?;
+        if cart.lines.is_empty() {
+            return Err(errors::Error::BadRequest("Cart is empty".into()));
+        }
+        if cart.totals.len() > 1 {
+            return Err(errors::Error::BadRequest("Cart contains products in different currencies".into()));
+        }
+
+        let mut items = Vec::with_capacity(cart.lines.len());
+        for line in cart.lines {
+            let product = match line.product {
+                Some(product) => product,
+                None => return Err(errors::Error::BadRequest(format!("Product {} is no longer available", line.item.product_id))),
+            };
+            let price = product
+                .price()
+                .ok_or_else(|| errors::Error::BadRequest(format!("Product {} has no price", product.id)))?;
+            items.push(models::OrderItem {
+                order_id: 0,
+                product_id: product.id,
+                name: product.name,
+                code: product.code,
+                quantity: line.item.quantity,
+                unit_price: price.amount,
+            });
+        }
+
+        let total = cart.totals.into_iter().next().ok_or_else(|| errors::Error::BadRequest("Cart is empty".into()))?;
+        let order = models::NewOrder {
+            user_id: self.identity()?.user_id,
+            status: models::OrderStatus::Pending.as_str().into(),
+            currency: total.currency,
+            total: total.amount,
+            email: self.identity()?.email.clone(),
+            phone: self.identity()?.phone.clone(),
+            reservation_id: None,
         };
+        let expires_at = SystemTime::now() + Duration::new(self.reservation_ttl.into(), 0);
+        self.repo.create_order(order, items, expires_at)
+    }
+
+    pub fn list_my_orders(&self, offset: Option<i64>, limit: Option<i64>) -> Result<(i64, Vec<models::OrderDetails>)> {
+        self.assert_role(auth_client::Role::User)?;
+        self.repo.list_orders(models::OrdersQuery {
+            user_id: Some(self.identity()?.user_id),
+            status: None,
+            offset,
+            limit,
+        })
+    }
+
+    // Users only see their own orders
+    pub fn get_order(&self, order_id: i32) -> Result<models::OrderDetails> {
+        self.assert_role(auth_client::Role::User)?;
+        match self.repo.get_order(order_id)? {
+            Some(order) if order.order.user_id == self.identity()?.user_id || self.is_admin() => Ok(order),
+            _ => Err(errors::Error::NotFound("Order not found".into())),
+        }
+    }
+
+    pub fn list_orders(&self, query: models::OrdersQuery) -> Result<(i64, Vec<models::OrderDetails>)> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo.list_orders(query)
+    }
+
+    pub fn update_order_status(&self, order_id: i32, status: models::OrderStatus) -> Result<models::OrderDetails> {
+        self.assert_role(auth_client::Role::Admin)?;
+
+        let order = self.repo
+            .get_order(order_id)?
+            .ok_or_else(|| errors::Error::NotFound("Order not found".into()))?;
+        let current = models::OrderStatus::parse(&order.order.status)
+            .ok_or_else(|| errors::Error::Internal(anyhow::anyhow!("Unknown order status {}", order.order.status)))?;
+        let action = order_transition(current, status).ok_or_else(|| {
+            errors::Error::BadRequest(format!("Order cannot go from {} to {}", current.as_str(), status.as_str()))
+        })?;
+
+        self.repo
+            .set_order_status(order_id, current, status, action, self.identity()?.user_id)?
+            .ok_or_else(|| errors::Error::BadRequest("Order status was changed concurrently".into()))
+    }
+
+    pub fn list_categories(&self, parent_id: Option<i32>) -> Result<Vec<models::Category>> {
+        if let Some(parent_id) = parent_id {
+            self.find_category(parent_id)?;
+        }
+        self.repo.list_categories(parent_id)
+    }
+
+    pub fn get_category_tree(&self, root_id: Option<i32>) -> Result<Vec<models::CategoryTree>> {
+        match root_id {
+            Some(root_id) => {
+                let root = self.find_category(root_id)?;
+                let subtree = self.repo.category_subtree(Some(&root))?;
+                Ok(categories::build_tree(subtree, root.parent_id)
+                    .into_iter()
+                    .filter(|tree| tree.category.id == root_id)
+                    .collect())
+            }
+            None => Ok(categories::build_tree(self.repo.category_subtree(None)?, None)),
+        }
+    }
+
+    pub fn add_review(&self, product_id: i32, rating: i32, text: Option<String>) -> Result<models::Review> {
+        self.assert_role(auth_client::Role::User)?;
+        if rating < models::MIN_RATING || rating > models::MAX_RATING {
+            return Err(errors::Error::BadRequest(format!(
+                "Rating must be from {} to {}", models::MIN_RATING, models::MAX_RATING,
+            )));
+        }
+        self.find_product(product_id)?;
+
+        self.repo.upsert_review(models::NewReview {
+            product_id,
+            user_id: self.identity()?.user_id,
+            rating,
+            text: text.unwrap_or_default(),
+        })
+    }
+
+    // Reviews awaiting moderation and hidden ones are for admins only
+    pub fn list_reviews(&self, mut query: models::ReviewsQuery) -> Result<(i64, Vec<models::Review>)> {
+        match query.status {
+            None => query.status = Some(models::ReviewStatus::Approved),
+            Some(models::ReviewStatus::Approved) => (),
+            Some(_) => self.assert_role(auth_client::Role::Admin)?,
+        }
+        query.limit = Some(self.page_size(query.limit)?);
+        self.repo.list_reviews(query)
+    }
+
+    pub fn remove_my_review(&self, product_id: i32) -> Result<()> {
+        self.assert_role(auth_client::Role::User)?;
+        match self.repo.remove_review(product_id, self.identity()?.user_id)? {
+            Some(_) => Ok(()),
+            None => Err(errors::Error::NotFound("Review not found".into())),
+        }
+    }
+
+    pub fn moderate_review(&self, review_id: i32, status: models::ReviewStatus) -> Result<models::Review> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo
+            .set_review_status(review_id, status)?
+            .ok_or_else(|| errors::Error::NotFound("Review not found".into()))
+    }
+
+    // Anyone with the token may read the list, so the owner's token is
+    // not shown
+    pub fn get_shared_wishlist(&self, token: &str) -> Result<models::WishlistDetails> {
+        let mut wishlist = self.repo
+            .get_shared_wishlist(token)?
+            .ok_or_else(|| errors::Error::NotFound("Wishlist not found".into()))?;
+        wishlist.share_token = None;
+        with_wishlist_items(&self.repo, wishlist)
+    }
+
+    pub fn list_wishlists(&self) -> Result<Vec<models::WishlistDetails>> {
+        self.assert_role(auth_client::Role::User)?;
+        let wishlists = self.repo.list_wishlists(self.identity()?.user_id)?;
+        Ok(wishlists.into_iter().map(|wishlist| models::WishlistDetails { wishlist, items: Vec::new() }).collect())
+    }
+
+    pub fn create_wishlist(&self, name: String) -> Result<models::WishlistDetails> {
+        self.assert_role(auth_client::Role::User)?;
+        let wishlist = self.repo
+            .create_wishlist(models::NewWishlist { user_id: self.identity()?.user_id, name: wishlist_name(&name)? })
+            .map_err(|error| match error {
+                errors::Error::DbNonUnique(_) => errors::Error::BadRequest("Wishlist with this name already exists".into()),
+                other => other,
+            })?;
+        Ok(models::WishlistDetails { wishlist, items: Vec::new() })
+    }
+
+    pub fn get_wishlist(&self, wishlist_id: i32) -> Result<models::WishlistDetails> {
+        let wishlist = self.find_wishlist(wishlist_id)?;
+        with_wishlist_items(&self.repo, wishlist)
+    }
+
+    pub fn remove_wishlist(&self, wishlist_id: i32) -> Result<()> {
+        let wishlist = self.find_wishlist(wishlist_id)?;
+        self.repo.remove_wishlist(wishlist.id)?;
         Ok(())
     }
+
+    pub fn add_to_wishlist(&self, wishlist_id: Option<i32>, product_id: i32) -> Result<models::WishlistDetails> {
+        self.assert_role(auth_client::Role::User)?;
+        self.find_product(product_id)?;
+
+        let wishlist = match wishlist_id {
+            Some(wishlist_id) => self.find_wishlist(wishlist_id)?,
+            None => self.repo.ensure_wishlist(models::NewWishlist {
+                user_id: self.identity()?.user_id,
+                name: models::DEFAULT_WISHLIST.into(),
+            })?,
+        };
+        self.repo.add_wishlist_item(wishlist.id, product_id)?;
+        with_wishlist_items(&self.repo, wishlist)
+    }
+
+    // Stale items are removed the same way, so there is no product check
+    pub fn remove_from_wishlist(&self, wishlist_id: i32, product_id: i32) -> Result<models::WishlistDetails> {
+        let wishlist = self.find_wishlist(wishlist_id)?;
+        match self.repo.remove_wishlist_item(wishlist.id, product_id)? {
+            0 => Err(errors::Error::NotFound("Product is not on the wishlist".into())),
+            _ => with_wishlist_items(&self.repo, wishlist),
+        }
+    }
+
+    pub fn share_wishlist(&self, wishlist_id: i32) -> Result<models::WishlistDetails> {
+        let wishlist = self.find_wishlist(wishlist_id)?;
+        if wishlist.share_token.is_some() {
+            return with_wishlist_items(&self.repo, wishlist);
+        }
+        self.set_share_token(wishlist.id, Some(&share_token()))
+    }
+
+    pub fn unshare_wishlist(&self, wishlist_id: i32) -> Result<models::WishlistDetails> {
+        let wishlist = self.find_wishlist(wishlist_id)?;
+        self.set_share_token(wishlist.id, None)
+    }
+
+    fn set_share_token(&self, wishlist_id: i32, token: Option<&str>) -> Result<models::WishlistDetails> {
+        let wishlist = self.repo
+            .set_wishlist_share_token(wishlist_id, token)?
+            .ok_or_else(|| errors::Error::NotFound("Wishlist not found".into()))?;
+        with_wishlist_items(&self.repo, wishlist)
+    }
+
+    // Users only see their own lists
+    fn find_wishlist(&self, wishlist_id: i32) -> Result<models::Wishlist> {
+        self.assert_role(auth_client::Role::User)?;
+        match self.repo.get_wishlist(wishlist_id)? {
+            Some(wishlist) if wishlist.user_id == self.identity()?.user_id => Ok(wishlist),
+            _ => Err(errors::Error::NotFound("Wishlist not found".into())),
+        }
+    }
+
+    pub fn add_promotion(&self, promotion: models::NewPromotion) -> Result<models::Promotion> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.check_promotion_category(&promotion)?;
+        self.repo.add_promotion(promotion).map_err(coupon_conflict)
+    }
+
+    pub fn update_promotion(&self, promotion_id: i32, promotion: models::NewPromotion) -> Result<models::Promotion> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.check_promotion_category(&promotion)?;
+        self.repo
+            .update_promotion(promotion_id, promotion)
+            .map_err(coupon_conflict)?
+            .ok_or_else(|| errors::Error::NotFound("Promotion not found".into()))
+    }
+
+    pub fn get_promotion(&self, promotion_id: i32) -> Result<models::Promotion> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo
+            .get_promotion(promotion_id)?
+            .ok_or_else(|| errors::Error::NotFound("Promotion not found".into()))
+    }
+
+    pub fn list_promotions(&self, limit: Option<i64>, offset: Option<i64>) -> Result<(i64, Vec<models::Promotion>)> {
+        self.assert_role(auth_client::Role::Admin)?;
+        self.repo.list_promotions(self.page_size(limit)?, offset.unwrap_or(0))
+    }
+
+    pub fn remove_promotion(&self, promotion_id: i32) -> Result<()> {
+        self.assert_role(auth_client::Role::Admin)?;
+        match self.repo.remove_promotion(promotion_id)? {
+            0 => Err(errors::Error::NotFound("Promotion not found".into())),
+            _ => Ok(()),
+        }
+    }
+
+    // Every priced cart item has to be in the same currency
+    pub fn evaluate_cart(&self, coupon: Option<String>) -> Result<models::Evaluation> {
+        let cart = self.get_cart()?;
+        if cart.totals.len() > 1 {
+            return Err(errors::Error::BadRequest("Cart contains products in different currencies".into()));
+        }
+        let currency = match cart.totals.first() {
+            Some(total) => total.currency.clone(),
+            None => return Err(errors::Error::BadRequest("Cart is empty".into())),
+        };
+
+        let lines: Vec<promotions::Line> = cart.lines
+            .into_iter()
+            .filter_map(|line| {
+                let product = line.product?;
+                let price = product.price()?;
+                Some(promotions::Line {
+                    product_id: product.id,
+                    category_path: Some(categories::join(&categories::split(&product.category))),
+                    quantity: line.item.quantity,
+                    unit_price: price.amount,
+                })
+            })
+            .collect();
+
+        let now = SystemTime::now();
+        let coupon = coupon.map(|coupon| promotions::normalize_coupon(&coupon)).filter(|coupon| !coupon.is_empty());
+        let rules: Vec<promotions::Rule> = self.repo
+            .applicable_promotions(coupon.as_deref(), now)?
+            .into_iter()
+            .map(|(promotion, category)| promotions::Rule { promotion, category_path: category.map(|category| category.path) })
+            .collect();
+        if let Some(coupon) = &coupon {
+            let known = rules.iter().any(|rule| {
+                rule.promotion.coupon_code.as_ref() == Some(coupon) && promotions::is_active(&rule.promotion, now)
+            });
+            if !known {
+                return Err(errors::Error::BadRequest("Coupon is unknown or has expired".into()));
+            }
+        }
+
+        promotions::evaluate(&rules, &lines, &currency, coupon.as_deref(), now)
+    }
+
+    fn check_promotion_category(&self, promotion: &models::NewPromotion) -> Result<()> {
+        if let Some(category_id) = promotion.category_id {
+            self.find_category(category_id)?;
+        }
+        Ok(())
+    }
+
+    fn find_category(&self, category_id: i32) -> Result<models::Category> {
+        self.repo
+            .get_category(category_id)?
+            .ok_or_else(|| errors::Error::NotFound("Category not found".into()))
+    }
+
+    fn resolve_category(&self, update: &mut models::ProductUpdate) -> Result<()> {
+        if let Some(category) = &update.changes.category {
+            update.changes.category_id = Some(self.repo.ensure_category(category)?);
+        }
+        Ok(())
+    }
+
+    fn check_batch_size(&self, size: usize) -> Result<()> {
+        if size > self.max_batch_size {
+            return Err(errors::Error::BadRequest(format!("At most {} products are allowed in a batch", self.max_batch_size)));
+        }
+        Ok(())
+    }
+
+    fn actor(&self) -> Result<models::Actor> {
+        Ok(models::Actor::user(self.identity()?.user_id))
+    }
+
+    fn page_size(&self, limit: Option<i64>) -> Result<i64> {
+        match limit {
+            None => Ok(self.default_page_size),
+            Some(limit) if limit <= 0 => Err(errors::Error::BadRequest("Limit must be positive".into())),
+            Some(limit) => Ok(limit.min(self.max_page_size)),
+        }
+    }
+
+    fn find_product(&self, product_id: i32) -> Result<models::Product> {
+        self.repo
+            .get_product(product_id)?
+            .ok_or_else(|| errors::Error::NotFound("Product not found".into()))
+    }
+
+    // Users may only touch their own reservations
+    fn find_reservation(&self, reservation_id: i32) -> Result<models::Reservation> {
+        self.assert_role(auth_client::Role::User)?;
+        match self.repo.get_reservation(reservation_id)? {
+            Some(reservation) if reservation.user_id == self.identity()?.user_id || self.is_admin() => Ok(reservation),
+            _ => Err(errors::Error::NotFound("Reservation not found".into())),
+        }
+    }
+
+    fn is_admin(&self) -> bool {
+        self.assert_role(auth_client::Role::Admin).is_ok()
+    }
+
+    fn assert_role(&self, minimal_role: auth_client::Role) -> Result<()> {
+        self.caller.assert_role(minimal_role)
+    }
+
+    fn identity(&self) -> Result<&auth_client::Identity> {
+        match &self.caller {
+            Caller::User(identity) => Ok(identity),
+            Caller::Anonymous { .. } => Err(errors::Error::Unauthorized("Permission denied".into())),
+        }
+    }
+}
+
+fn code_conflict(error: errors::Error) -> errors::Error {
+    match error {
+        errors::Error::DbNonUnique(_) => errors::Error::BadRequest("Product code is already used".into()),
+        other => other,
+    }
+}
+
+fn coupon_conflict(error: errors::Error) -> errors::Error {
+    match error {
+        errors::Error::DbNonUnique(_) => errors::Error::BadRequest("Coupon code is already used".into()),
+        other => other,
+    }
+}
+
+fn with_wishlist_items(repo: &repo::PgRepo, wishlist: models::Wishlist) -> Result<models::WishlistDetails> {
+    let items = repo
+        .get_wishlist_items(wishlist.id)?
+        .into_iter()
+        .map(|(item, product)| models::WishlistLine { item, product })
+        .collect();
+    Ok(models::WishlistDetails { wishlist, items })
+}
+
+fn wishlist_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(errors::Error::BadRequest("Wishlist name must not be empty".into()));
+    }
+    Ok(name.into())
+}
+
+fn share_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SHARE_TOKEN_LENGTH)
+        .collect()
+}
+
+fn check_price_range(min_price: &Option<Money>, max_price: &Option<Money>) -> Result<()> {
+    if let (Some(min_price), Some(max_price)) = (min_price, max_price) {
+        if min_price.currency != max_price.currency {
+            return Err(errors::Error::BadRequest("Price range bounds must have the same currency".into()));
+        }
+    }
+    Ok(())
+}
+
+fn validate_quantity(quantity: i32, max_quantity: i32) -> Result<()> {
+    if quantity <= 0 {
+        return Err(errors::Error::BadRequest("Quantity must be positive".into()));
+    }
+    if quantity > max_quantity {
+        return Err(errors::Error::BadRequest(format!("At most {} units of a product fit in the cart", max_quantity)));
+    }
+    Ok(())
+}
+
+// Returns what happens to the reserved stock if the transition is allowed
+fn order_transition(from: models::OrderStatus, to: models::OrderStatus) -> Option<models::ReservationAction> {
+    use models::OrderStatus::*;
+    use models::ReservationAction;
+
+    match (from, to) {
+        (Pending, Paid) => Some(ReservationAction::Commit),
+        (Pending, Cancelled) => Some(ReservationAction::Release),
+        (Paid, Shipped) => Some(ReservationAction::Keep),
+        (Paid, Refunded) => Some(ReservationAction::Keep),
+        (Shipped, Delivered) => Some(ReservationAction::Keep),
+        (Shipped, Refunded) => Some(ReservationAction::Keep),
+        (Delivered, Refunded) => Some(ReservationAction::Keep),
+        _ => None,
+    }
 }
@@ -0,0 +1,69 @@
+use std::time::{Duration, SystemTime};
+
+use crate::config;
+use crate::models;
+use crate::repo;
+
+const SWEEP_BATCH_SIZE: i64 = 100;
+
+// Releases stock held by expired reservations and purges products which
+// stayed in the trash longer than the retention period
+pub struct Sweeper {
+    repo: repo::PgRepo,
+    interval: Duration,
+    product_retention: Duration,
+}
+
+impl Sweeper {
+    pub fn new(repo: repo::PgRepo, config: &config::Settings) -> Self {
+        Sweeper {
+            repo,
+            interval: Duration::from_secs(config.reservation_sweep_interval),
+            product_retention: Duration::from_secs(config.product_retention),
+        }
+    }
+
+    pub async fn run(&self) {
+        loop {
+            // A full batch means there may be more to sweep right away
+            let expired = self.expire_reservations();
+            let purged = self.purge_products();
+            if expired < SWEEP_BATCH_SIZE && purged < SWEEP_BATCH_SIZE {
+                tokio::time::delay_for(self.interval).await;
+            }
+        }
+    }
+
+    fn expire_reservations(&self) -> i64 {
+        match self.repo.expire_reservations(SWEEP_BATCH_SIZE) {
+            Ok(0) => 0,
+            Ok(count) => {
+                log::info!("Expired {} reservations", count);
+                count as i64
+            }
+            Err(e) => {
+                log::error!("Reservations sweep failed: {}", e.to_string());
+                0
+            }
+        }
+    }
+
+    fn purge_products(&self) -> i64 {
+        let deleted_before = match SystemTime::now().checked_sub(self.product_retention) {
+            Some(deleted_before) => deleted_before,
+            // Retention reaches back before the epoch, nothing is old enough
+            None => return 0,
+        };
+        match self.repo.purge_deleted_products(deleted_before, SWEEP_BATCH_SIZE, models::Actor::sweeper()) {
+            Ok(0) => 0,
+            Ok(count) => {
+                log::info!("Purged {} deleted products", count);
+                count as i64
+            }
+            Err(e) => {
+                log::error!("Deleted products sweep failed: {}", e.to_string());
+                0
+            }
+        }
+    }
+}